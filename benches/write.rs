@@ -0,0 +1,60 @@
+//! Time per write through a `RotatingFile` for a few common setups, i.e. `cargo bench --bench write`. Uses the in-memory
+//! filesystem so what's measured is the library rather than the disk.
+use std::{
+    hint::black_box,
+    io::Write,
+    time::{Duration, Instant},
+};
+use turnstiles::{MemoryFileSystem, PruneCondition, RotatingFile, RotationCondition};
+
+const WRITES: u32 = 1_000_000;
+
+fn bench(name: &str, rotation: RotationCondition, prune: PruneCondition, require_newline: bool) {
+    let mut file = RotatingFile::new_in(
+        MemoryFileSystem::new(),
+        "/logs/bench.log",
+        rotation,
+        prune,
+        require_newline,
+    )
+    .unwrap();
+    let line = b"2024-01-01T00:00:00Z INFO bench: a typical log line of a typical length\n";
+    let start = Instant::now();
+    for _ in 0..WRITES {
+        file.write_all(black_box(line)).unwrap();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<32} {:>8.1} ns/write, {} rotations",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(WRITES),
+        file.index()
+    );
+}
+
+fn main() {
+    bench(
+        "no rotation",
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    );
+    bench(
+        "size, max files",
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(5),
+        false,
+    );
+    bench(
+        "size, require newline",
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        true,
+    );
+    bench(
+        "age",
+        RotationCondition::Duration(Duration::from_secs(3600)),
+        PruneCondition::None,
+        false,
+    );
+}
@@ -0,0 +1,576 @@
+//! Convenience API mirroring `tracing_appender`, so switching over is a one line change. Where there'd be
+//! `tracing_appender::rolling::daily(dir, prefix)` use `turnstiles::appender::daily(dir, prefix)?` instead, and
+//! `turnstiles::appender::non_blocking` in place of `tracing_appender::non_blocking`. Pruning can then be added with
+//! `RotatingFile::set_prune_condition`.
+//!
+//! Unlike `tracing_appender` rotation here is based on the age of the active file rather than wall clock boundaries, and files are
+//! named with an index rather than a date (see the crate docs).
+use crate::{PruneCondition, RotatingFile, RotationCondition};
+use anyhow::{bail, Result};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+fn rolling(
+    dir: impl AsRef<Path>,
+    prefix: &str,
+    rotation: RotationCondition,
+) -> Result<RotatingFile> {
+    let path = dir.as_ref().join(prefix);
+    // tracing writes each event in one go so there's no need for require_newline
+    RotatingFile::new(path, rotation, PruneCondition::None, false)
+}
+
+/// File in `dir` named after `prefix` which rotates when it's a minute old.
+pub fn minutely(dir: impl AsRef<Path>, prefix: &str) -> Result<RotatingFile> {
+    rolling(
+        dir,
+        prefix,
+        RotationCondition::Duration(Duration::from_secs(60)),
+    )
+}
+
+/// File in `dir` named after `prefix` which rotates when it's an hour old.
+pub fn hourly(dir: impl AsRef<Path>, prefix: &str) -> Result<RotatingFile> {
+    rolling(
+        dir,
+        prefix,
+        RotationCondition::Duration(Duration::from_secs(60 * 60)),
+    )
+}
+
+/// File in `dir` named after `prefix` which rotates when it's a day old.
+pub fn daily(dir: impl AsRef<Path>, prefix: &str) -> Result<RotatingFile> {
+    rolling(
+        dir,
+        prefix,
+        RotationCondition::Duration(Duration::from_secs(24 * 60 * 60)),
+    )
+}
+
+/// File in `dir` named after `prefix` which never rotates.
+pub fn never(dir: impl AsRef<Path>, prefix: &str) -> Result<RotatingFile> {
+    rolling(dir, prefix, RotationCondition::None)
+}
+
+/// How many writes `non_blocking` queues up by default before the [`OverflowPolicy`] applies, as for `tracing_appender`.
+pub const DEFAULT_QUEUE_LEN: usize = 128_000;
+
+/// What happens to a write when the `non_blocking` queue is full, see [`NonBlockingBuilder::with_overflow`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the worker to make room, so nothing is lost. The default.
+    Block,
+    /// Drop the write being made.
+    DropNewest,
+    /// Drop the oldest write in the queue to make room for this one.
+    DropOldest,
+    /// Drop the write being made, and have the worker write a line saying how many were dropped once it catches up.
+    Summarize,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    records: VecDeque<Vec<u8>>,
+    /// Dropped since the last summary, only counted with `OverflowPolicy::Summarize`
+    unreported: u64,
+    closed: bool,
+    high_water_mark: usize,
+    blocked: Duration,
+    /// Open while there are records spilled to disk, see `NonBlockingBuilder::with_spill`. Everything written meanwhile goes here
+    /// too, so records stay in order.
+    spill: Option<BufWriter<File>>,
+    /// A write to the spill file failed, so it's closed but still has to be replayed before anything else is queued
+    spill_closed: bool,
+    spilled: u64,
+}
+
+/// What the worker has to write next.
+struct Batch {
+    records: VecDeque<Vec<u8>>,
+    unreported: u64,
+    /// File of spilled records to replay after `records`
+    spill: Option<PathBuf>,
+}
+
+/// How a `non_blocking` queue has coped, from [`NonBlocking::stats`]. With the `metrics` feature the same figures are also
+/// published through the `metrics` crate as `turnstiles_queue_depth`, `turnstiles_queue_high_water_mark`,
+/// `turnstiles_queue_dropped_total` and `turnstiles_queue_blocked_seconds` (a histogram of each wait).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Writes waiting for the worker right now
+    pub depth: usize,
+    /// Most writes there have ever been waiting at once
+    pub high_water_mark: usize,
+    /// Writes dropped because the queue was full, see [`OverflowPolicy`]
+    pub dropped: u64,
+    /// Total time writers have spent waiting for room with [`OverflowPolicy::Block`]
+    pub blocked: Duration,
+    /// Writes spilled to disk rather than queued, see [`NonBlockingBuilder::with_spill`]
+    pub spilled: u64,
+}
+
+#[derive(Debug)]
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+    spill_path: Option<PathBuf>,
+}
+
+/// Where a spill file is moved once the worker starts replaying it, so a new one can be started meanwhile.
+fn replay_path(spill_path: &Path) -> PathBuf {
+    let mut path = spill_path.as_os_str().to_owned();
+    path.push(".replay");
+    PathBuf::from(path)
+}
+
+/// Records are stored in a spill file as a little endian `u64` length followed by the bytes.
+fn write_spilled(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+/// Write out every record in a spill file, then remove it. Each length is checked against what's left of the file before
+/// anything is allocated for it, and a record cut short at the end, i.e. by a crash part way through spilling it, is dropped
+/// rather than stopping the rest being replayed.
+fn replay_spill(path: &Path, writer: &mut impl Write) -> io::Result<()> {
+    let file = File::open(path)?;
+    let mut remaining = file.metadata()?.len();
+    let mut spill = BufReader::new(file);
+    let mut len = [0; 8];
+    while remaining > 0 {
+        let record_len = match remaining.checked_sub(len.len() as u64) {
+            Some(rest) => {
+                spill.read_exact(&mut len)?;
+                remaining = rest;
+                u64::from_le_bytes(len)
+            }
+            None => u64::MAX,
+        };
+        if record_len > remaining {
+            warn(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{} ends part way through a record, dropping it",
+                    path.display()
+                ),
+            ));
+            break;
+        }
+        let mut bytes = vec![0; record_len as usize];
+        spill.read_exact(&mut bytes)?;
+        remaining -= record_len;
+        if let Err(e) = writer.write_all(&bytes) {
+            warn(e);
+        }
+    }
+    fs::remove_file(path)
+}
+
+/// Replay whatever a previous run left behind, before anything new can be spilled: first a file it was part way through
+/// replaying, then its spill file, which is moved to the replay path first like any other.
+fn replay_leftovers(spill_path: &Path, writer: &mut impl Write) -> io::Result<()> {
+    let replay = replay_path(spill_path);
+    if replay.exists() {
+        replay_spill(&replay, writer)?;
+    }
+    if spill_path.exists() {
+        fs::rename(spill_path, &replay)?;
+        replay_spill(&replay, writer)?;
+    }
+    writer.flush()
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut state = self.lock();
+        let mut blocked_since = None;
+        loop {
+            if state.closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "turnstiles worker thread has stopped",
+                ));
+            }
+            if state.spill.is_none() && !state.spill_closed && state.records.len() < self.capacity {
+                break;
+            }
+            match &self.spill_path {
+                Some(spill_path) if !state.spill_closed => {
+                    match Self::spill(&mut state, spill_path, bytes) {
+                        Ok(()) => {
+                            self.not_empty.notify_one();
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            warn(e);
+                            // Keep whatever made it to the file for the worker to replay. Until it has, anything queued would
+                            // come out ahead of it, so this write and those after it are treated as overflowing the queue
+                            if let Some(mut spill) = state.spill.take() {
+                                if let Err(e) = spill.flush() {
+                                    warn(e);
+                                }
+                                state.spill_closed = true;
+                                self.not_empty.notify_one();
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            match self.policy {
+                OverflowPolicy::Block => {
+                    blocked_since.get_or_insert_with(Instant::now);
+                    state = self.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+                OverflowPolicy::DropOldest if !state.spill_closed => {
+                    state.records.pop_front();
+                    self.record_drop();
+                }
+                // Dropping what's queued wouldn't make room while a closed spill file is waiting to be replayed
+                OverflowPolicy::DropNewest
+                | OverflowPolicy::DropOldest
+                | OverflowPolicy::Summarize => {
+                    self.record_drop();
+                    if self.policy == OverflowPolicy::Summarize {
+                        state.unreported += 1;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        if let Some(since) = blocked_since {
+            let blocked = since.elapsed();
+            state.blocked += blocked;
+            #[cfg(feature = "metrics")]
+            metrics::histogram!("turnstiles_queue_blocked_seconds").record(blocked.as_secs_f64());
+        }
+        state.records.push_back(bytes.to_vec());
+        let depth = state.records.len();
+        if depth > state.high_water_mark {
+            state.high_water_mark = depth;
+            #[cfg(feature = "metrics")]
+            metrics::gauge!("turnstiles_queue_high_water_mark").set(depth as f64);
+        }
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("turnstiles_queue_depth").set(depth as f64);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn spill(state: &mut QueueState, spill_path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let spill = match &mut state.spill {
+            Some(spill) => spill,
+            None => state.spill.insert(BufWriter::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(spill_path)?,
+            )),
+        };
+        write_spilled(spill, bytes)?;
+        state.spilled += 1;
+        Ok(())
+    }
+
+    fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("turnstiles_queue_dropped_total").increment(1);
+    }
+
+    fn stats(&self) -> QueueStats {
+        let state = self.lock();
+        QueueStats {
+            depth: state.records.len(),
+            high_water_mark: state.high_water_mark,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            blocked: state.blocked,
+            spilled: state.spilled,
+        }
+    }
+
+    /// Take everything queued along with the count to summarize, waiting if there's nothing. `None` once closed and drained.
+    fn pop_all(&self) -> Option<Batch> {
+        let mut state = self.lock();
+        while state.records.is_empty()
+            && state.unreported == 0
+            && state.spill.is_none()
+            && !state.spill_closed
+        {
+            if state.closed {
+                return None;
+            }
+            state = self
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        let records = std::mem::take(&mut state.records);
+        let unreported = std::mem::take(&mut state.unreported);
+        // Everything spilled came after what's queued, so only take the spill file along with the last of the queue. Anything
+        // written after this is queued in memory again, and comes after the replay.
+        let spill = match &self.spill_path {
+            Some(spill_path) if state.spill.is_some() || state.spill_closed => {
+                state.spill_closed = false;
+                match Self::start_replay(state.spill.take(), spill_path) {
+                    Ok(replay) => Some(replay),
+                    Err(e) => {
+                        warn(e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("turnstiles_queue_depth").set(0.0);
+        self.not_full.notify_all();
+        Some(Batch {
+            records,
+            unreported,
+            spill,
+        })
+    }
+
+    /// Move the spill file to the replay path, flushing it first if it's still open.
+    fn start_replay(spill: Option<BufWriter<File>>, spill_path: &Path) -> io::Result<PathBuf> {
+        if let Some(mut spill) = spill {
+            spill.flush()?;
+        }
+        let replay = replay_path(spill_path);
+        fs::rename(spill_path, &replay)?;
+        Ok(replay)
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
+
+/// Writer which hands each write off to a background thread, see `non_blocking`. Cheap to clone, and with the `tracing` feature
+/// it implements `MakeWriter` so it can be given straight to `tracing_subscriber::fmt().with_writer(...)`.
+#[derive(Debug, Clone)]
+pub struct NonBlocking {
+    queue: Arc<Queue>,
+}
+
+impl NonBlocking {
+    /// Number of writes dropped because the queue was full, see [`OverflowPolicy`].
+    pub fn dropped_count(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Depth, high-water mark, drops and time spent blocked for the queue, to see whether it's keeping up.
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+}
+
+impl Write for NonBlocking {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.queue.push(bytes)?;
+        Ok(bytes.len())
+    }
+    /// Flushing is left to the worker, which flushes whenever it's caught up and when the `WorkerGuard` is dropped.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for NonBlocking {
+    type Writer = NonBlocking;
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Keeps the worker thread behind a `NonBlocking` writer alive. When dropped everything sent so far is written and flushed before
+/// the thread stops, so hold on to it for as long as logging is needed, usually by binding it in `main`. Writes made after it's
+/// dropped return an error.
+#[must_use = "dropping the guard stops the worker thread, so nothing more will be written"]
+#[derive(Debug)]
+pub struct WorkerGuard {
+    queue: Arc<Queue>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WorkerGuard {
+    /// As [`NonBlocking::dropped_count`].
+    pub fn dropped_count(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// As [`NonBlocking::stats`].
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        self.queue.close();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn warn(e: io::Error) {
+    println!(
+        "WARN: turnstiles caught error in non_blocking worker.\nErr: {}",
+        e
+    );
+}
+
+fn worker<W: Write>(mut writer: W, queue: Arc<Queue>) {
+    // Write everything queued up then flush once, rather than after every record
+    while let Some(Batch {
+        records,
+        unreported,
+        spill,
+    }) = queue.pop_all()
+    {
+        if unreported > 0 {
+            let summary = format!(
+                "turnstiles: {} records dropped by non_blocking queue\n",
+                unreported
+            );
+            if let Err(e) = writer.write_all(summary.as_bytes()) {
+                warn(e);
+            }
+        }
+        for bytes in records {
+            if let Err(e) = writer.write_all(&bytes) {
+                warn(e);
+            }
+        }
+        if let Some(spill) = spill {
+            if let Err(e) = replay_spill(&spill, &mut writer) {
+                warn(e);
+            }
+        }
+        if let Err(e) = writer.flush() {
+            warn(e);
+        }
+    }
+}
+
+/// Options for a `non_blocking` writer, i.e. `NonBlockingBuilder::new().with_queue_len(1000)?.with_overflow(OverflowPolicy::DropOldest).finish(file)`.
+#[derive(Debug, Clone)]
+pub struct NonBlockingBuilder {
+    queue_len: usize,
+    overflow: OverflowPolicy,
+    spill_path: Option<PathBuf>,
+}
+
+impl Default for NonBlockingBuilder {
+    fn default() -> Self {
+        Self {
+            queue_len: DEFAULT_QUEUE_LEN,
+            overflow: OverflowPolicy::Block,
+            spill_path: None,
+        }
+    }
+}
+
+impl NonBlockingBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many writes can be queued before the overflow policy applies, [`DEFAULT_QUEUE_LEN`] by default.
+    pub fn with_queue_len(mut self, queue_len: usize) -> Result<Self> {
+        if queue_len == 0 {
+            bail!("Invalid option: queue length of 0");
+        }
+        self.queue_len = queue_len;
+        Ok(self)
+    }
+
+    /// What to do with writes made while the queue is full, [`OverflowPolicy::Block`] by default.
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Once the queue is full, write to this file instead of applying the overflow policy, for bursty workloads where some disk is
+    /// better than losing records or holding up the writer. The worker replays the file once it's caught up with the queue and then
+    /// removes it, so records stay in order. The overflow policy only applies if the file can't be written to, and then until the
+    /// worker has replayed what was spilled before the failure. A spill file left by
+    /// a previous run which stopped before replaying it is replayed by `finish` before it returns, so before anything new can be
+    /// spilled, dropping any record cut short at its end.
+    ///
+    /// The file is created when first needed, along with `<path>.replay` while it's being replayed, so put it on a disk with room
+    /// to spare.
+    pub fn with_spill(mut self, path: impl Into<PathBuf>) -> Self {
+        self.spill_path = Some(path.into());
+        self
+    }
+
+    /// Move `writer` onto the worker thread, as for [`non_blocking`].
+    pub fn finish<W: Write + Send + 'static>(self, mut writer: W) -> (NonBlocking, WorkerGuard) {
+        if let Some(spill_path) = &self.spill_path {
+            if let Err(e) = replay_leftovers(spill_path, &mut writer) {
+                warn(e);
+            }
+        }
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState::default()),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: self.queue_len,
+            policy: self.overflow,
+            dropped: AtomicU64::new(0),
+            spill_path: self.spill_path,
+        });
+        let worker_queue = queue.clone();
+        let handle = thread::Builder::new()
+            .name("turnstiles-worker".to_string())
+            .spawn(move || worker(writer, worker_queue));
+        let handle = match handle {
+            Ok(handle) => Some(handle),
+            Err(e) => {
+                // Writes will then fail with BrokenPipe
+                println!("WARN: turnstiles caught error in non_blocking.\nErr: {}", e);
+                queue.close();
+                None
+            }
+        };
+        (
+            NonBlocking {
+                queue: queue.clone(),
+            },
+            WorkerGuard { queue, handle },
+        )
+    }
+}
+
+/// Move `writer` (normally a `RotatingFile`) onto a background thread, returning a writer which queues writes for it and a guard
+/// which must be kept alive for as long as the writer is in use. Writing, rotation (including the sync before it) and pruning all
+/// happen on the worker, so a write only costs a copy into the queue. The queue holds [`DEFAULT_QUEUE_LEN`] writes, after which
+/// writers wait for the worker to catch up, so nothing is dropped and memory use is bounded. Use a [`NonBlockingBuilder`] to change
+/// either of these.
+pub fn non_blocking<W: Write + Send + 'static>(writer: W) -> (NonBlocking, WorkerGuard) {
+    NonBlockingBuilder::new().finish(writer)
+}
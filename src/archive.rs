@@ -0,0 +1,107 @@
+//! Packing aged rotated files into monthly tarballs, see `RotatingFile::with_archive`.
+use crate::{
+    checksum_sidecar, is_archive, utils::format_rfc3339, with_suffix, FileIndexInt, RotatingFile,
+    ARCHIVE_SUFFIX,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Move the rotated files in `rotated` last modified more than `older_than` ago into `<root>.<YYYY-MM>.tar.gz`, by the month they
+/// were last modified, adding to the month's tarball if there is one already. The newest rotated file always stays where it is so
+/// the index can still be found from the directory. Checksum sidecars (see `ChecksumHook`) go into the tarball with their files.
+/// Returns the names of the files archived and of the tarballs written.
+pub(crate) fn archive_logs(
+    parent: &Path,
+    filename_root: &OsStr,
+    rotated: &[OsString],
+    older_than: Duration,
+) -> io::Result<(Vec<OsString>, Vec<OsString>)> {
+    let Some(cutoff) = SystemTime::now().checked_sub(older_than) else {
+        return Ok((vec![], vec![]));
+    };
+    let mut loose: Vec<(FileIndexInt, &OsString)> = rotated
+        .iter()
+        .filter(|name| !is_archive(name))
+        .filter_map(|name| Some((<RotatingFile>::rotated_file_index(name).ok()?, name)))
+        .collect();
+    loose.sort();
+    loose.pop();
+
+    let mut months: BTreeMap<String, Vec<OsString>> = BTreeMap::new();
+    for (_, name) in loose {
+        let modified = fs::metadata(parent.join(name))?.modified()?;
+        if modified < cutoff {
+            let month = format_rfc3339(modified)[..7].to_string();
+            let names = months.entry(month).or_default();
+            names.push(name.clone());
+            let sidecar = checksum_sidecar(name);
+            if parent.join(&sidecar).exists() {
+                names.push(sidecar);
+            }
+        }
+    }
+
+    let mut archived = vec![];
+    let mut archives = vec![];
+    for (month, names) in months {
+        let archive = with_suffix(filename_root, &format!(".{}{}", month, ARCHIVE_SUFFIX));
+        append_to_archive(&parent.join(&archive), parent, &names)?;
+        for name in names {
+            fs::remove_file(parent.join(&name))?;
+            archived.push(name);
+        }
+        archives.push(archive);
+    }
+    Ok((archived, archives))
+}
+
+/// Write a new tarball with everything in the existing one, if any, followed by `names`, and rename it into place once complete
+/// so a crash part way through leaves the old one alone.
+fn append_to_archive(archive: &Path, parent: &Path, names: &[OsString]) -> io::Result<()> {
+    let mut partial = archive.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let result = write_archive(archive, &partial, parent, names);
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result?;
+    fs::rename(&partial, archive)
+}
+
+fn write_archive(
+    archive: &Path,
+    partial: &Path,
+    parent: &Path,
+    names: &[OsString],
+) -> io::Result<()> {
+    let out = GzEncoder::new(
+        BufWriter::new(File::create(partial)?),
+        Compression::default(),
+    );
+    let mut builder = tar::Builder::new(out);
+    if archive.exists() {
+        let mut existing = tar::Archive::new(GzDecoder::new(File::open(archive)?));
+        for entry in existing.entries()? {
+            let mut entry = entry?;
+            let header = entry.header().clone();
+            builder.append(&header, &mut entry)?;
+        }
+    }
+    for name in names {
+        builder.append_path_with_name(parent.join(name), name)?;
+    }
+    let file = builder
+        .into_inner()?
+        .finish()?
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+    file.sync_all()
+}
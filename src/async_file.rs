@@ -0,0 +1,270 @@
+//! Async writers around a `RotatingFile`. Writes, rotation and pruning all happen on a blocking thread pool, so the async
+//! side only ever copies the buffer and hands it off.
+use crate::RotatingFile;
+use std::{
+    future::Future,
+    io::{self, Write},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+type Job = Box<dyn FnOnce() -> (Box<RotatingFile>, io::Result<()>) + Send>;
+type InFlight =
+    Pin<Box<dyn Future<Output = io::Result<(Box<RotatingFile>, io::Result<()>)>> + Send>>;
+/// Runs a job on whichever blocking pool the runtime provides. An error means the job was lost, i.e. it panicked.
+type Spawner = fn(Job) -> InFlight;
+
+enum State {
+    /// `None` once a job has been lost, after which every call fails
+    Idle(Option<Box<RotatingFile>>),
+    Busy(InFlight),
+}
+
+/// The state machine shared by the async writers. As with `tokio::fs::File` a write is reported as done as soon as it's been
+/// handed off, and any error from it is returned by the next call instead.
+pub(crate) struct AsyncCore {
+    state: State,
+    spawn: Spawner,
+    flushing: bool,
+}
+
+impl AsyncCore {
+    pub fn new(file: RotatingFile, spawn: Spawner) -> Self {
+        Self {
+            state: State::Idle(Some(Box::new(file))),
+            spawn,
+            flushing: false,
+        }
+    }
+
+    /// Wait for anything in flight, returning its result.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let State::Busy(in_flight) = &mut self.state {
+            let result = match ready!(in_flight.as_mut().poll(cx)) {
+                Ok((file, result)) => {
+                    self.state = State::Idle(Some(file));
+                    result
+                }
+                Err(e) => {
+                    self.state = State::Idle(None);
+                    Err(e)
+                }
+            };
+            return Poll::Ready(result);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start(
+        &mut self,
+        op: impl FnOnce(&mut RotatingFile) -> io::Result<()> + Send + 'static,
+    ) -> io::Result<()> {
+        let mut file = match &mut self.state {
+            State::Idle(file) => file.take().ok_or_else(lost)?,
+            State::Busy(_) => unreachable!("start called while busy"),
+        };
+        self.state = State::Busy((self.spawn)(Box::new(move || {
+            let result = op(&mut file);
+            (file, result)
+        })));
+        Ok(())
+    }
+
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, bytes: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.poll_idle(cx))?;
+        let owned = bytes.to_vec();
+        self.start(move |file| file.write_all(&owned))?;
+        Poll::Ready(Ok(bytes.len()))
+    }
+
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.flushing {
+            ready!(self.poll_idle(cx))?;
+            self.start(|file| file.flush())?;
+            self.flushing = true;
+        }
+        let result = ready!(self.poll_idle(cx));
+        self.flushing = false;
+        Poll::Ready(result)
+    }
+
+    /// Run something else against the file, i.e. a rotation, once anything in flight is done.
+    pub async fn run(
+        &mut self,
+        op: impl FnOnce(&mut RotatingFile) -> io::Result<()> + Send + 'static,
+    ) -> io::Result<()> {
+        std::future::poll_fn(|cx| self.poll_idle(cx)).await?;
+        self.start(op)?;
+        std::future::poll_fn(|cx| self.poll_idle(cx)).await
+    }
+
+    pub async fn into_inner(mut self) -> io::Result<RotatingFile> {
+        std::future::poll_fn(|cx| self.poll_idle(cx)).await?;
+        match self.state {
+            State::Idle(file) => file.map(|file| *file).ok_or_else(lost),
+            State::Busy(_) => unreachable!("busy after waiting"),
+        }
+    }
+}
+
+fn lost() -> io::Error {
+    io::Error::other("RotatingFile was lost after a panic on the blocking thread")
+}
+
+/// `futures::io::AsyncWrite` around a `RotatingFile`, for async-std, smol and anything else using the `futures` traits. The file
+/// work runs on the `blocking` crate's thread pool, so it doesn't depend on any particular runtime.
+///
+/// A write returns as soon as the bytes have been copied and handed off, so an error from it shows up on the next write or flush.
+/// Flush (or close) before dropping to be sure everything has been written, as with `tokio::fs::File`.
+#[cfg(feature = "futures-io")]
+pub struct FuturesRotatingFile {
+    core: AsyncCore,
+}
+
+#[cfg(feature = "futures-io")]
+impl std::fmt::Debug for FuturesRotatingFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuturesRotatingFile")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl FuturesRotatingFile {
+    pub fn new(file: RotatingFile) -> Self {
+        Self {
+            core: AsyncCore::new(file, |job| {
+                Box::pin(async move { Ok(blocking::unblock(job).await) })
+            }),
+        }
+    }
+
+    /// Rotate now, regardless of the rotation condition, see `RotatingFile::rotate`.
+    pub async fn rotate(&mut self) -> io::Result<()> {
+        self.core.run(RotatingFile::rotate).await
+    }
+
+    /// Wait for anything in flight and hand back the `RotatingFile`.
+    pub async fn into_inner(self) -> io::Result<RotatingFile> {
+        self.core.into_inner().await
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for FuturesRotatingFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bytes: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().core.poll_write(cx, bytes)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().core.poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().core.poll_flush(cx)
+    }
+}
+
+/// `tokio::io::AsyncWrite` around a `RotatingFile`. Writes, rotation and pruning run via `tokio::task::spawn_blocking`, so this
+/// must be used from within a tokio runtime.
+///
+/// A write returns as soon as the bytes have been copied and handed off, so an error from it shows up on the next write or flush.
+/// Flush (or shut down) before dropping to be sure everything has been written, as with `tokio::fs::File`.
+#[cfg(feature = "tokio")]
+pub struct AsyncRotatingFile {
+    core: AsyncCore,
+}
+
+#[cfg(feature = "tokio")]
+impl std::fmt::Debug for AsyncRotatingFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncRotatingFile").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncRotatingFile {
+    pub fn new(file: RotatingFile) -> Self {
+        Self {
+            core: AsyncCore::new(file, |job| {
+                let handle = tokio::task::spawn_blocking(job);
+                Box::pin(async move { handle.await.map_err(io::Error::other) })
+            }),
+        }
+    }
+
+    /// Rotate now, regardless of the rotation condition, see `RotatingFile::rotate`.
+    pub async fn rotate(&mut self) -> io::Result<()> {
+        self.core.run(RotatingFile::rotate).await
+    }
+
+    /// Wait for anything in flight and hand back the `RotatingFile`.
+    pub async fn into_inner(self) -> io::Result<RotatingFile> {
+        self.core.into_inner().await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for AsyncRotatingFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bytes: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().core.poll_write(cx, bytes)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().core.poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().core.poll_flush(cx)
+    }
+}
+
+/// Handle on an [`AsyncRotatingFile`] for many tasks to write to at once, the async counterpart to
+/// [`SharedRotatingFile`](crate::SharedRotatingFile). Cheap to clone, and each `write_all` is a record which is handed off whole, so
+/// records from different tasks are never interleaved.
+///
+/// Tasks take turns through a `tokio::sync::Mutex`, which is fair: whoever has waited longest writes next, so a busy task can't
+/// starve the others. The lock is only held while a task waits for the previous write to finish and hands off its own, never across
+/// the blocking file work itself, so nothing running on the blocking pool ever waits on it.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct SharedAsyncRotatingFile {
+    inner: std::sync::Arc<tokio::sync::Mutex<AsyncRotatingFile>>,
+}
+
+#[cfg(feature = "tokio")]
+impl SharedAsyncRotatingFile {
+    pub fn new(file: RotatingFile) -> Self {
+        Self {
+            inner: std::sync::Arc::new(tokio::sync::Mutex::new(AsyncRotatingFile::new(file))),
+        }
+    }
+
+    /// Write all of `bytes` as one record. As with `AsyncRotatingFile`, an error from writing it is returned by the next call.
+    pub async fn write_all(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut file = self.inner.lock().await;
+        std::future::poll_fn(|cx| file.core.poll_write(cx, bytes))
+            .await
+            .map(|_| ())
+    }
+
+    /// Wait for every write handed off so far, then flush the file.
+    pub async fn flush(&self) -> io::Result<()> {
+        let mut file = self.inner.lock().await;
+        std::future::poll_fn(|cx| file.core.poll_flush(cx)).await
+    }
+
+    /// Rotate now, regardless of the rotation condition, see `RotatingFile::rotate`.
+    pub async fn rotate(&self) -> io::Result<()> {
+        self.inner.lock().await.rotate().await
+    }
+
+    /// Take the lock for exclusive use of the `AsyncRotatingFile`, i.e. to make several writes in a row.
+    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, AsyncRotatingFile> {
+        self.inner.lock().await
+    }
+}
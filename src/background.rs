@@ -0,0 +1,144 @@
+//! Worker for `RotatingFile::with_background_rotation`, which takes the slow parts of rotation (syncing the old file, pruning and
+//! opening the spare file for `RotatingFile::with_precreate`) off the writing thread.
+use crate::{
+    filesystem::{open_append_with, OpenWith},
+    FileHandle, FileIndexInt, FileSystem, PruneCondition, RotatingFile,
+};
+use regex::bytes::Regex;
+use std::{
+    ffi::OsString,
+    io,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+pub(crate) enum Job<F> {
+    /// Sync and close a file which has just been rotated
+    Retire(F),
+    Prune {
+        file_regex: Regex,
+        parent: PathBuf,
+        filename_root: OsString,
+        index: FileIndexInt,
+        prune_method: PruneCondition,
+        /// As for `RotatingFile::with_archive`, done before pruning
+        #[cfg(feature = "archive")]
+        archive_after: Option<std::time::Duration>,
+    },
+    /// Open the spare file to swap in at the next rotation, sending it back to the `RotatingFile`.
+    OpenSpare { path: PathBuf, with: OpenWith },
+    /// Reply once every job sent before this one is done
+    Barrier(Sender<()>),
+}
+
+pub(crate) struct Background<F> {
+    sender: Option<Sender<Job<F>>>,
+    spares: Receiver<F>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<F> Background<F> {
+    pub fn new<FS>(fs: FS) -> std::io::Result<Self>
+    where
+        FS: FileSystem<File = F> + Send + 'static,
+        F: FileHandle + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let (spare_sender, spares) = channel();
+        let handle = thread::Builder::new()
+            .name("turnstiles-rotation".to_string())
+            .spawn(move || worker(fs, receiver, spare_sender))?;
+        Ok(Self {
+            sender: Some(sender),
+            spares,
+            handle: Some(handle),
+        })
+    }
+
+    /// A spare file opened by the worker, if one is ready.
+    pub fn take_spare(&self) -> Option<F> {
+        self.spares.try_iter().last()
+    }
+
+    /// Wait for every job sent so far to be done.
+    pub fn drain(&self) {
+        let (done, wait) = channel();
+        if self.send(Job::Barrier(done)).is_ok() {
+            // An error means the worker has gone, so there's nothing left to wait for
+            let _ = wait.recv();
+        }
+    }
+
+    /// Hand a job to the worker, giving it back if the worker has gone so it can be done inline.
+    pub fn send(&self, job: Job<F>) -> Result<(), Job<F>> {
+        match &self.sender {
+            Some(sender) => sender.send(job).map_err(|e| e.0),
+            None => Err(job),
+        }
+    }
+}
+
+impl<F> Drop for Background<F> {
+    /// Waits for outstanding jobs, so everything is synced and pruned once the `RotatingFile` is gone.
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Do a job, giving back the file if it was `OpenSpare`.
+pub(crate) fn run<FS: FileSystem>(fs: &FS, job: Job<FS::File>) -> io::Result<Option<FS::File>> {
+    match job {
+        Job::Retire(file) => file.sync_all().map(|_| None),
+        Job::OpenSpare { path, with } => open_append_with(fs, &path, with).map(Some),
+        Job::Barrier(done) => {
+            let _ = done.send(());
+            Ok(None)
+        }
+        Job::Prune {
+            file_regex,
+            parent,
+            filename_root,
+            index,
+            prune_method,
+            #[cfg(feature = "archive")]
+            archive_after,
+        } => {
+            #[cfg(feature = "archive")]
+            if let Some(older_than) = archive_after {
+                let rotated = RotatingFile::<FS>::list_rotated_log_files(fs, &file_regex, &parent)?;
+                crate::archive::archive_logs(&parent, &filename_root, &rotated, older_than)?;
+            }
+            for path in RotatingFile::<FS>::files_to_prune(
+                fs,
+                &file_regex,
+                &parent,
+                &filename_root,
+                index,
+                &prune_method,
+            )? {
+                RotatingFile::<FS>::remove_rotated_file(fs, &path)?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+fn worker<FS: FileSystem>(fs: FS, receiver: Receiver<Job<FS::File>>, spares: Sender<FS::File>) {
+    while let Ok(job) = receiver.recv() {
+        match run(&fs, job) {
+            Ok(Some(spare)) => {
+                // If the RotatingFile has gone there's nothing to do with it
+                let _ = spares.send(spare);
+            }
+            Ok(None) => {}
+            Err(e) => println!(
+                "WARN: turnstiles caught error in background rotation.\nErr: {}",
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Command line tool for sets of files written by turnstiles, see `turnstiles help`.
+use anyhow::{bail, Result};
+use std::{
+    env, io,
+    process::ExitCode,
+    time::{Duration, SystemTime},
+};
+use turnstiles::{
+    inspect::{self, ChecksumStatus},
+    PruneCondition,
+};
+
+const USAGE: &str = "Usage: turnstiles <command> <path> [args]
+
+<path> is the path the files were created with, i.e. /var/log/app.log for /var/log/app.log.ACTIVE
+
+Commands:
+    cat <path>               Print the whole set in order, oldest first
+    ls <path>                List the files with their sizes and ages
+    prune <path> <condition> Remove files as a RotatingFile would, i.e. 'files: 10' or 'age: 7d'
+    verify <path>            Check for missing indices, unreadable files, broken epoch chains and bad checksums";
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1).collect()) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("turnstiles: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<ExitCode> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["cat", path] => {
+            inspect::cat(path, &mut io::stdout().lock())?;
+        }
+        ["ls", path] => {
+            let now = SystemTime::now();
+            for file in inspect::list(path)? {
+                let age = file
+                    .modified
+                    .and_then(|m| now.duration_since(m).ok())
+                    .map_or_else(|| "-".to_string(), format_age);
+                println!("{:>12} {:>6} {}", file.size, age, file.path.display());
+            }
+        }
+        ["prune", path, condition] => {
+            let condition: PruneCondition = condition.parse()?;
+            for removed in inspect::prune(path, &condition)? {
+                println!("removed {}", removed.display());
+            }
+        }
+        ["verify", path] => {
+            let report = inspect::verify_integrity(path)?;
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            let checked = report
+                .files
+                .iter()
+                .filter(|file| file.checksum == ChecksumStatus::Match)
+                .count();
+            println!(
+                "{} files, {} checksums verified, {} problems",
+                report.files.len(),
+                checked,
+                report.problems.len()
+            );
+            if !report.is_ok() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        ["help"] | ["--help"] | ["-h"] => println!("{}", USAGE),
+        _ => bail!("invalid arguments\n\n{}", USAGE),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Age in its largest whole unit, i.e. `3d`.
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        s if s >= 24 * 60 * 60 => format!("{}d", s / (24 * 60 * 60)),
+        s if s >= 60 * 60 => format!("{}h", s / (60 * 60)),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    FileIndexInt, FileSystem, MemoryFileSystem, PruneCondition, RotatingFile, RotationCondition,
+};
+use anyhow::Result;
+use std::{io, path::PathBuf};
+
+/// A `RotatingFile` held entirely in memory, rotating into numbered segments rather than files. Everything else, i.e. the
+/// rotation and prune conditions, filters and hooks, behaves exactly as it does on disk.
+pub type RotatingBuffer = RotatingFile<MemoryFileSystem>;
+
+/// A rotated segment taken out of a [`RotatingBuffer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// The index the segment was rotated to, as a file would have been `<name>.<index>`
+    pub index: FileIndexInt,
+    pub data: Vec<u8>,
+}
+
+impl RotatingFile<MemoryFileSystem> {
+    /// Create a new in-memory buffer. The name only shows up in banners and error messages, as there's nowhere for it to go.
+    pub fn new_buffer(
+        name: &str,
+        rotation_method: RotationCondition,
+        prune_method: PruneCondition,
+        require_newline: bool,
+    ) -> Result<Self> {
+        Self::new_in(
+            MemoryFileSystem::new(),
+            name,
+            rotation_method,
+            prune_method,
+            require_newline,
+        )
+    }
+
+    /// Contents of the segment currently being written to.
+    pub fn active_segment(&self) -> Vec<u8> {
+        self.fs.read(&self.active_file_path).unwrap_or_default()
+    }
+
+    /// Copy of the rotated segments still held, oldest first.
+    pub fn segments(&self) -> Result<Vec<Segment>, io::Error> {
+        Ok(self
+            .segment_paths()?
+            .into_iter()
+            .map(|(_, segment)| segment)
+            .collect())
+    }
+
+    /// Take the rotated segments out of the buffer, oldest first, i.e. to ship them elsewhere. The active segment is left alone,
+    /// call [`RotatingFile::rotate`] first to include it.
+    pub fn drain_segments(&mut self) -> Result<Vec<Segment>, io::Error> {
+        let mut segments = vec![];
+        for (path, segment) in self.segment_paths()? {
+            self.fs.remove_file(&path)?;
+            segments.push(segment);
+        }
+        Ok(segments)
+    }
+
+    /// Rotated segments along with where they're held, listed as for pruning so any compression suffix is included.
+    fn segment_paths(&self) -> Result<Vec<(PathBuf, Segment)>, io::Error> {
+        let mut segments = vec![];
+        for name in Self::list_rotated_log_files(&self.fs, &self.file_regex, &self.parent)? {
+            let index = Self::rotated_file_index(&name).map_err(io::Error::other)?;
+            let path = self.parent.join(name);
+            if let Some(data) = self.fs.read(&path) {
+                segments.push((path, Segment { index, data }));
+            }
+        }
+        segments.sort_by_key(|(_, segment)| segment.index);
+        Ok(segments)
+    }
+}
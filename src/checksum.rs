@@ -0,0 +1,90 @@
+//! SHA-256 sidecars for rotated files, see `ChecksumHook`.
+use crate::{checksum_sidecar, FileIndexInt, RotationHook};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+/// `RotationHook` which writes the SHA-256 of each file as it's rotated to `<file>.sha256` alongside it, in the format `sha256sum`
+/// uses so they can be checked with `sha256sum -c` as well as by [`inspect::verify`](crate::inspect::verify). The file is hashed on
+/// the thread doing the rotation, before any rollers run. Sidecars are pruned and archived along with their files.
+///
+/// A file compressed or encrypted by a roller afterwards keeps the sidecar of its original contents under its original name, so
+/// `verify` skips it rather than reporting it missing. With `RotatingFile::with_streaming_compression` it's the compressed file
+/// that's hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChecksumHook;
+
+impl RotationHook for ChecksumHook {
+    fn on_after_rotate(
+        &mut self,
+        _old_path: &Path,
+        new_path: &Path,
+        _index: FileIndexInt,
+    ) -> io::Result<()> {
+        write_sidecar(new_path)
+    }
+}
+
+/// Hex SHA-256 of a file's contents.
+pub(crate) fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(hasher))
+}
+
+fn to_hex(hasher: Sha256) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in hasher.finalize() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// Running SHA-256 of what's been written to the active file, for `RotatingFile::with_hash_chain`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HashChain {
+    /// `None` once something may have reached the file without being hashed, until the next file
+    hasher: Option<Sha256>,
+}
+
+impl HashChain {
+    pub fn new() -> Self {
+        Self {
+            hasher: Some(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(bytes);
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.hasher = None;
+    }
+
+    /// Hash of the file just rotated if every byte of it was seen, starting afresh for the next one.
+    pub fn finish(&mut self) -> Option<String> {
+        self.hasher.replace(Sha256::new()).map(to_hex)
+    }
+}
+
+fn write_sidecar(path: &Path) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other(format!("Invalid file name {}", path.display())))?;
+    let sidecar = path.with_file_name(checksum_sidecar(name));
+    let mut partial = sidecar.as_os_str().to_owned();
+    partial.push(".partial");
+    // The name as it is, as `sha256sum` would write it, so it needn't be UTF-8
+    let mut contents = format!("{}  ", sha256_file(path)?).into_bytes();
+    contents.extend_from_slice(name.as_encoded_bytes());
+    contents.push(b'\n');
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, &sidecar)
+}
@@ -0,0 +1,149 @@
+//! Running a command on each rotated file, see `CommandRoller`.
+use crate::{ErrorHook, Roller};
+use std::{
+    ffi::OsString,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How often a command with a timeout is checked on. Without a timeout the thread just blocks until it exits.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `Roller` which runs a command with the rotated file's path as its last argument, like logrotate's `postrotate`. The command is
+/// started during rotation but waited on in the background, so it doesn't hold up writes; if it exits unsuccessfully or is killed for
+/// running past the timeout that's passed to the error hook (or printed if there isn't one) along with anything it wrote to stderr.
+/// When dropped it waits for any commands still running.
+///
+/// The file is passed on unchanged to any later rollers, and as the command runs later they shouldn't move or remove it.
+pub struct CommandRoller {
+    program: OsString,
+    args: Vec<OsString>,
+    timeout: Option<Duration>,
+    error_hook: Arc<Mutex<Option<ErrorHook>>>,
+    running: Vec<JoinHandle<()>>,
+}
+
+impl fmt::Debug for CommandRoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandRoller")
+            .field("program", &self.program)
+            .field("args", &self.args)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandRoller {
+    pub fn new(program: impl Into<OsString>) -> Self {
+        Self {
+            program: program.into(),
+            args: vec![],
+            timeout: None,
+            error_hook: Arc::new(Mutex::new(None)),
+            running: vec![],
+        }
+    }
+
+    /// Add an argument, which goes before the file path.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Kill the command if it's still running after this long.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send failures here rather than printing them, as for `RotatingFile::with_error_hook`. Called from a background thread.
+    pub fn with_error_hook(self, hook: impl FnMut(&str, &anyhow::Error) + Send + 'static) -> Self {
+        *self.error_hook.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(hook));
+        self
+    }
+}
+
+/// Wait for the command to finish or time out, returning a description of what went wrong if it didn't succeed.
+fn wait(mut child: Child, timeout: Option<Duration>) -> Result<(), String> {
+    // Read stderr on its own thread so a chatty command can't fill the pipe and block
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut buf = vec![];
+            let _ = stderr.read_to_end(&mut buf);
+            String::from_utf8_lossy(&buf).trim().to_string()
+        })
+    });
+    let start = Instant::now();
+    let status = match timeout {
+        None => child
+            .wait()
+            .map_err(|e| format!("could not be waited on: {}", e)),
+        Some(timeout) => loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if start.elapsed() > timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break Err("timed out and was killed".to_string());
+                }
+                Ok(None) => sleep(POLL_INTERVAL),
+                Err(e) => break Err(format!("could not be waited on: {}", e)),
+            }
+        },
+    };
+    let stderr = stderr.and_then(|h| h.join().ok()).unwrap_or_default();
+    let problem = match status {
+        Ok(status) if status.success() => return Ok(()),
+        Ok(status) => format!("exited with {}", status),
+        Err(problem) => problem,
+    };
+    if stderr.is_empty() {
+        Err(problem)
+    } else {
+        Err(format!("{}, stderr: {}", problem, stderr))
+    }
+}
+
+impl Roller for CommandRoller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>> {
+        self.running.retain(|handle| !handle.is_finished());
+        let child = Command::new(&self.program)
+            .args(&self.args)
+            .arg(rotated)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let timeout = self.timeout;
+        let error_hook = self.error_hook.clone();
+        let context = format!(
+            "post-rotation command {:?} on {}",
+            self.program,
+            rotated.display()
+        );
+        self.running.push(thread::spawn(move || {
+            if let Err(problem) = wait(child, timeout) {
+                let e = anyhow::anyhow!(problem);
+                let mut hook = error_hook.lock().unwrap_or_else(|e| e.into_inner());
+                match hook.as_mut() {
+                    Some(hook) => hook(&context, &e),
+                    None => println!("WARN: turnstiles caught error in {}.\nErr: {}", context, e),
+                }
+            }
+        }));
+        Ok(Some(rotated.to_path_buf()))
+    }
+}
+
+impl Drop for CommandRoller {
+    fn drop(&mut self) {
+        for handle in self.running.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,397 @@
+//! Compressing rotated files in the background with gzip, zstd or lz4, see `CompressRoller`.
+use crate::{ErrorHook, Roller};
+#[cfg(feature = "compression")]
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, sync_channel, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// How many rotated files can be waiting for compression before more are left uncompressed.
+pub const COMPRESS_QUEUE_LEN: usize = 64;
+
+/// Which compression `CompressRoller` uses, each behind its own feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionCodec {
+    /// `<file>.gz`, with the `compression` feature. Levels 0 (none) to 9 (best).
+    #[cfg(feature = "compression")]
+    Gzip,
+    /// `<file>.zst`, with the `zstd` feature. Much faster than gzip for a similar ratio. Levels 1 (fastest) to 22 (best), or
+    /// negative for faster still.
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// `<file>.lz4` in the frame format, so the `lz4` command line tool can read it, with the `lz4` feature. The cheapest of the three
+    /// for the busiest machines though the files are bigger. Has no levels.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl CompressionCodec {
+    fn suffix(self) -> &'static str {
+        match self {
+            #[cfg(feature = "compression")]
+            CompressionCodec::Gzip => ".gz",
+            #[cfg(feature = "zstd")]
+            CompressionCodec::Zstd => ".zst",
+            #[cfg(feature = "lz4")]
+            CompressionCodec::Lz4 => ".lz4",
+        }
+    }
+}
+
+/// Options for a [`CompressRoller`], i.e. `CompressionConfig { level: Some(19), ..CompressionConfig::new(CompressionCodec::Zstd) }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionConfig {
+    pub codec: CompressionCodec,
+    /// On the codec's own scale, see [`CompressionCodec`], or `None` for its default. Levels out of range are clamped to it.
+    pub level: Option<i32>,
+    /// Threads to compress each file with, where the codec supports it (zstd), otherwise ignored
+    pub threads: u32,
+    /// Files smaller than this many bytes are left uncompressed, as compressing them saves next to nothing
+    pub min_size: u64,
+}
+
+impl CompressionConfig {
+    /// The codec at its default level on one thread, compressing every file.
+    pub fn new(codec: CompressionCodec) -> Self {
+        Self {
+            codec,
+            level: None,
+            threads: 1,
+            min_size: 0,
+        }
+    }
+}
+
+/// `Roller` which compresses each rotated file on a background thread, removing the original once done, so compression never
+/// happens inside `write`. `new` gzips to `<file>.gz`, `new_zstd` and `new_lz4` use the other codecs and `with_config` takes a
+/// [`CompressionConfig`] for the rest of the options. The compressed file is written under a temporary name and renamed into
+/// place, so a crash part way through leaves the original alone. Compressed files are still picked up by the `PruneCondition` and
+/// when finding the latest index.
+///
+/// If [`COMPRESS_QUEUE_LEN`] files are already waiting the file is left uncompressed and the error reported, rather than holding up
+/// the rotation. Errors on the background thread go to the error hook given to this roller, or are printed as warnings. When dropped
+/// it waits for queued files to be compressed.
+///
+/// The original is passed on to any later rollers, so put this last in the chain.
+pub struct CompressRoller {
+    sender: Option<SyncSender<Task>>,
+    handle: Option<JoinHandle<()>>,
+    error_hook: Arc<Mutex<Option<ErrorHook>>>,
+}
+
+enum Task {
+    Compress(PathBuf),
+    /// Reply once everything queued before this is compressed
+    Barrier(Sender<()>),
+}
+
+impl fmt::Debug for CompressRoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressRoller").finish_non_exhaustive()
+    }
+}
+
+impl CompressRoller {
+    /// Gzip rotated files at the default level.
+    #[cfg(feature = "compression")]
+    pub fn new() -> io::Result<Self> {
+        Self::with_config(CompressionConfig::new(CompressionCodec::Gzip))
+    }
+
+    /// As `new` but with a gzip level from 0 (none) to 9 (best).
+    #[cfg(feature = "compression")]
+    pub fn new_with_level(level: u32) -> io::Result<Self> {
+        Self::with_config(CompressionConfig {
+            level: Some(level.min(9) as i32),
+            ..CompressionConfig::new(CompressionCodec::Gzip)
+        })
+    }
+
+    /// Compress rotated files with zstd at its default level.
+    #[cfg(feature = "zstd")]
+    pub fn new_zstd() -> io::Result<Self> {
+        Self::with_config(CompressionConfig::new(CompressionCodec::Zstd))
+    }
+
+    /// As `new_zstd` but with a zstd level, from 1 (fastest) to 22 (best), or negative for faster still. Levels out of range are
+    /// clamped to it.
+    #[cfg(feature = "zstd")]
+    pub fn new_zstd_with_level(level: i32) -> io::Result<Self> {
+        Self::with_config(CompressionConfig {
+            level: Some(level),
+            ..CompressionConfig::new(CompressionCodec::Zstd)
+        })
+    }
+
+    /// Compress rotated files with lz4, using the frame format so they can be read with the `lz4` command line tool.
+    #[cfg(feature = "lz4")]
+    pub fn new_lz4() -> io::Result<Self> {
+        Self::with_config(CompressionConfig::new(CompressionCodec::Lz4))
+    }
+
+    pub fn with_config(config: CompressionConfig) -> io::Result<Self> {
+        let (sender, receiver) = sync_channel::<Task>(COMPRESS_QUEUE_LEN);
+        let error_hook: Arc<Mutex<Option<ErrorHook>>> = Arc::new(Mutex::new(None));
+        let worker_hook = error_hook.clone();
+        let handle = thread::Builder::new()
+            .name("turnstiles-compress".to_string())
+            .spawn(move || {
+                for task in receiver {
+                    let path = match task {
+                        Task::Compress(path) => path,
+                        Task::Barrier(done) => {
+                            let _ = done.send(());
+                            continue;
+                        }
+                    };
+                    if let Err(e) = compress(&path, &config) {
+                        let context = format!("compressing {}", path.display());
+                        let mut hook = worker_hook.lock().unwrap_or_else(|e| e.into_inner());
+                        match hook.as_mut() {
+                            Some(hook) => hook(&context, &e.into()),
+                            None => println!(
+                                "WARN: turnstiles caught error in {}.\nErr: {}",
+                                context, e
+                            ),
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            error_hook,
+        })
+    }
+
+    /// Send errors from the background thread here rather than printing them, as for `RotatingFile::with_error_hook`.
+    pub fn with_error_hook(self, hook: impl FnMut(&str, &anyhow::Error) + Send + 'static) -> Self {
+        *self.error_hook.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(hook));
+        self
+    }
+}
+
+fn compress(path: &Path, config: &CompressionConfig) -> io::Result<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(config.codec.suffix());
+    let compressed = PathBuf::from(compressed);
+    let mut partial = compressed.as_os_str().to_owned();
+    partial.push(".partial");
+
+    // The original may have been pruned while it waited, in which case there's nothing to do
+    let mut original = File::open(path)?;
+    if original.metadata()?.len() < config.min_size {
+        return Ok(());
+    }
+    let result = write_compressed(&mut original, &partial, config);
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result?;
+    fs::rename(&partial, &compressed)?;
+    match fs::remove_file(path) {
+        // Pruned while being compressed, so the compressed copy shouldn't outlive it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::remove_file(&compressed),
+        result => result,
+    }
+}
+
+fn write_compressed(
+    original: &mut File,
+    partial: impl AsRef<Path>,
+    config: &CompressionConfig,
+) -> io::Result<()> {
+    let out = File::create(partial)?;
+    // WASI has no permissions to copy, and says so rather than doing nothing
+    #[cfg(not(target_os = "wasi"))]
+    out.set_permissions(original.metadata()?.permissions())?;
+    #[cfg(all(unix, feature = "ownership"))]
+    crate::owner::copy_owner(original, &out)?;
+    let modified = original.metadata()?.modified()?;
+    let mut encoder = encoder(config, BufWriter::new(out))?;
+    io::copy(original, &mut encoder)?;
+    let file = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+    file.set_modified(modified)?;
+    file.sync_all()
+}
+
+/// Any of the codecs' encoders, writing compressed bytes to `W`.
+pub(crate) trait Encoder<W>: Write + Send {
+    fn get_mut(&mut self) -> &mut W;
+    /// Write out the end of the stream and hand back `W`
+    fn finish(self: Box<Self>) -> io::Result<W>;
+}
+
+#[cfg(feature = "compression")]
+impl<W: Write + Send> Encoder<W> for GzEncoder<W> {
+    fn get_mut(&mut self) -> &mut W {
+        GzEncoder::get_mut(self)
+    }
+    fn finish(self: Box<Self>) -> io::Result<W> {
+        GzEncoder::finish(*self)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write + Send> Encoder<W> for zstd::Encoder<'static, W> {
+    fn get_mut(&mut self) -> &mut W {
+        zstd::Encoder::get_mut(self)
+    }
+    fn finish(self: Box<Self>) -> io::Result<W> {
+        zstd::Encoder::finish(*self)
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl<W: Write + Send> Encoder<W> for lz4_flex::frame::FrameEncoder<W> {
+    fn get_mut(&mut self) -> &mut W {
+        lz4_flex::frame::FrameEncoder::get_mut(self)
+    }
+    fn finish(self: Box<Self>) -> io::Result<W> {
+        Ok(lz4_flex::frame::FrameEncoder::finish(*self)?)
+    }
+}
+
+/// Encoder for the configured codec and level.
+pub(crate) fn encoder<W: Write + Send + 'static>(
+    config: &CompressionConfig,
+    out: W,
+) -> io::Result<Box<dyn Encoder<W>>> {
+    Ok(match config.codec {
+        #[cfg(feature = "compression")]
+        CompressionCodec::Gzip => {
+            let level = config.level.map_or_else(Compression::default, |l| {
+                Compression::new(l.clamp(0, 9) as u32)
+            });
+            Box::new(GzEncoder::new(out, level))
+        }
+        #[cfg(feature = "zstd")]
+        CompressionCodec::Zstd => {
+            let range = zstd::compression_level_range();
+            let level = config.level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| {
+                l.clamp(*range.start(), *range.end())
+            });
+            let mut encoder = zstd::Encoder::new(out, level)?;
+            if config.threads > 1 {
+                encoder.multithread(config.threads)?;
+            }
+            Box::new(encoder)
+        }
+        #[cfg(feature = "lz4")]
+        CompressionCodec::Lz4 => Box::new(lz4_flex::frame::FrameEncoder::new(out)),
+    })
+}
+
+/// What a size-based rotation counts with [`RotatingFile::with_streaming_compression`](crate::RotatingFile::with_streaming_compression).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeBasis {
+    /// Bytes written to the `RotatingFile`, before compression
+    Uncompressed,
+    /// Bytes which have reached the file, after compression. The encoder holds some back until it has a block's worth, so this
+    /// lags behind a little.
+    Compressed,
+}
+
+/// Compresses what's written to the active file as it goes, see `RotatingFile::with_streaming_compression`. Each active file gets
+/// a stream of its own, finished when it's rotated.
+pub(crate) struct Stream {
+    config: CompressionConfig,
+    pub basis: SizeBasis,
+    encoder: Box<dyn Encoder<Vec<u8>>>,
+    /// Anything has been written or flushed, so there's a stream to finish. One which hasn't is left empty rather than given a
+    /// header and trailer, so a file which was opened and closed without being written to stays empty.
+    started: bool,
+}
+
+impl Stream {
+    pub fn new(config: CompressionConfig, basis: SizeBasis) -> io::Result<Self> {
+        Ok(Self {
+            config,
+            basis,
+            encoder: encoder(&config, vec![])?,
+            started: false,
+        })
+    }
+
+    /// Added to the active file's name and to rotated files.
+    pub fn suffix(&self) -> &'static str {
+        self.config.codec.suffix()
+    }
+
+    /// Compress `bytes`, giving back whatever compressed output is ready to go to the file.
+    pub fn compress(&mut self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        self.started = true;
+        self.encoder.write_all(bytes)?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// Push out everything compressed so far, so the file can be read up to here.
+    pub fn flush(&mut self) -> io::Result<Vec<u8>> {
+        if !self.started {
+            return Ok(vec![]);
+        }
+        self.encoder.flush()?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// End the stream, giving back the rest of it, and start a new one for the next file.
+    pub fn finish(&mut self) -> io::Result<Vec<u8>> {
+        if !std::mem::take(&mut self.started) {
+            return Ok(vec![]);
+        }
+        let encoder = std::mem::replace(&mut self.encoder, encoder(&self.config, vec![])?);
+        encoder.finish()
+    }
+}
+
+impl Roller for CompressRoller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>> {
+        let sender = self.sender.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "turnstiles compression thread has stopped",
+            )
+        })?;
+        match sender.try_send(Task::Compress(rotated.to_path_buf())) {
+            Ok(()) => Ok(Some(rotated.to_path_buf())),
+            Err(TrySendError::Full(_)) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "compression queue is full, leaving the file uncompressed",
+            )),
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "turnstiles compression thread has stopped",
+            )),
+        }
+    }
+
+    /// Waits for every file queued so far to be compressed.
+    fn drain(&mut self) -> io::Result<()> {
+        let Some(sender) = self.sender.as_ref() else {
+            return Ok(());
+        };
+        let (done, wait) = channel();
+        if sender.send(Task::Barrier(done)).is_ok() {
+            let _ = wait.recv();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CompressRoller {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish what's queued and stop
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::parse::{parse_bool, parse_duration, parse_size_mb};
+use crate::{PruneCondition, RotationCondition};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Settings which can be supplied from outside the code, keyed by `<PREFIX>_<KEY>`:
+///
+/// | Key               | Example | Meaning                                 |
+/// |-------------------|---------|-----------------------------------------|
+/// | `PATH`            | `/var/log/app.log` | Root path for the log files  |
+/// | `ROTATE_SIZE`     | `100MB` | `RotationCondition::SizeMB`             |
+/// | `ROTATE_AGE`      | `1d`    | `RotationCondition::Duration`           |
+/// | `MAX_FILES`       | `10`    | `PruneCondition::MaxFiles`              |
+/// | `MAX_AGE`         | `7d`    | `PruneCondition::MaxAge`                |
+/// | `REQUIRE_NEWLINE` | `true`  | `require_newline` argument to `new()`   |
+///
+/// Anything not given falls back to `None`/`false`.
+#[derive(Debug)]
+pub(crate) struct Config {
+    pub path: Option<String>,
+    pub rotation: RotationCondition,
+    pub prune: PruneCondition,
+    pub require_newline: bool,
+}
+
+impl Config {
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        Self::from_lookup(prefix, |key| std::env::var(key).ok())
+    }
+
+    /// Build the config by asking `lookup` for each `<PREFIX>_<KEY>`, so the same parsing is used however the values are stored.
+    pub fn from_lookup(prefix: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let get = |key: &str| {
+            let name = if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}_{}", prefix, key)
+            };
+            lookup(&name).map(|value| (name, value))
+        };
+
+        let rotation = match (get("ROTATE_SIZE"), get("ROTATE_AGE")) {
+            (Some((a, _)), Some((b, _))) => bail!("Only one of {} and {} may be set", a, b),
+            (Some((name, v)), None) => RotationCondition::SizeMB(
+                parse_size_mb(&v).with_context(|| format!("Invalid value for {}", name))?,
+            ),
+            (None, Some((name, v))) => RotationCondition::Duration(
+                parse_duration(&v).with_context(|| format!("Invalid value for {}", name))?,
+            ),
+            (None, None) => RotationCondition::None,
+        };
+
+        let prune = match (get("MAX_FILES"), get("MAX_AGE")) {
+            (Some((a, _)), Some((b, _))) => bail!("Only one of {} and {} may be set", a, b),
+            (Some((name, v)), None) => PruneCondition::MaxFiles(
+                v.trim()
+                    .parse()
+                    .with_context(|| format!("Invalid value for {}", name))?,
+            ),
+            (None, Some((name, v))) => PruneCondition::MaxAge(
+                parse_duration(&v).with_context(|| format!("Invalid value for {}", name))?,
+            ),
+            (None, None) => PruneCondition::None,
+        };
+
+        let require_newline = match get("REQUIRE_NEWLINE") {
+            Some((name, v)) => {
+                parse_bool(&v).with_context(|| format!("Invalid value for {}", name))?
+            }
+            None => false,
+        };
+
+        Ok(Self {
+            path: get("PATH").map(|(_, v)| v),
+            rotation,
+            prune,
+            require_newline,
+        })
+    }
+
+    /// Parse a config file of `KEY = value` lines using the same keys as the environment variables but without a prefix,
+    /// i.e. `ROTATE_SIZE = 100MB`. Keys are case-insensitive, blank lines and lines starting with `#` are ignored.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let values = read_values(path)?;
+        Self::from_lookup("", |key| values.get(key).cloned())
+    }
+}
+
+/// Read the `KEY = value` lines of a config file, with keys upper-cased.
+pub(crate) fn read_values(path: &Path) -> Result<HashMap<String, String>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Could not read config file {}", path.display()))?;
+    let mut values = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) => {
+                values.insert(key.trim().to_ascii_uppercase(), value.trim().to_string());
+            }
+            None => bail!("Line {} of {} is not KEY = value", i + 1, path.display()),
+        }
+    }
+    Ok(values)
+}
+
+/// Polls a config file for changes, at most once per interval, by looking at its modified time.
+#[derive(Debug)]
+pub(crate) struct ConfigWatcher {
+    path: PathBuf,
+    interval: Duration,
+    last_checked: Instant,
+    last_modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    /// Start watching a file, returning the watcher along with the config as it currently stands.
+    pub fn new(path: PathBuf, interval: Duration) -> Result<(Self, Config)> {
+        let last_modified = fs::metadata(&path)?.modified().ok();
+        let config = Config::from_file(&path)?;
+        Ok((
+            Self {
+                path,
+                interval,
+                last_checked: Instant::now(),
+                last_modified,
+            },
+            config,
+        ))
+    }
+
+    /// Returns the freshly parsed config if the file has changed since we last looked, or None if it's not time to check yet
+    /// or nothing has changed. A file which fails to parse is only reported once, not on every poll.
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        if self.last_checked.elapsed() < self.interval {
+            return None;
+        }
+        self.last_checked = Instant::now();
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => Some(modified),
+            Err(e) => return Some(Err(e.into())),
+        };
+        if modified == self.last_modified {
+            return None;
+        }
+        self.last_modified = modified;
+        Some(Config::from_file(&self.path))
+    }
+}
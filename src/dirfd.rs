@@ -0,0 +1,184 @@
+//! Working relative to a handle on the log directory rather than by path, see `DirFileSystem`.
+use crate::{DiskFileSystem, FileSystem, Metadata};
+use std::{
+    ffi::{CStr, CString, OsString},
+    fs::File,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::Path,
+    sync::Arc,
+};
+
+/// Unix only. A [`FileSystem`] which opens the log directory once and does everything after that relative to it with
+/// `openat`, `renameat` and `unlinkat`, so a `RotatingFile` keeps working if the directory is renamed or the process chroots,
+/// and nothing can swap a parent directory for a symlink between one step of a rotation and the next. Give it to
+/// [`RotatingFile::new_in`](crate::RotatingFile::new_in) along with a path in the directory:
+///
+/// ```no_run
+/// use turnstiles::{DirFileSystem, PruneCondition, RotatingFile, RotationCondition};
+/// let fs = DirFileSystem::open("/var/log/app")?;
+/// let file = RotatingFile::new_in(fs, "/var/log/app/app.log", RotationCondition::SizeMB(10), PruneCondition::MaxFiles(5), false)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Only the file name of each path is looked at, so every file is taken to be in the directory. Rollers and the helpers in
+/// [`inspect`](crate::inspect) are given paths as usual, so they see the directory as it is now, and `with_open_options` is ignored.
+#[derive(Debug, Clone)]
+pub struct DirFileSystem {
+    dir: Arc<OwnedFd>,
+}
+
+impl DirFileSystem {
+    /// Open the directory to work in.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let path = CString::new(dir.as_ref().as_os_str().as_bytes())?;
+        // SAFETY: the path is nul terminated, and the fd is ours if the call succeeded
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        Ok(Self {
+            dir: Arc::new(owned_fd(fd)?),
+        })
+    }
+
+    fn open_at(&self, name: &CStr, flags: libc::c_int) -> io::Result<File> {
+        // SAFETY: as for `open`, the directory fd being kept open by `self`
+        let fd = unsafe {
+            libc::openat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_CLOEXEC,
+                0o666 as libc::c_uint,
+            )
+        };
+        owned_fd(fd).map(File::from)
+    }
+}
+
+/// Take ownership of a fd returned by a libc call, or the error if it failed.
+fn owned_fd(fd: libc::c_int) -> io::Result<OwnedFd> {
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: a fd just returned to us, which nothing else has
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn file_name(path: &Path) -> io::Result<CString> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no file name", path.display()),
+        )
+    })?;
+    Ok(CString::new(name.as_bytes())?)
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl DiskFileSystem for DirFileSystem {}
+
+impl FileSystem for DirFileSystem {
+    type File = File;
+    fn open_append(&self, path: &Path) -> io::Result<File> {
+        self.open_at(
+            &file_name(path)?,
+            libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT,
+        )
+    }
+    fn open_append_dsync(&self, path: &Path) -> io::Result<Option<File>> {
+        self.open_at(
+            &file_name(path)?,
+            libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_DSYNC,
+        )
+        .map(Some)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (file_name(from)?, file_name(to)?);
+        let dir = self.dir.as_raw_fd();
+        // SAFETY: both names are nul terminated and the directory fd is open
+        check(unsafe { libc::renameat(dir, from.as_ptr(), dir, to.as_ptr()) })
+    }
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let mut from = self.open_at(&file_name(from)?, libc::O_RDONLY)?;
+        let mut to = self.open_at(
+            &file_name(to)?,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+        )?;
+        // Specialised by std to use copy_file_range where it can
+        io::copy(&mut from, &mut to)
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let name = file_name(path)?;
+        // SAFETY: as for `rename`
+        check(unsafe { libc::unlinkat(self.dir.as_raw_fd(), name.as_ptr(), 0) })
+    }
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        self.open_at(
+            &file_name(path)?,
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+        )
+        .map(|_| ())
+    }
+    fn read_dir(&self, _path: &Path) -> io::Result<Vec<OsString>> {
+        // A fresh fd for the directory, as `closedir` closes the one it's given and its position would otherwise be shared
+        let fd = self
+            .open_at(c".", libc::O_RDONLY | libc::O_DIRECTORY)?
+            .into_raw_fd();
+        // SAFETY: the fd is open, and is owned by the stream if this succeeds
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let e = io::Error::last_os_error();
+            // SAFETY: still ours as the stream wasn't made
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        let mut names = vec![];
+        loop {
+            // SAFETY: `stream` is an open directory stream, and the entry is only read until the next call. A null entry is the
+            // end of the directory: `readdir` otherwise only fails for a stream which isn't open.
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                break;
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsString::from_vec(name.to_vec()));
+            }
+        }
+        // SAFETY: the stream is open and not used again
+        unsafe { libc::closedir(stream) };
+        Ok(names)
+    }
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        // Opened rather than `fstatat` so std works out the creation time where there is one
+        let file = self.open_at(&file_name(path)?, libc::O_RDONLY | libc::O_NONBLOCK)?;
+        file.metadata().map(Metadata::from)
+    }
+    fn is_stream(&self, path: &Path) -> bool {
+        let Ok(name) = file_name(path) else {
+            return false;
+        };
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+        // SAFETY: the name is nul terminated and `stat` is only read if it was filled in
+        let result =
+            unsafe { libc::fstatat(self.dir.as_raw_fd(), name.as_ptr(), stat.as_mut_ptr(), 0) };
+        if result < 0 {
+            return false;
+        }
+        let kind = unsafe { stat.assume_init() }.st_mode & libc::S_IFMT;
+        kind != libc::S_IFREG && kind != libc::S_IFDIR
+    }
+}
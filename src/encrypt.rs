@@ -0,0 +1,210 @@
+//! Encrypting rotated files at rest with age, see `EncryptRoller`.
+use crate::{ErrorHook, Roller, ENCRYPTED_SUFFIX};
+use age::{
+    secrecy::SecretString,
+    x25519::{self, Recipient},
+    Encryptor,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter},
+    iter,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, sync_channel, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// How many rotated files can be waiting for encryption before more are left as plaintext.
+pub const ENCRYPT_QUEUE_LEN: usize = 64;
+
+/// Who can decrypt the rotated files.
+enum Key {
+    Recipient(Recipient),
+    Passphrase(SecretString),
+}
+
+impl Key {
+    fn encryptor(&self) -> io::Result<Encryptor> {
+        match self {
+            Key::Recipient(recipient) => {
+                Encryptor::with_recipients(iter::once(recipient as &dyn age::Recipient))
+                    .map_err(io::Error::other)
+            }
+            Key::Passphrase(passphrase) => Ok(Encryptor::with_user_passphrase(passphrase.clone())),
+        }
+    }
+}
+
+/// `Roller` which encrypts each rotated file with [age](https://age-encryption.org) on a background thread, writing
+/// `<file>.age` and removing the plaintext once done. `new` encrypts to an X25519 recipient, so only the holder of the matching
+/// identity can read them and the key to do so never has to be on the machine, while `with_passphrase` uses a passphrase instead.
+/// Either way the files can be decrypted with the `age` command line tool. The encrypted file is written under a temporary name and
+/// renamed into place, so a crash part way through leaves the plaintext alone. Encrypted files are still picked up by the
+/// `PruneCondition` and when finding the latest index.
+///
+/// If [`ENCRYPT_QUEUE_LEN`] files are already waiting the file is left as plaintext and the error reported, rather than holding up
+/// the rotation. Errors on the background thread go to the error hook given to this roller, or are printed as warnings. When dropped
+/// it waits for queued files to be encrypted.
+///
+/// Like `CompressRoller` this works on the original, so the two can't be chained. To compress as well use
+/// `RotatingFile::with_streaming_compression`, which gives files like `<file>.gz.age`. Put this last in the chain.
+pub struct EncryptRoller {
+    sender: Option<SyncSender<Task>>,
+    handle: Option<JoinHandle<()>>,
+    error_hook: Arc<Mutex<Option<ErrorHook>>>,
+}
+
+enum Task {
+    Encrypt(PathBuf),
+    /// Reply once everything queued before this is encrypted
+    Barrier(Sender<()>),
+}
+
+impl fmt::Debug for EncryptRoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptRoller").finish_non_exhaustive()
+    }
+}
+
+impl EncryptRoller {
+    /// Encrypt rotated files to an age X25519 recipient, the `age1...` public key printed by `age-keygen`.
+    pub fn new(recipient: &str) -> Result<Self> {
+        let recipient = recipient
+            .trim()
+            .parse::<x25519::Recipient>()
+            .map_err(|e| anyhow!("Invalid option: age recipient {:?}: {}", recipient, e))?;
+        Ok(Self::start(Key::Recipient(recipient))?)
+    }
+
+    /// Encrypt rotated files with a passphrase. Deriving the key from it is deliberately slow, around a second for each file.
+    pub fn with_passphrase(passphrase: impl Into<String>) -> io::Result<Self> {
+        Self::start(Key::Passphrase(SecretString::from(passphrase.into())))
+    }
+
+    fn start(key: Key) -> io::Result<Self> {
+        let (sender, receiver) = sync_channel::<Task>(ENCRYPT_QUEUE_LEN);
+        let error_hook: Arc<Mutex<Option<ErrorHook>>> = Arc::new(Mutex::new(None));
+        let worker_hook = error_hook.clone();
+        let handle = thread::Builder::new()
+            .name("turnstiles-encrypt".to_string())
+            .spawn(move || {
+                for task in receiver {
+                    let path = match task {
+                        Task::Encrypt(path) => path,
+                        Task::Barrier(done) => {
+                            let _ = done.send(());
+                            continue;
+                        }
+                    };
+                    if let Err(e) = encrypt(&path, &key) {
+                        let context = format!("encrypting {}", path.display());
+                        let mut hook = worker_hook.lock().unwrap_or_else(|e| e.into_inner());
+                        match hook.as_mut() {
+                            Some(hook) => hook(&context, &e.into()),
+                            None => println!(
+                                "WARN: turnstiles caught error in {}.\nErr: {}",
+                                context, e
+                            ),
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            error_hook,
+        })
+    }
+
+    /// Send errors from the background thread here rather than printing them, as for `RotatingFile::with_error_hook`.
+    pub fn with_error_hook(self, hook: impl FnMut(&str, &anyhow::Error) + Send + 'static) -> Self {
+        *self.error_hook.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(hook));
+        self
+    }
+}
+
+fn encrypt(path: &Path, key: &Key) -> io::Result<()> {
+    let mut encrypted = path.as_os_str().to_owned();
+    encrypted.push(ENCRYPTED_SUFFIX);
+    let encrypted = PathBuf::from(encrypted);
+    let mut partial = encrypted.as_os_str().to_owned();
+    partial.push(".partial");
+
+    // The original may have been pruned while it waited
+    let mut original = File::open(path)?;
+    let result = write_encrypted(&mut original, &partial, key);
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result?;
+    fs::rename(&partial, &encrypted)?;
+    match fs::remove_file(path) {
+        // Pruned while being encrypted, so the encrypted copy shouldn't outlive it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::remove_file(&encrypted),
+        result => result,
+    }
+}
+
+fn write_encrypted(original: &mut File, partial: impl AsRef<Path>, key: &Key) -> io::Result<()> {
+    let out = File::create(partial)?;
+    // WASI has no permissions to copy, and says so rather than doing nothing
+    #[cfg(not(target_os = "wasi"))]
+    out.set_permissions(original.metadata()?.permissions())?;
+    #[cfg(all(unix, feature = "ownership"))]
+    crate::owner::copy_owner(original, &out)?;
+    let modified = original.metadata()?.modified()?;
+    let mut writer = key.encryptor()?.wrap_output(BufWriter::new(out))?;
+    io::copy(original, &mut writer)?;
+    let file = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+    file.set_modified(modified)?;
+    file.sync_all()
+}
+
+impl Roller for EncryptRoller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>> {
+        let sender = self.sender.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "turnstiles encryption thread has stopped",
+            )
+        })?;
+        match sender.try_send(Task::Encrypt(rotated.to_path_buf())) {
+            Ok(()) => Ok(Some(rotated.to_path_buf())),
+            Err(TrySendError::Full(_)) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "encryption queue is full, leaving the file as plaintext",
+            )),
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "turnstiles encryption thread has stopped",
+            )),
+        }
+    }
+
+    /// Waits for every file queued so far to be encrypted.
+    fn drain(&mut self) -> io::Result<()> {
+        let Some(sender) = self.sender.as_ref() else {
+            return Ok(());
+        };
+        let (done, wait) = channel();
+        if sender.send(Task::Barrier(done)).is_ok() {
+            let _ = wait.recv();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EncryptRoller {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish what's queued and stop
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Wrapping a filesystem to make chosen operations fail, see `FailingFileSystem`.
+use crate::{
+    filesystem::{lock, OpenOptionsHook},
+    DiskFileSystem, FileHandle, FileSystem, Metadata,
+};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// What a [`FailingFileSystem`] can be told to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileOperation {
+    /// Opening or creating a file
+    Open,
+    Rename,
+    Copy,
+    Remove,
+    ReadDir,
+    /// Metadata of a path or of an open file
+    Metadata,
+    /// Writing to an open file
+    Write,
+    /// Syncing an open file
+    Sync,
+}
+
+/// A failure waiting to happen.
+#[derive(Debug)]
+struct Fault {
+    operation: FileOperation,
+    /// Which call of the operation fails, counting from the first made through the filesystem, `None` for every call
+    call: Option<usize>,
+    kind: io::ErrorKind,
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    calls: HashMap<FileOperation, usize>,
+    faults: Vec<Fault>,
+}
+
+/// Wraps another [`FileSystem`], usually a [`MemoryFileSystem`](crate::MemoryFileSystem), failing whichever operations it's been
+/// told to, for testing what a `RotatingFile` does when something goes wrong part way through. Cloning gives another handle on the
+/// same script, so keep a clone to change it after handing the filesystem to [`RotatingFile::new_in`](crate::RotatingFile::new_in):
+///
+/// ```
+/// use std::io::{ErrorKind, Write};
+/// use turnstiles::{FailingFileSystem, FileOperation, MemoryFileSystem, PruneCondition, RotatingFile, RotationCondition};
+/// let fs = FailingFileSystem::new(MemoryFileSystem::new());
+/// let mut file = RotatingFile::new_in(fs.clone(), "/logs/app.log", RotationCondition::None, PruneCondition::None, false)?;
+/// fs.fail_nth(FileOperation::Rename, 1, ErrorKind::PermissionDenied);
+/// assert!(file.rotate().is_err());
+/// file.rotate()?;
+/// fs.fail_every(FileOperation::Write, ErrorKind::StorageFull);
+/// assert!(file.write_all(b"hello\n").is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Files opened through it fail writes, syncs and metadata the same way.
+#[derive(Debug, Clone, Default)]
+pub struct FailingFileSystem<FS> {
+    inner: FS,
+    script: Arc<Mutex<Script>>,
+}
+
+impl<FS> FailingFileSystem<FS> {
+    pub fn new(inner: FS) -> Self {
+        Self {
+            inner,
+            script: Arc::default(),
+        }
+    }
+
+    /// The wrapped filesystem, i.e. to read back what was written.
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    /// Fail the `nth` call of `operation` from now on, 1 being the next, with an error of this kind.
+    pub fn fail_nth(&self, operation: FileOperation, nth: usize, kind: io::ErrorKind) {
+        let mut script = lock(&self.script);
+        let done = script.calls.get(&operation).copied().unwrap_or(0);
+        script.faults.push(Fault {
+            operation,
+            call: Some(done + nth),
+            kind,
+        });
+    }
+
+    /// Fail every call of `operation` with an error of this kind until [`FailingFileSystem::clear`].
+    pub fn fail_every(&self, operation: FileOperation, kind: io::ErrorKind) {
+        lock(&self.script).faults.push(Fault {
+            operation,
+            call: None,
+            kind,
+        });
+    }
+
+    /// Forget every failure set up, so everything goes through to the wrapped filesystem again.
+    pub fn clear(&self) {
+        lock(&self.script).faults.clear();
+    }
+
+    /// How many times `operation` has been tried, failed or not.
+    pub fn calls(&self, operation: FileOperation) -> usize {
+        lock(&self.script)
+            .calls
+            .get(&operation)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Count a call of `operation`, failing it if the script says to.
+fn check(script: &Mutex<Script>, operation: FileOperation) -> io::Result<()> {
+    let mut script = lock(script);
+    let calls = script.calls.entry(operation).or_insert(0);
+    *calls += 1;
+    let call = *calls;
+    match script
+        .faults
+        .iter()
+        .find(|f| f.operation == operation && f.call.is_none_or(|c| c == call))
+    {
+        Some(fault) => Err(io::Error::new(
+            fault.kind,
+            format!("injected failure of {:?} call {}", operation, call),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// An open file in a [`FailingFileSystem`].
+#[derive(Debug)]
+pub struct FailingFile<F> {
+    inner: F,
+    script: Arc<Mutex<Script>>,
+}
+
+impl<F> FailingFile<F> {
+    fn check(&self, operation: FileOperation) -> io::Result<()> {
+        check(&self.script, operation)
+    }
+}
+
+impl<F: Write> Write for FailingFile<F> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.check(FileOperation::Write)?;
+        self.inner.write(bytes)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<F: FileHandle> FileHandle for FailingFile<F> {
+    fn metadata(&self) -> io::Result<Metadata> {
+        self.check(FileOperation::Metadata)?;
+        self.inner.metadata()
+    }
+    fn sync_all(&self) -> io::Result<()> {
+        self.check(FileOperation::Sync)?;
+        self.inner.sync_all()
+    }
+    fn sync_data(&self) -> io::Result<()> {
+        self.check(FileOperation::Sync)?;
+        self.inner.sync_data()
+    }
+    fn set_mode(&self, mode: u32) -> io::Result<()> {
+        self.inner.set_mode(mode)
+    }
+    fn set_modified(&self, time: SystemTime) -> io::Result<()> {
+        self.inner.set_modified(time)
+    }
+    #[cfg(all(unix, feature = "ownership"))]
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        self.inner.set_owner(uid, gid)
+    }
+}
+
+impl<FS: FileSystem> FailingFileSystem<FS> {
+    fn check(&self, operation: FileOperation) -> io::Result<()> {
+        check(&self.script, operation)
+    }
+
+    fn wrap(&self, inner: FS::File) -> FailingFile<FS::File> {
+        FailingFile {
+            inner,
+            script: self.script.clone(),
+        }
+    }
+}
+
+// Rollers and hooks still go straight to the disk, so the failures don't apply to them
+impl<FS: DiskFileSystem> DiskFileSystem for FailingFileSystem<FS> {}
+
+impl<FS: FileSystem> FileSystem for FailingFileSystem<FS> {
+    type File = FailingFile<FS::File>;
+    fn open_append(&self, path: &Path) -> io::Result<Self::File> {
+        self.check(FileOperation::Open)?;
+        self.inner.open_append(path).map(|f| self.wrap(f))
+    }
+    fn open_append_dsync(&self, path: &Path) -> io::Result<Option<Self::File>> {
+        self.check(FileOperation::Open)?;
+        Ok(self.inner.open_append_dsync(path)?.map(|f| self.wrap(f)))
+    }
+    fn open_append_customized(
+        &self,
+        path: &Path,
+        dsync: bool,
+        customize: &OpenOptionsHook,
+    ) -> io::Result<Option<Self::File>> {
+        self.check(FileOperation::Open)?;
+        Ok(self
+            .inner
+            .open_append_customized(path, dsync, customize)?
+            .map(|f| self.wrap(f)))
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check(FileOperation::Rename)?;
+        self.inner.rename(from, to)
+    }
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.check(FileOperation::Copy)?;
+        self.inner.copy(from, to)
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check(FileOperation::Remove)?;
+        self.inner.remove_file(path)
+    }
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        self.check(FileOperation::Open)?;
+        self.inner.create_new(path)
+    }
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        self.check(FileOperation::ReadDir)?;
+        self.inner.read_dir(path)
+    }
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.check(FileOperation::Metadata)?;
+        self.inner.metadata(path)
+    }
+    fn is_stream(&self, path: &Path) -> bool {
+        self.inner.is_stream(path)
+    }
+}
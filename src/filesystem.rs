@@ -0,0 +1,617 @@
+//! The filesystem operations a `RotatingFile` needs, so it can run on something other than `std::fs`, i.e. [`MemoryFileSystem`]
+//! in tests.
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+/// What a `RotatingFile` needs to know about a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub len: u64,
+    /// `None` if the filesystem doesn't record it
+    pub created: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+}
+
+impl Metadata {
+    /// When the file was created, or failing that when it was last modified, which is as good for a file we've just created and
+    /// otherwise errs on the side of rotating late. For filesystems which don't record creation times, i.e. on WASI.
+    pub(crate) fn created_or_modified(&self) -> Option<SystemTime> {
+        self.created.or(self.modified)
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            created: metadata.created().ok(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// An open file, as returned by [`FileSystem::open_append`].
+pub trait FileHandle: Write {
+    fn metadata(&self) -> io::Result<Metadata>;
+    fn sync_all(&self) -> io::Result<()>;
+    fn sync_data(&self) -> io::Result<()>;
+    /// Set the Unix permission bits, whatever the umask. Does nothing where files don't have them.
+    fn set_mode(&self, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+    /// Set the last modified time. Does nothing where files don't have one.
+    fn set_modified(&self, _time: SystemTime) -> io::Result<()> {
+        Ok(())
+    }
+    /// Change the owning user and group, leaving either alone if `None`. Does nothing where files don't have them.
+    #[cfg(all(unix, feature = "ownership"))]
+    fn set_owner(&self, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Filesystem operations used by a `RotatingFile`. Paths are always the file name joined onto the parent directory worked out from the path the
+/// `RotatingFile` was created with.
+pub trait FileSystem {
+    type File: FileHandle;
+    /// Open a file for appending, creating it if it doesn't exist.
+    fn open_append(&self, path: &Path) -> io::Result<Self::File>;
+    /// As `open_append` but with every write reaching the disk before it returns, i.e. `O_DSYNC`. `None` if the filesystem can't
+    /// do this, in which case `RotatingFile::with_sync_every_write` calls `sync_data` after each write instead.
+    fn open_append_dsync(&self, _path: &Path) -> io::Result<Option<Self::File>> {
+        Ok(None)
+    }
+    /// As `open_append`, or `open_append_dsync` if `dsync`, but giving `customize` the `std::fs::OpenOptions` to change before
+    /// opening, see `RotatingFile::with_open_options`. `None` if the filesystem doesn't open files with `OpenOptions`, in which case
+    /// the customization is ignored.
+    fn open_append_customized(
+        &self,
+        _path: &Path,
+        _dsync: bool,
+        _customize: &OpenOptionsHook,
+    ) -> io::Result<Option<Self::File>> {
+        Ok(None)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Copy a whole file, replacing `to` if it exists, returning the bytes copied. For maintenance which has to copy rather than
+    /// rename, i.e. copy-truncate, so it should use whatever the platform has to avoid pushing the data through userspace.
+    fn copy(&self, _from: &Path, _to: &Path) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "copying files is not supported by this filesystem",
+        ))
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Create an empty file, failing with `AlreadyExists` if there's one there already. For lock files, so it should be atomic
+    /// even over NFS, as `O_EXCL` is since NFSv3.
+    fn create_new(&self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "exclusive creation is not supported by this filesystem",
+        ))
+    }
+    /// Names of the files in a directory, which needn't be UTF-8.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>>;
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+    /// Whether `path` is something which can be written to but isn't a file, i.e. a FIFO or character device, which a
+    /// `RotatingFile` then writes to without rotating. `false` if it doesn't exist or the filesystem can't tell.
+    fn is_stream(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// A [`FileSystem`] whose paths are real ones `std::fs` can use. Rollers, rotation hooks and archiving are given the paths of
+/// rotated files and work on them directly, so a `RotatingFile` only has those options on one of these:
+///
+/// ```compile_fail
+/// use turnstiles::{DeleteRoller, MemoryFileSystem, PruneCondition, RotatingFile, RotationCondition};
+/// let fs = MemoryFileSystem::new();
+/// let file = RotatingFile::new_in(fs, "/logs/app.log", RotationCondition::None, PruneCondition::None, false)?
+///     .with_roller(DeleteRoller);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub trait DiskFileSystem: FileSystem {}
+
+/// Changes the `std::fs::OpenOptions` used to open the files a `RotatingFile` writes, see `RotatingFile::with_open_options`.
+pub type OpenOptionsHook = Arc<dyn Fn(&mut OpenOptions) + Send + Sync>;
+
+/// The real filesystem, via `std::fs`. The default for `RotatingFile`. On Linux new files are created as an anonymous `O_TMPFILE` and
+/// linked into place once open where the filesystem supports it, so none is ever seen half made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StdFileSystem;
+
+impl FileHandle for File {
+    fn metadata(&self) -> io::Result<Metadata> {
+        File::metadata(self).map(Metadata::from)
+    }
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+    fn set_modified(&self, time: SystemTime) -> io::Result<()> {
+        File::set_modified(self, time)
+    }
+    #[cfg(unix)]
+    fn set_mode(&self, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        self.set_permissions(fs::Permissions::from_mode(mode))
+    }
+    #[cfg(all(unix, feature = "ownership"))]
+    fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        std::os::unix::fs::fchown(self, uid, gid)
+    }
+}
+
+/// Open a file for appending with these extra flags, creating it if need be as an anonymous `O_TMPFILE` which is only linked into
+/// place once open, so a crash can't leave it half made. `None` if the filesystem can't do that, to create it by name instead.
+#[cfg(target_os = "linux")]
+fn open_append_linked(path: &Path, flags: libc::c_int) -> io::Result<Option<File>> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, fs::OpenOptionsExt, io::AsRawFd},
+    };
+    let existing = || {
+        OpenOptions::new()
+            .append(true)
+            .custom_flags(flags)
+            .open(path)
+    };
+    match existing() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        result => return result.map(Some),
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file = match OpenOptions::new()
+        .append(true)
+        .custom_flags(libc::O_TMPFILE | flags)
+        .open(parent)
+    {
+        Ok(file) => file,
+        // Not supported by the filesystem, or by the kernel before 3.11 where it reads as O_DIRECTORY
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EOPNOTSUPP | libc::EISDIR | libc::EINVAL)
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    };
+    // Through /proc rather than AT_EMPTY_PATH, which needs CAP_DAC_READ_SEARCH
+    let from = CString::new(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+    let to = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: both paths are nul terminated
+    let linked = unsafe {
+        libc::linkat(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::AT_SYMLINK_FOLLOW,
+        )
+    };
+    if linked == 0 {
+        return Ok(Some(file));
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // Made by something else in the meantime, so append to that as we would have
+        Some(libc::EEXIST) => existing().map(Some),
+        // No /proc to link through
+        Some(libc::ENOENT) => Ok(None),
+        _ => Err(e),
+    }
+}
+
+/// `OpenOptions` with `O_DSYNC` where there is one.
+fn dsync_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_DSYNC);
+    options
+}
+
+impl DiskFileSystem for StdFileSystem {}
+
+impl FileSystem for StdFileSystem {
+    type File = File;
+    fn open_append(&self, path: &Path) -> io::Result<File> {
+        #[cfg(target_os = "linux")]
+        if let Some(file) = open_append_linked(path, 0)? {
+            return Ok(file);
+        }
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+    #[cfg(unix)]
+    fn open_append_dsync(&self, path: &Path) -> io::Result<Option<File>> {
+        #[cfg(target_os = "linux")]
+        if let Some(file) = open_append_linked(path, libc::O_DSYNC)? {
+            return Ok(Some(file));
+        }
+        dsync_options().open(path).map(Some)
+    }
+    fn open_append_customized(
+        &self,
+        path: &Path,
+        dsync: bool,
+        customize: &OpenOptionsHook,
+    ) -> io::Result<Option<File>> {
+        let mut options = if dsync {
+            dsync_options()
+        } else {
+            OpenOptions::new()
+        };
+        options.create(true).append(true);
+        customize(&mut options);
+        options.open(path).map(Some)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+    /// `std::fs::copy`, which already takes the fast paths: `copy_file_range` on Linux (a reflink on filesystems which support
+    /// it), `fclonefileat`/`fcopyfile` on macOS and `CopyFileExW` on Windows, with a read/write loop elsewhere.
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        fs::copy(from, to)
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map(|_| ())
+    }
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let mut names = vec![];
+        for entry in fs::read_dir(path)? {
+            names.push(entry?.file_name());
+        }
+        Ok(names)
+    }
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Metadata::from)
+    }
+    fn is_stream(&self, path: &Path) -> bool {
+        fs::metadata(path).is_ok_and(|metadata| {
+            let file_type = metadata.file_type();
+            !file_type.is_file() && !file_type.is_dir()
+        })
+    }
+}
+
+/// How a `RotatingFile` opens the files it writes to.
+#[derive(Clone, Default)]
+pub(crate) struct OpenWith {
+    /// `O_DSYNC` if the filesystem supports it, see `RotatingFile::with_sync_every_write`
+    pub dsync: bool,
+    /// See `RotatingFile::with_mode`
+    pub mode: Option<u32>,
+    /// User and group, see `RotatingFile::with_owner`
+    #[cfg(all(unix, feature = "ownership"))]
+    pub owner: Option<(Option<u32>, Option<u32>)>,
+    /// See `RotatingFile::with_open_options`
+    pub customize: Option<OpenOptionsHook>,
+}
+
+impl OpenWith {
+    /// Set the permissions and owner on a file which has just been opened.
+    pub fn apply(&self, file: &impl FileHandle) -> io::Result<()> {
+        if let Some(mode) = self.mode {
+            file.set_mode(mode)?;
+        }
+        #[cfg(all(unix, feature = "ownership"))]
+        if let Some((uid, gid)) = self.owner {
+            file.set_owner(uid, gid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Open a file for appending as `with` says.
+pub(crate) fn open_append_with<FS: FileSystem>(
+    fs: &FS,
+    path: &Path,
+    with: OpenWith,
+) -> io::Result<FS::File> {
+    let opened = match &with.customize {
+        Some(customize) => fs.open_append_customized(path, with.dsync, customize)?,
+        None => None,
+    };
+    let dsync_file = match opened {
+        Some(file) => Some(file),
+        None if with.dsync => fs.open_append_dsync(path)?,
+        None => None,
+    };
+    let file = match dsync_file {
+        Some(file) => file,
+        None => fs.open_append(path)?,
+    };
+    with.apply(&file)?;
+    Ok(file)
+}
+
+#[derive(Debug)]
+struct MemoryFileData {
+    data: Vec<u8>,
+    created: SystemTime,
+    modified: SystemTime,
+}
+
+type SharedData = Arc<Mutex<MemoryFileData>>;
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Filesystem held entirely in memory, for tests and what a [`RotatingBuffer`](crate::RotatingBuffer) is built on. Cloning gives
+/// another handle on the same files, so keep a clone to look at what was written. There are no directories as such: a file is in whichever directory its path's parent is.
+///
+/// As on Unix an open file follows renames, and writing to a removed file succeeds but goes nowhere. Failures can be set up with
+/// [`MemoryFileSystem::set_capacity`] for a full disk and [`MemoryFileSystem::deny`] for permissions.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    files: Arc<Mutex<HashMap<PathBuf, SharedData>>>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+/// Failures a `MemoryFileSystem` has been told to simulate.
+#[derive(Debug, Default)]
+struct Faults {
+    capacity: Option<u64>,
+    denied: Vec<PathBuf>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Contents of a file, if it exists.
+    pub fn read(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        lock(&self.files)
+            .get(path.as_ref())
+            .map(|file| lock(file).data.clone())
+    }
+
+    /// Every file's path, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = lock(&self.files).keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Limit the total size of the files to this many bytes, `None` for no limit. Writes past it fail with `StorageFull`, as on a
+    /// full disk, once what fits has been written.
+    pub fn set_capacity(&self, bytes: Option<u64>) {
+        lock(&self.faults).capacity = bytes;
+    }
+
+    /// Fail anything done to this path, or anything under it if it's a directory, with `PermissionDenied`, apart from writes to
+    /// files already open.
+    pub fn deny(&self, path: impl AsRef<Path>) {
+        lock(&self.faults).denied.push(path.as_ref().to_path_buf());
+    }
+
+    /// Undo [`MemoryFileSystem::deny`] for a path.
+    pub fn allow(&self, path: impl AsRef<Path>) {
+        lock(&self.faults)
+            .denied
+            .retain(|denied| denied != path.as_ref());
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        )
+    }
+
+    fn check_allowed(&self, path: &Path) -> io::Result<()> {
+        if lock(&self.faults)
+            .denied
+            .iter()
+            .any(|denied| path.starts_with(denied))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} permission denied", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bytes left before the capacity is reached, if there is one.
+    fn space_left(&self) -> Option<u64> {
+        let capacity = lock(&self.faults).capacity?;
+        let used: u64 = lock(&self.files)
+            .values()
+            .map(|file| lock(file).data.len() as u64)
+            .sum();
+        Some(capacity.saturating_sub(used))
+    }
+}
+
+/// An open file in a [`MemoryFileSystem`].
+pub struct MemoryFile {
+    data: SharedData,
+    fs: MemoryFileSystem,
+}
+
+impl fmt::Debug for MemoryFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryFile")
+            .field("len", &lock(&self.data).data.len())
+            .finish()
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut bytes = bytes;
+        if let Some(left) = self.fs.space_left() {
+            if left == 0 && !bytes.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    "no space left on the memory filesystem",
+                ));
+            }
+            bytes = &bytes[..bytes.len().min(left as usize)];
+        }
+        let mut file = lock(&self.data);
+        file.data.extend_from_slice(bytes);
+        file.modified = SystemTime::now();
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileHandle for MemoryFile {
+    fn metadata(&self) -> io::Result<Metadata> {
+        let file = lock(&self.data);
+        Ok(Metadata {
+            len: file.data.len() as u64,
+            created: Some(file.created),
+            modified: Some(file.modified),
+        })
+    }
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_modified(&self, time: SystemTime) -> io::Result<()> {
+        lock(&self.data).modified = time;
+        Ok(())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    type File = MemoryFile;
+    fn open_append(&self, path: &Path) -> io::Result<MemoryFile> {
+        self.check_allowed(path)?;
+        let data = lock(&self.files)
+            .entry(path.to_path_buf())
+            .or_insert_with(|| {
+                let now = SystemTime::now();
+                Arc::new(Mutex::new(MemoryFileData {
+                    data: vec![],
+                    created: now,
+                    modified: now,
+                }))
+            })
+            .clone();
+        Ok(MemoryFile {
+            data,
+            fs: self.clone(),
+        })
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_allowed(from)?;
+        self.check_allowed(to)?;
+        let mut files = lock(&self.files);
+        let data = files.remove(from).ok_or_else(|| Self::not_found(from))?;
+        files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.check_allowed(from)?;
+        self.check_allowed(to)?;
+        let left = self.space_left();
+        let mut files = lock(&self.files);
+        let data = lock(files.get(from).ok_or_else(|| Self::not_found(from))?)
+            .data
+            .clone();
+        let len = data.len() as u64;
+        if left.is_some_and(|left| len > left) {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "no space left on the memory filesystem",
+            ));
+        }
+        let now = SystemTime::now();
+        // As with std an existing file is overwritten in place, so anything with it open sees the copy
+        match files.get(to) {
+            Some(existing) => {
+                let mut existing = lock(existing);
+                existing.data = data;
+                existing.modified = now;
+            }
+            None => {
+                files.insert(
+                    to.to_path_buf(),
+                    Arc::new(Mutex::new(MemoryFileData {
+                        data,
+                        created: now,
+                        modified: now,
+                    })),
+                );
+            }
+        }
+        Ok(len)
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check_allowed(path)?;
+        lock(&self.files)
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| Self::not_found(path))
+    }
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        self.check_allowed(path)?;
+        let mut files = lock(&self.files);
+        if files.contains_key(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        let now = SystemTime::now();
+        files.insert(
+            path.to_path_buf(),
+            Arc::new(Mutex::new(MemoryFileData {
+                data: vec![],
+                created: now,
+                modified: now,
+            })),
+        );
+        Ok(())
+    }
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        self.check_allowed(path)?;
+        Ok(lock(&self.files)
+            .keys()
+            .filter(|p| p.parent() == Some(path))
+            .filter_map(|p| Some(p.file_name()?.to_os_string()))
+            .collect())
+    }
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.check_allowed(path)?;
+        let files = lock(&self.files);
+        let file = lock(files.get(path).ok_or_else(|| Self::not_found(path))?);
+        Ok(Metadata {
+            len: file.data.len() as u64,
+            created: Some(file.created),
+            modified: Some(file.modified),
+        })
+    }
+}
@@ -0,0 +1,237 @@
+//! Stages applied to the bytes given to `write` before they reach the file. Each takes and returns a `Cow` so that the common
+//! case of nothing needing to change doesn't copy the buffer.
+use std::borrow::Cow;
+
+/// Caps the length of each line, carrying the length of a partial line over to the next write so lines split across
+/// several writes (i.e. from an async logger) are still caught.
+#[derive(Debug)]
+pub(crate) struct LineTruncator {
+    max: usize,
+    marker: Vec<u8>,
+    /// Bytes of the current line written so far
+    current_len: usize,
+    /// Whether the current line has already been truncated and marked
+    truncated: bool,
+}
+
+impl LineTruncator {
+    pub fn new(max: usize, marker: Vec<u8>) -> Self {
+        Self {
+            max,
+            marker,
+            current_len: 0,
+            truncated: false,
+        }
+    }
+
+    pub fn apply<'a>(&mut self, bytes: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        let mut out: Option<Vec<u8>> = None;
+        let mut pos = 0;
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            let has_newline = line.last() == Some(&b'\n');
+            let content = if has_newline {
+                &line[..line.len() - 1]
+            } else {
+                line
+            };
+            let allowed = self.max.saturating_sub(self.current_len);
+            if content.len() > allowed || (self.truncated && !content.is_empty()) {
+                // Over the limit, so switch to building our own buffer from everything up to this line
+                let out = out.get_or_insert_with(|| {
+                    let mut v = Vec::with_capacity(bytes.len());
+                    v.extend_from_slice(&bytes[..pos]);
+                    v
+                });
+                out.extend_from_slice(&content[..allowed.min(content.len())]);
+                if !self.truncated {
+                    out.extend_from_slice(&self.marker);
+                    self.truncated = true;
+                }
+                if has_newline {
+                    out.push(b'\n');
+                }
+            } else if let Some(out) = out.as_mut() {
+                out.extend_from_slice(line);
+            }
+            self.current_len += content.len();
+            if has_newline {
+                self.current_len = 0;
+                self.truncated = false;
+            }
+            pos += line.len();
+        }
+        match out {
+            Some(out) => Cow::Owned(out),
+            None => bytes,
+        }
+    }
+}
+
+/// How to neutralise control characters, see `RotatingFile::with_sanitize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeMode {
+    /// Remove control characters, and ANSI escape sequences in their entirety.
+    Strip,
+    /// Replace each control character with a visible `\xNN` escape, which leaves any escape sequence harmless but still readable.
+    Escape,
+}
+
+fn is_control(b: u8) -> bool {
+    (b < 0x20 && b != b'\n' && b != b'\t') || b == 0x7f
+}
+
+/// Whether bytes at the start of `bytes` are a UTF-8 encoded C1 control character (U+0080 to U+009F).
+fn is_c1_control(bytes: &[u8]) -> bool {
+    bytes.len() > 1 && bytes[0] == 0xc2 && (0x80..0xa0).contains(&bytes[1])
+}
+
+/// Length of the ANSI escape sequence starting at `bytes[0] == ESC`, which for anything we don't recognise is just the ESC itself.
+fn escape_sequence_len(bytes: &[u8]) -> usize {
+    match bytes.get(1) {
+        // CSI: parameters then a final byte in 0x40..=0x7e
+        Some(b'[') => bytes[2..]
+            .iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+            .map_or(bytes.len(), |i| i + 3),
+        // OSC: terminated by BEL or ESC \
+        Some(b']') => {
+            let mut i = 2;
+            while i < bytes.len() {
+                if bytes[i] == 0x07 {
+                    return i + 1;
+                }
+                if bytes[i] == 0x1b && bytes.get(i + 1) == Some(&b'\\') {
+                    return i + 2;
+                }
+                i += 1;
+            }
+            bytes.len()
+        }
+        // Two byte sequences such as ESC c
+        Some(b) if (0x40..=0x7e).contains(b) => 2,
+        _ => 1,
+    }
+}
+
+pub(crate) fn sanitize(mode: SanitizeMode, bytes: Cow<'_, [u8]>) -> Cow<'_, [u8]> {
+    let first = match (0..bytes.len()).find(|&i| is_control(bytes[i]) || is_c1_control(&bytes[i..]))
+    {
+        None => return bytes,
+        Some(i) => i,
+    };
+    let mut out = Vec::with_capacity(bytes.len() + 16);
+    out.extend_from_slice(&bytes[..first]);
+    let mut i = first;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let len = if b == 0x1b && mode == SanitizeMode::Strip {
+            escape_sequence_len(&bytes[i..])
+        } else if is_control(b) {
+            1
+        } else if is_c1_control(&bytes[i..]) {
+            2
+        } else {
+            out.push(b);
+            i += 1;
+            continue;
+        };
+        if mode == SanitizeMode::Escape {
+            for c in &bytes[i..i + len] {
+                out.extend_from_slice(format!("\\x{:02x}", c).as_bytes());
+            }
+        }
+        i += len;
+    }
+    Cow::Owned(out)
+}
+
+/// Collapses runs of identical lines into the first one followed by a `last message repeated N times` line, syslog style.
+/// Partial lines are held back until their newline arrives so they can be compared.
+#[derive(Debug, Default)]
+pub(crate) struct Deduplicator {
+    last_line: Vec<u8>,
+    repeats: u64,
+    partial: Vec<u8>,
+}
+
+impl Deduplicator {
+    fn summary(&mut self, out: &mut Vec<u8>) {
+        if self.repeats > 0 {
+            out.extend_from_slice(
+                format!("last message repeated {} times\n", self.repeats).as_bytes(),
+            );
+            self.repeats = 0;
+        }
+    }
+
+    pub fn apply<'a>(&mut self, bytes: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        // Only borrow the input if it's all complete, unrepeated lines and nothing is pending from before
+        let mut out: Option<Vec<u8>> = if self.partial.is_empty() && self.repeats == 0 {
+            None
+        } else {
+            Some(Vec::with_capacity(bytes.len()))
+        };
+        let mut pos = 0;
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            if line.last() != Some(&b'\n') {
+                // Trailing partial line, keep it for next time
+                out.get_or_insert_with(|| bytes[..pos].to_vec());
+                self.partial.extend_from_slice(line);
+                break;
+            }
+            let raw_len = line.len();
+            let line = if self.partial.is_empty() {
+                Cow::Borrowed(line)
+            } else {
+                let mut full = std::mem::take(&mut self.partial);
+                full.extend_from_slice(line);
+                Cow::Owned(full)
+            };
+            if *line == self.last_line[..] {
+                out.get_or_insert_with(|| bytes[..pos].to_vec());
+                self.repeats += 1;
+            } else {
+                if let Some(out) = out.as_mut() {
+                    self.summary(out);
+                    out.extend_from_slice(&line);
+                }
+                self.last_line.clear();
+                self.last_line.extend_from_slice(&line);
+            }
+            pos += raw_len;
+        }
+        match out {
+            Some(out) => Cow::Owned(out),
+            None => bytes,
+        }
+    }
+
+    /// Anything held back, i.e. on flush: the repeat count so far and any partial line.
+    pub fn pending(&mut self) -> Vec<u8> {
+        let mut out = vec![];
+        self.summary(&mut out);
+        out.append(&mut self.partial);
+        out
+    }
+}
+
+/// Rewrites each buffer before it's written, i.e. to redact tokens or PII. Runs before any of the other filters. Return `Cow::Borrowed`
+/// when there's nothing to change to avoid a copy. For a closure use `RotatingFile::with_transform_fn` instead.
+///
+/// Note buffers are whatever the caller passed to `write`, which for some loggers may be a partial line.
+pub trait Transformer {
+    fn transform<'a>(&mut self, bytes: &'a [u8]) -> Cow<'a, [u8]>;
+}
+
+/// Lets a closure be used as a `Transformer`. This isn't a blanket impl as closures only get the right lifetimes inferred when
+/// passed straight to something with an `FnMut` bound.
+pub(crate) struct FnTransformer<F>(pub F);
+
+impl<F> Transformer for FnTransformer<F>
+where
+    F: for<'a> FnMut(&'a [u8]) -> Cow<'a, [u8]>,
+{
+    fn transform<'a>(&mut self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        (self.0)(bytes)
+    }
+}
@@ -0,0 +1,154 @@
+//! Group commit for `SharedRotatingFile::with_group_commit`: writers hand over a ticket and wait, while a worker thread syncs
+//! everything written so far in one go, so many concurrent writes share each `sync_data`.
+use crate::{FileIndexInt, RotatingFile};
+use std::{
+    fmt,
+    fs::File,
+    io,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+};
+
+#[derive(Default)]
+struct State {
+    /// Ticket of the latest write
+    written: u64,
+    /// Every write up to this ticket has been synced, or failed to be if `error` is set
+    synced: u64,
+    /// Handle on the active file, with the index it was cloned at so a rotation can be spotted
+    current: Option<(FileIndexInt, Arc<File>)>,
+    /// Handles on files rotated since the last sync, which may still have unsynced writes
+    retired: Vec<Arc<File>>,
+    /// Set once a sync fails, after which every write fails too
+    error: Option<(io::ErrorKind, String)>,
+    stop: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when there's something to sync
+    work: Condvar,
+    /// Signalled when a sync has finished
+    done: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub(crate) struct GroupCommit {
+    shared: Arc<Shared>,
+    /// In a `Mutex` to keep `SharedRotatingFile` `RefUnwindSafe`, which slog needs
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl fmt::Debug for GroupCommit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupCommit").finish_non_exhaustive()
+    }
+}
+
+impl GroupCommit {
+    pub fn new() -> io::Result<Self> {
+        let shared = Arc::new(Shared::default());
+        let worker_shared = shared.clone();
+        let handle = thread::Builder::new()
+            .name("turnstiles-commit".to_string())
+            .spawn(move || worker(&worker_shared))?;
+        Ok(Self {
+            shared,
+            handle: Mutex::new(Some(handle)),
+        })
+    }
+
+    /// Note a write which has just been made to `file`, with the lock on it still held, returning the ticket to wait on.
+    pub fn enqueue(&self, file: &mut RotatingFile) -> io::Result<u64> {
+        // The sync can only cover what's reached the file
+        file.flush_buffer()?;
+        let mut state = self.shared.lock();
+        if let Some((kind, message)) = &state.error {
+            return Err(io::Error::new(*kind, message.clone()));
+        }
+        if state.current.as_ref().map(|(index, _)| *index) != Some(file.index()) {
+            let handle = Arc::new(file.current_file.try_clone()?);
+            if let Some((_, old)) = state.current.replace((file.index(), handle)) {
+                state.retired.push(old);
+            }
+        }
+        state.written += 1;
+        let ticket = state.written;
+        self.shared.work.notify_one();
+        Ok(ticket)
+    }
+
+    /// Block until the write with this ticket has been synced, without the lock on the file held.
+    pub fn wait(&self, ticket: u64) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        while state.synced < ticket && state.error.is_none() {
+            state = self
+                .shared
+                .done
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        match &state.error {
+            Some((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Forget the handle on the active file, i.e. after it's been reopened, so it's cloned again on the next write.
+    #[cfg(feature = "reopen")]
+    pub fn reset(&self) {
+        let mut state = self.shared.lock();
+        if let Some((_, old)) = state.current.take() {
+            state.retired.push(old);
+        }
+    }
+}
+
+fn worker(shared: &Shared) {
+    loop {
+        let mut state = shared.lock();
+        while !state.stop && state.written == state.synced {
+            state = shared.work.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if state.written == state.synced {
+            // Stopped with nothing left to do
+            return;
+        }
+        let target = state.written;
+        let mut files = std::mem::take(&mut state.retired);
+        files.extend(state.current.as_ref().map(|(_, file)| file.clone()));
+        drop(state);
+
+        // Writers carry on appending while this runs, and are picked up by the next sync
+        let result = files.iter().try_for_each(|file| file.sync_data());
+
+        let mut state = shared.lock();
+        if let Err(e) = result {
+            state.error = Some((e.kind(), format!("group commit sync failed: {}", e)));
+        }
+        state.synced = target;
+        shared.done.notify_all();
+    }
+}
+
+impl Drop for GroupCommit {
+    /// Syncs anything outstanding before stopping the worker.
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.work.notify_one();
+        let handle = self
+            .handle
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
+    }
+}
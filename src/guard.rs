@@ -0,0 +1,35 @@
+use std::fmt;
+
+/// Flushes a shared `RotatingFile` and waits for its background work when dropped, as `appender::WorkerGuard` does for a
+/// `NonBlocking` writer. For files which are never dropped themselves, i.e. ones given to a global logger, where anything still
+/// buffered or queued would otherwise be lost at exit. Bind it in `main` so it's dropped at the end. See
+/// [`SharedRotatingFile::flush_guard`](crate::SharedRotatingFile::flush_guard) and
+/// [`ShardedRotatingFile::flush_guard`](crate::ShardedRotatingFile::flush_guard).
+///
+/// Errors are reported as for anything else caught internally, to the error hook or printed.
+#[must_use = "the flush happens when the guard is dropped, so dropping it straight away flushes now rather than at exit"]
+pub struct FlushGuard {
+    flush: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl fmt::Debug for FlushGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlushGuard").finish_non_exhaustive()
+    }
+}
+
+impl FlushGuard {
+    pub(crate) fn new(flush: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            flush: Some(Box::new(flush)),
+        }
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if let Some(flush) = self.flush.take() {
+            flush();
+        }
+    }
+}
@@ -0,0 +1,498 @@
+//! Looking at a set of log files from outside a running `RotatingFile`, i.e. for tooling. This is what the `turnstiles` binary
+//! (`cli` feature) is built on. Files are found, ordered and pruned with the same logic the library uses, so the two never disagree.
+//!
+//! Each function takes the same path a `RotatingFile` would be created with, i.e. `/var/log/app.log` for `/var/log/app.log.ACTIVE`
+//! and friends.
+use crate::{
+    active_filename, is_archive, name_suffix, rotated_file_regex, utils::filename_to_details,
+    with_suffix, FileIndexInt, PruneCondition, RotatingFile, RotationCondition, StdFileSystem,
+    CHECKSUM_SUFFIX,
+};
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt, fs,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A file in the set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// `None` for the active file
+    pub index: Option<FileIndexInt>,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Something wrong with a set of files, from [`verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// No rotated files between these two indices, i.e. some were deleted by hand. Files missing from the start are expected
+    /// with pruning so aren't reported.
+    MissingIndices {
+        after: FileIndexInt,
+        before: FileIndexInt,
+    },
+    Unreadable {
+        path: PathBuf,
+        error: String,
+    },
+    /// A file named by a checksum sidecar or by the next file's hash chained banner is gone, in any form.
+    Missing {
+        path: PathBuf,
+    },
+    /// A file's banner and footer, or a footer and the banner of the file after it, disagree on the epoch id. Only checked where
+    /// the files have them, see `RotatingFile::with_banner` and `RotatingFile::with_footer`.
+    EpochMismatch {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+    /// A file's contents don't match the hash in the next file's banner, see `RotatingFile::with_hash_chain`.
+    ChainBroken {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+    /// A file's contents don't match the checksum in its sidecar, see `ChecksumHook`.
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MissingIndices { after, before } => {
+                write!(f, "missing files between index {} and {}", after, before)
+            }
+            Problem::Unreadable { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            Problem::Missing { path } => {
+                write!(f, "{} has a checksum but is missing", path.display())
+            }
+            Problem::EpochMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has epoch {} but {} was expected",
+                path.display(),
+                found,
+                expected
+            ),
+            Problem::ChainBroken {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has SHA-256 {} but the next file's banner has {}",
+                path.display(),
+                found,
+                expected
+            ),
+            Problem::ChecksumMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has SHA-256 {} but {} was expected",
+                path.display(),
+                found,
+                expected
+            ),
+        }
+    }
+}
+
+/// How a file compared with its checksum sidecar, see `ChecksumHook`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    Match,
+    Mismatch {
+        expected: String,
+        found: String,
+    },
+    /// No sidecar, i.e. the active file or one rotated without checksums turned on
+    NoSidecar,
+    /// Has a sidecar but couldn't be checked, because it's been compressed or encrypted since it was hashed, it couldn't be read,
+    /// or the `checksum` feature is off
+    Unchecked,
+}
+
+/// A file of the set as found by [`verify_integrity`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    pub file: LogFile,
+    pub checksum: ChecksumStatus,
+}
+
+/// What [`verify_integrity`] found, every file of the set along with anything wrong with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub files: Vec<FileReport>,
+    pub problems: Vec<Problem>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// The files of a set, rotated files oldest first followed by the active file if there is one. Tarballs of rotated files made by
+/// `RotatingFile::with_archive` aren't included.
+pub fn list(path: impl AsRef<Path>) -> Result<Vec<LogFile>> {
+    let (filename_root, parent) = filename_to_details(path.as_ref())?;
+    let file_regex = rotated_file_regex(&filename_root)?;
+    let mut files = vec![];
+    for name in <RotatingFile>::list_rotated_log_files(&StdFileSystem, &file_regex, &parent)? {
+        if is_archive(&name) {
+            continue;
+        }
+        let index = <RotatingFile>::rotated_file_index(&name)?;
+        files.push(log_file(parent.join(name), Some(index))?);
+    }
+    files.sort_by_key(|file| file.index);
+    let active = parent.join(active_filename(&filename_root));
+    if active.exists() {
+        files.push(log_file(active, None)?);
+    }
+    Ok(files)
+}
+
+fn log_file(path: impl Into<PathBuf>, index: Option<FileIndexInt>) -> Result<LogFile> {
+    let path = path.into();
+    let metadata = fs::metadata(&path)?;
+    Ok(LogFile {
+        path,
+        index,
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+/// Write the contents of the whole set to `out` in order. Compressed files (see `CompressRoller`) are written as they are.
+pub fn cat(path: impl AsRef<Path>, out: &mut impl Write) -> Result<()> {
+    for file in list(path)? {
+        io::copy(&mut fs::File::open(&file.path)?, out)?;
+    }
+    Ok(())
+}
+
+/// Apply a prune condition now, as a `RotatingFile` would after a rotation, returning the files removed.
+pub fn prune(path: impl AsRef<Path>, prune_method: &PruneCondition) -> Result<Vec<PathBuf>> {
+    <RotatingFile>::check_options(&RotationCondition::None, prune_method)?;
+    let (filename_root, parent) = filename_to_details(path.as_ref())?;
+    let file_regex = rotated_file_regex(&filename_root)?;
+    let index = <RotatingFile>::detect_latest_file_index(&StdFileSystem, &file_regex, &parent)?;
+    let mut removed = vec![];
+    for path in <RotatingFile>::files_to_prune(
+        &StdFileSystem,
+        &file_regex,
+        &parent,
+        &filename_root,
+        index,
+        prune_method,
+    )? {
+        <RotatingFile>::remove_rotated_file(&StdFileSystem, &path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Check a set for gaps in the indices, unreadable files and broken banner/footer epoch chains, and with the `checksum` feature
+/// rotated files against their checksum sidecars. Just the problems from [`verify_integrity`].
+pub fn verify(path: impl AsRef<Path>) -> Result<Vec<Problem>> {
+    Ok(verify_integrity(path)?.problems)
+}
+
+/// Check every file of a set: that none are missing, going by the indices, checksum sidecars and hash chained banners, that each
+/// can be read, that banner/footer epoch chains are unbroken and, with the `checksum` feature, that each rotated file with a sidecar
+/// still matches it and each file matches the hash in the next one's banner.
+/// Sidecars don't record the size separately, a changed size being caught by the hash.
+pub fn verify_integrity(path: impl AsRef<Path>) -> Result<IntegrityReport> {
+    let (filename_root, parent) = filename_to_details(path.as_ref())?;
+    let files = list(path)?;
+    let mut problems = vec![];
+    for pair in files.windows(2) {
+        if let (Some(a), Some(b)) = (pair[0].index, pair[1].index) {
+            // The same index twice is a file part way through compression
+            if b > a + 1 {
+                problems.push(Problem::MissingIndices {
+                    after: a,
+                    before: b,
+                });
+            }
+        }
+    }
+
+    // Epoch the next file should start with, from the previous file's footer
+    let mut expected_next: Option<String> = None;
+    let mut previous_index: Option<FileIndexInt> = None;
+    for (i, file) in files.iter().enumerate() {
+        let follows_on = match (previous_index, file.index) {
+            (Some(a), Some(b)) => b == a + 1 || b == a,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        if !follows_on {
+            expected_next = None;
+        }
+        previous_index = file.index;
+        let (first, last) = match first_and_last_lines(&file.path) {
+            Ok(lines) => lines,
+            Err(e) => {
+                problems.push(Problem::Unreadable {
+                    path: file.path.clone(),
+                    error: e.to_string(),
+                });
+                expected_next = None;
+                continue;
+            }
+        };
+        if let Some(banner) = first.as_deref() {
+            check_chain(&parent, &files[..i], file, banner, &mut problems);
+        }
+        let banner_epoch = first.as_deref().and_then(banner_epoch);
+        let footer = last.as_deref().and_then(footer_epochs);
+        if let (Some(expected), Some(found)) = (expected_next.take(), banner_epoch.clone()) {
+            if expected != found {
+                problems.push(Problem::EpochMismatch {
+                    path: file.path.clone(),
+                    expected,
+                    found,
+                });
+            }
+        }
+        if let Some((epoch, next)) = footer {
+            if let Some(banner_epoch) = banner_epoch {
+                if banner_epoch != epoch {
+                    problems.push(Problem::EpochMismatch {
+                        path: file.path.clone(),
+                        expected: banner_epoch,
+                        found: epoch,
+                    });
+                }
+            }
+            expected_next = Some(next);
+        }
+    }
+
+    let mut sidecars = read_sidecars(&parent, &filename_root, &mut problems)?;
+    let mut reports = vec![];
+    for file in files {
+        let sidecar = file.index.and_then(|index| sidecars.get(&index));
+        let checksum = match sidecar {
+            None => ChecksumStatus::NoSidecar,
+            Some((expected, name)) if file.path.file_name() == Some(name.as_os_str()) => {
+                check_checksum(&file.path, expected, &mut problems)
+            }
+            // Compressed or encrypted since it was hashed, so there's nothing to compare against
+            Some(_) => ChecksumStatus::Unchecked,
+        };
+        reports.push(FileReport { file, checksum });
+    }
+    for report in &reports {
+        if let Some(index) = report.file.index {
+            sidecars.remove(&index);
+        }
+    }
+    for (_, name) in sidecars.into_values() {
+        problems.push(Problem::Missing {
+            path: parent.join(name),
+        });
+    }
+    Ok(IntegrityReport {
+        files: reports,
+        problems,
+    })
+}
+
+/// Checksum sidecars in `parent` by the index of their file, giving the expected hash and the name of the file that was hashed.
+/// Sidecars which can't be read are added to `problems`.
+fn read_sidecars(
+    parent: &Path,
+    filename_root: &OsStr,
+    problems: &mut Vec<Problem>,
+) -> io::Result<BTreeMap<FileIndexInt, (String, PathBuf)>> {
+    let mut sidecars = BTreeMap::new();
+    for entry in fs::read_dir(parent)? {
+        let name = entry?.file_name();
+        let index = name_suffix(filename_root, name.as_encoded_bytes())
+            .and_then(|rest| rest.strip_suffix(CHECKSUM_SUFFIX))
+            .and_then(|index| index.parse::<FileIndexInt>().ok());
+        let Some(index) = index else {
+            continue;
+        };
+        let path = parent.join(name);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                problems.push(Problem::Unreadable {
+                    path,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        // `sha256sum` format, only ever naming a file of the set in the same directory. The name is left as bytes as it needn't be
+        // UTF-8, so it's rebuilt from the part after the root.
+        let contents = contents.trim_ascii_end();
+        let parsed = contents
+            .windows(2)
+            .position(|pair| pair == b"  ")
+            .and_then(|i| {
+                let hash = std::str::from_utf8(&contents[..i]).ok()?;
+                let hashed = contents[i + 2..].rsplit(|&byte| byte == b'/').next()?;
+                let suffix = name_suffix(filename_root, hashed)?;
+                let hashed = with_suffix(filename_root, &format!(".{}", suffix));
+                Some((hash.to_string(), PathBuf::from(hashed)))
+            });
+        match parsed {
+            Some(parsed) => {
+                sidecars.insert(index, parsed);
+            }
+            None => problems.push(Problem::Unreadable {
+                path,
+                error: "not in sha256sum format".to_string(),
+            }),
+        }
+    }
+    Ok(sidecars)
+}
+
+#[cfg(feature = "checksum")]
+fn check_checksum(path: &Path, expected: &str, problems: &mut Vec<Problem>) -> ChecksumStatus {
+    match crate::checksum::sha256_file(path) {
+        Ok(found) if found == expected => ChecksumStatus::Match,
+        Ok(found) => {
+            problems.push(Problem::ChecksumMismatch {
+                path: path.to_path_buf(),
+                expected: expected.to_string(),
+                found: found.clone(),
+            });
+            ChecksumStatus::Mismatch {
+                expected: expected.to_string(),
+                found,
+            }
+        }
+        Err(e) => {
+            problems.push(Problem::Unreadable {
+                path: path.to_path_buf(),
+                error: e.to_string(),
+            });
+            ChecksumStatus::Unchecked
+        }
+    }
+}
+
+#[cfg(not(feature = "checksum"))]
+fn check_checksum(_path: &Path, _expected: &str, _problems: &mut Vec<Problem>) -> ChecksumStatus {
+    ChecksumStatus::Unchecked
+}
+
+/// Check a file against the hash of the one before it in its banner, see `RotatingFile::with_hash_chain`. `earlier` is the files
+/// before it in the set.
+fn check_chain(
+    parent: &Path,
+    earlier: &[LogFile],
+    file: &LogFile,
+    banner: &str,
+    problems: &mut Vec<Problem>,
+) {
+    let Some((previous, expected)) = banner_previous(banner) else {
+        return;
+    };
+    let Ok(previous_index) = <RotatingFile>::rotated_file_index(OsStr::new(&previous)) else {
+        return;
+    };
+    let path = parent.join(&previous);
+    match earlier.iter().find(|f| f.index == Some(previous_index)) {
+        Some(previous) if previous.path == path =>
+        {
+            #[cfg(feature = "checksum")]
+            match crate::checksum::sha256_file(&path) {
+                Ok(found) if found != expected => problems.push(Problem::ChainBroken {
+                    path,
+                    expected,
+                    found,
+                }),
+                Ok(_) => {}
+                Err(e) => problems.push(Problem::Unreadable {
+                    path,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        // Compressed or encrypted since, so there's nothing to compare against
+        Some(_) => {}
+        // A gap between rotated files is already reported, but one after the newest wouldn't be. With nothing before it at all
+        // the previous file may well have been pruned.
+        None if file.index.is_none() && !earlier.is_empty() => {
+            problems.push(Problem::Missing { path })
+        }
+        None => {}
+    }
+    #[cfg(not(feature = "checksum"))]
+    let _ = expected;
+}
+
+/// Name and SHA-256 of the previous file from a hash chained banner, `# turnstiles ... | previous <name> | previous sha256 <hash>`.
+fn banner_previous(line: &str) -> Option<(String, String)> {
+    let mut parts = line.strip_prefix("# turnstiles ")?.split(" | ");
+    let previous = parts.find_map(|part| part.strip_prefix("previous "))?;
+    let sha256 = parts.next()?.strip_prefix("previous sha256 ")?;
+    Some((previous.to_string(), sha256.to_string()))
+}
+
+/// Longest line we look for at the end of a file, comfortably more than a footer.
+const TAIL_LEN: u64 = 512;
+
+fn first_and_last_lines(path: &PathBuf) -> io::Result<(Option<String>, Option<String>)> {
+    let mut file = fs::File::open(path)?;
+    let mut first = String::new();
+    BufReader::new(&mut file)
+        .take(TAIL_LEN)
+        .read_line(&mut first)?;
+
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+    let mut tail = vec![];
+    file.read_to_end(&mut tail)?;
+    let tail = String::from_utf8_lossy(&tail);
+    let last = tail
+        .trim_end_matches('\n')
+        .rsplit('\n')
+        .next()
+        .unwrap_or("");
+
+    let non_empty = |s: &str| (!s.is_empty()).then(|| s.trim_end().to_string());
+    Ok((non_empty(&first), non_empty(last)))
+}
+
+/// Epoch from a banner line, `# turnstiles <version> | epoch <id> | ...`.
+fn banner_epoch(line: &str) -> Option<String> {
+    let mut parts = line.strip_prefix("# turnstiles ")?.split(" | ");
+    parts.next()?;
+    parts.next()?.strip_prefix("epoch ").map(str::to_string)
+}
+
+/// Epoch and next epoch from a footer line, `# turnstiles epoch <id> | ended <time> | next epoch <id>`.
+fn footer_epochs(line: &str) -> Option<(String, String)> {
+    let mut parts = line.strip_prefix("# turnstiles epoch ")?.split(" | ");
+    let epoch = parts.next()?.to_string();
+    parts.next()?;
+    let next = parts.next()?.strip_prefix("next epoch ")?.to_string();
+    Some((epoch, next))
+}
@@ -0,0 +1,43 @@
+//! Minimal client for journald's native protocol, only used to mirror errors which turnstiles catches, so they're visible even
+//! when the log files themselves can't be written.
+use std::{io, os::unix::net::UnixDatagram};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// syslog warning level, as the errors are ones we've carried on from
+const PRIORITY_WARNING: &str = "4";
+
+#[derive(Debug)]
+pub(crate) struct Journald {
+    socket: UnixDatagram,
+}
+
+impl Journald {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+        })
+    }
+
+    /// Send a warning, tagged with the active file's path. Fields which may contain newlines use the length-prefixed form.
+    pub fn send(&self, message: &str, active_file: &str) -> io::Result<()> {
+        let mut buf = vec![];
+        for (key, value) in [
+            ("MESSAGE", message),
+            ("PRIORITY", PRIORITY_WARNING),
+            ("SYSLOG_IDENTIFIER", "turnstiles"),
+            ("TURNSTILES_FILE", active_file),
+        ] {
+            buf.extend_from_slice(key.as_bytes());
+            if value.contains('\n') {
+                buf.push(b'\n');
+                buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                buf.push(b'=');
+            }
+            buf.extend_from_slice(value.as_bytes());
+            buf.push(b'\n');
+        }
+        self.socket.send_to(&buf, JOURNALD_SOCKET)?;
+        Ok(())
+    }
+}
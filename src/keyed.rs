@@ -0,0 +1,169 @@
+use crate::{PruneCondition, RotatingFile, RotationCondition};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+type Setup = Box<dyn Fn(RotatingFile) -> Result<RotatingFile> + Send>;
+
+/// A `RotatingFile` per key, i.e. per tenant, at `<dir>/<key>/<filename>`, created the first time the key is written to. All of them
+/// share the same rotation and prune conditions.
+///
+/// At most `max_open` files are kept open; beyond that the least recently used is flushed and closed, and reopened when next written
+/// to, picking up where it left off (see [`RotatingFile::reopen`] on how the index is found).
+pub struct KeyedRotatingFiles {
+    dir: PathBuf,
+    filename: String,
+    rotation: RotationCondition,
+    prune: PruneCondition,
+    require_newline: bool,
+    max_open: usize,
+    setup: Option<Setup>,
+    /// Open files along with when they were last used
+    open: HashMap<String, (RotatingFile, u64)>,
+    tick: u64,
+}
+
+impl fmt::Debug for KeyedRotatingFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyedRotatingFiles")
+            .field("dir", &self.dir)
+            .field("filename", &self.filename)
+            .field("rotation", &self.rotation)
+            .field("prune", &self.prune)
+            .field("max_open", &self.max_open)
+            .field("open", &self.open.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyedRotatingFiles {
+    /// Defaults to keeping 64 files open.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        filename: &str,
+        rotation_method: RotationCondition,
+        prune_method: PruneCondition,
+        require_newline: bool,
+    ) -> Result<Self> {
+        <RotatingFile>::check_options(&rotation_method, &prune_method)?;
+        Ok(Self {
+            dir: dir.into(),
+            filename: filename.to_string(),
+            rotation: rotation_method,
+            prune: prune_method,
+            require_newline,
+            max_open: 64,
+            setup: None,
+            open: HashMap::new(),
+            tick: 0,
+        })
+    }
+
+    /// Cap the number of files open at once, which must be at least one.
+    pub fn with_max_open(mut self, max_open: usize) -> Result<Self> {
+        if max_open == 0 {
+            bail!("Invalid option: max_open must be at least 1");
+        }
+        self.max_open = max_open;
+        Ok(self)
+    }
+
+    /// Applied to each file as it's opened, i.e. `|file| Ok(file.with_deduplication())`, for options beyond the conditions.
+    pub fn with_setup(
+        mut self,
+        setup: impl Fn(RotatingFile) -> Result<RotatingFile> + Send + 'static,
+    ) -> Self {
+        self.setup = Some(Box::new(setup));
+        self
+    }
+
+    /// Keys become directory names so must be a single normal path component, i.e. no `/` or `..`.
+    fn check_key(key: &str) -> Result<()> {
+        let mut components = Path::new(key).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(()),
+            _ => bail!("Invalid key '{}', must be usable as a directory name", key),
+        }
+    }
+
+    fn open_file(&self, key: &str) -> Result<RotatingFile> {
+        Self::check_key(key)?;
+        let dir = self.dir.join(key);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Could not create log directory {}", dir.display()))?;
+        let file = RotatingFile::new(
+            dir.join(&self.filename),
+            self.rotation.clone(),
+            self.prune.clone(),
+            self.require_newline,
+        )?;
+        match self.setup.as_ref() {
+            Some(setup) => setup(file),
+            None => Ok(file),
+        }
+    }
+
+    /// Close the least recently used file to make room for another.
+    fn evict(&mut self) {
+        let oldest = self
+            .open
+            .iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(key, _)| key.clone());
+        if let Some((mut file, _)) = oldest.and_then(|key| self.open.remove(&key)) {
+            if let Err(e) = io::Write::flush(&mut file) {
+                file.report_error(
+                    "flushing file closed to make room for another key",
+                    e.into(),
+                );
+            }
+        }
+    }
+
+    /// The file for a key, opening it (and creating its directory) if need be.
+    pub fn get_mut(&mut self, key: &str) -> Result<&mut RotatingFile> {
+        self.tick += 1;
+        if !self.open.contains_key(key) {
+            let file = self.open_file(key)?;
+            while self.open.len() >= self.max_open {
+                self.evict();
+            }
+            self.open.insert(key.to_string(), (file, self.tick));
+        }
+        let (file, last_used) = self
+            .open
+            .get_mut(key)
+            .context("File for key missing after opening")?;
+        *last_used = self.tick;
+        Ok(file)
+    }
+
+    /// Write all of `bytes` to the file for `key`.
+    pub fn write_all(&mut self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let file = self.get_mut(key).map_err(io::Error::other)?;
+        io::Write::write_all(file, bytes)
+    }
+
+    /// Number of files currently open.
+    pub fn open_count(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Flush every open file, returning the first error once all have been tried.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut first_error = None;
+        for (key, (file, _)) in self.open.iter_mut() {
+            if let Err(e) = io::Write::flush(file) {
+                first_error
+                    .get_or_insert_with(|| io::Error::new(e.kind(), format!("{}: {}", key, e)));
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Latency histograms published with the `metrics` feature, see the crate docs.
+use std::time::Instant;
+
+/// Records the time from `start` until it's dropped into the named histogram, in seconds, so every return path is counted.
+pub(crate) struct Timer {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Timer {
+    pub fn start(name: &'static str) -> Self {
+        Self {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        metrics::histogram!(self.name).record(self.start.elapsed().as_secs_f64());
+    }
+}
@@ -17,11 +17,95 @@ Log suffix numbers will increase with age, so the first of the rotated logs will
 
 ## Warning
 <p style="background:rgba(255,181,77,0.16);padding:0.75em;">
-Little to no protection is given defend against the file indices being modified during the operation of whatever code is using this logger: when `write` is called it does not currently refresh the internal index which tracks the suffix integer, this is only done when the logger is created. Changing this is noted as future work.
+Little protection is given against the file indices being modified during the operation of whatever code is using this logger: the internal index which tracks the suffix integer is only detected from disk when the logger is created, when [`RotatingFile::reopen`] is called (i.e. on SIGHUP, see `SharedRotatingFile::into_reopen` with the `reopen` feature), or when a rotation finds the name it was about to use already taken, in which case it carries on after the highest index on disk rather than overwriting anything.
 </p>
 
 ## Error handling
 Not all internal errors are handled the same way. For example, if during the process of checking if rotation is required an error occurs, the default is to print a warning to stdout and _not_ rotate. In contrast to this, if an error occurs during the actual rotation procedure, this error is bubbled up through error handling eventually returning as a `std::io::Error` to the caller. However probable future state will outsource all error handling logic to the caller of this library rather than making assumptions.
+The printed warnings can be redirected by giving a callback to [`RotatingFile::with_error_hook`], i.e. to send them to your own logger or metrics.
+On Unix the `journald` feature adds `RotatingFile::with_journald_errors`, which also sends them to systemd-journald so they're seen even when
+it's the log files which are broken.
+
+Files are created with the process umask unless given permissions with [`RotatingFile::with_mode`]. On Unix the `ownership` feature adds
+`RotatingFile::with_owner` and `RotatingFile::with_owner_names`, to have them owned by a service user when starting as root.
+How they're opened, i.e. with `O_NOFOLLOW`, can be changed with [`RotatingFile::with_open_options`]. On Unix `DirFileSystem` opens the
+directory once and works relative to it, so rotation carries on if the directory is renamed or the process chroots. A path (or
+`.ACTIVE` file) which is a FIFO or device, i.e. `/dev/stdout`, is written to as is without rotating or pruning, which is reported to
+the error hook on the first write. On Windows paths
+too long for `MAX_PATH` are turned into `\\?\` extended-length paths, so deeply nested log directories work.
+
+The crate builds for `wasm32-wasip1`, where paths are resolved against the directories preopened for the module, so give the
+`RotatingFile` a path under one of those. WASI doesn't record when files were created, so age based rotation goes by when the
+active file was last modified until it's rotated, which can only make the first rotation late. There are no threads there, so
+anything which needs one, i.e. `with_background_rotation` or `CompressRoller`, returns an error rather than starting.
+
+For finer control rotation can be split into a [`Trigger`] and a chain of [`Roller`]s, log4rs style, see
+[`RotatingFile::with_trigger`] and [`RotatingFile::with_roller`]. Rotated files can be shipped off elsewhere with an [`UploadRoller`],
+the `object-store` feature providing an uploader for S3, GCS, Azure and friends and the `http` feature one which POSTs them to an endpoint. For existing `postrotate`
+style scripts there's [`CommandRoller`], with the `compression` feature `CompressRoller` (or just `RotatingFile::with_compression`) gzips rotated files on a background thread
+(or with the `zstd` and `lz4` features compresses them with zstd or lz4), `RotatingFile::with_streaming_compression` instead compresses
+the active file as it's written, and anything which just wants to know about rotations can register a [`RotationHook`].
+With the `archive` feature `RotatingFile::with_archive` packs rotated files into monthly tarballs once they reach a given age, and
+with the `encryption` feature `EncryptRoller` (or just `RotatingFile::with_encryption`) encrypts rotated files with age so they can't
+be read without the key. The `checksum` feature's `RotatingFile::with_checksums` writes a SHA-256 sidecar for each rotated file, which
+[`inspect::verify`] checks to catch bit rot and tampering. [`XattrHook`] stamps rotated files with their index and rotation time as
+extended attributes, for when something else renames them.
+
+To rotate something other than files on disk, i.e. compressed streams or network connections, use [`Rotating`] with your own
+[`SinkFactory`]. It takes the same rotation conditions and triggers. With the `object-store` feature `ObjectStoreSpool` is a
+factory which rotates straight into a bucket, for containers without a persistent disk.
+
+All filesystem access goes through the [`FileSystem`] trait, [`StdFileSystem`] by default. [`RotatingFile::new_in`] takes another,
+i.e. [`MemoryFileSystem`] to test rotation and pruning without touching the disk. The `test-support` feature adds `FailingFileSystem`,
+which wraps another to test what happens when an operation fails. Where there's no filesystem at all a [`RotatingBuffer`] rotates into
+segments in memory, which can be taken out with [`RotatingFile::drain_segments`].
+
+Existing sets of files can be listed, read in order, pruned and checked for gaps with the functions in [`inspect`]. The `cli` feature
+builds these into a `turnstiles` binary with `cat`, `ls`, `prune` and `verify` subcommands.
+
+Services with several log streams can keep them together in a [`RotatingFileSet`], to flush, rotate and shut them down in one go. Where streams come and
+go, i.e. a file per tenant, [`KeyedRotatingFiles`] creates them on demand and limits how many are open at once.
+
+## Sharing between threads
+`RotatingFile` needs `&mut self` to write, so to share one between threads wrap it in a [`SharedRotatingFile`], which is `Send + Sync`, cheap to clone, and implements `io::Write` for `&SharedRotatingFile`.
+See its docs for the locking behaviour. With many threads writing at once that one lock can become the bottleneck, in which case
+a [`ShardedRotatingFile`] spreads writes over several buffers which are committed to the file together.
+
+With the `tracing` feature enabled `SharedRotatingFile` also implements `tracing_subscriber`'s `MakeWriter`, so it can be given straight to
+`tracing_subscriber::fmt().with_writer(...)`, and `LevelRouter` splits events between two files by level.
+
+For a drop-in replacement for `tracing_appender`, with a `WorkerGuard`, see the [`appender`] module.
+
+Where even the occasional sync and rename on rotation is too slow for the writing thread, [`RotatingFile::into_non_blocking`] moves the
+file onto a worker thread behind a bounded queue, whose depth, drops and blocking can be checked with
+[`appender::NonBlocking::stats`] or, with the `metrics` feature, through the `metrics` crate. To keep writing on the calling thread but take the sync of the old file and pruning
+off it, use [`RotatingFile::with_background_rotation`]. Where the file is never dropped, i.e. it's behind a global logger, hold a
+[`FlushGuard`] in `main` so whatever is still buffered or queued is written out at exit.
+To make fewer write calls use [`RotatingFile::with_write_buffer`] rather than wrapping in a `BufWriter`, which would hide from
+the rotation condition what's been written and split records across files.
+With the `metrics` feature the time taken by every `write`, including any rotation it triggers, is recorded in the
+`turnstiles_write_seconds` histogram and the time taken by each rotation in `turnstiles_rotation_seconds`, so the tail latency of
+logging can be watched in production.
+
+## Async
+With the `futures-io` feature `FuturesRotatingFile` implements `futures::io::AsyncWrite`, for async-std, smol and friends. Writes, rotation
+and pruning run on a blocking thread pool so the executor is never held up by filesystem calls. With the `tokio` feature
+`AsyncRotatingFile` does the same for `tokio::io::AsyncWrite`, using `spawn_blocking`, and `SharedAsyncRotatingFile` shares one between
+many tasks.
+
+## `log` backend
+With the `log-backend` feature there's a minimal [`log::Log`](https://docs.rs/log) implementation, `RotatingLogger`, so small applications
+can get rotating file logging with `RotatingLogger::new(file).init()`. To keep using `env_logger` instead, give it
+[`SharedRotatingFile::pipe_target`] as a `Target::Pipe`.
+
+## slog
+With the `slog` feature there's `SlogDrain`, which serializes each record in full before writing it in one go. This avoids the problem of
+`slog_json` and friends making several writes per record, which otherwise needs `require_newline` to avoid splitting records across files. `SlogLevelRouter` splits records between two
+drains by level.
+
+## syslog
+With the `syslog` feature records can also be sent to a syslog daemon, over UDP or a UNIX socket, by passing a `SyslogTee` to
+[`RotatingFile::with_tee`].
 
 # Examples
 Rotate when a log file exceeds a certain filesize
@@ -132,72 +216,490 @@ for i in 1..4 {
 ```
 
 */
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "futures-io")]
+pub use async_file::FuturesRotatingFile;
+#[cfg(feature = "tokio")]
+pub use async_file::{AsyncRotatingFile, SharedAsyncRotatingFile};
+pub use buffer::{RotatingBuffer, Segment};
+#[cfg(feature = "checksum")]
+pub use checksum::ChecksumHook;
+pub use command::CommandRoller;
+#[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+pub use compress::{
+    CompressRoller, CompressionCodec, CompressionConfig, SizeBasis, COMPRESS_QUEUE_LEN,
+};
+use config::{Config, ConfigWatcher};
+#[cfg(unix)]
+pub use dirfd::DirFileSystem;
+#[cfg(feature = "encryption")]
+pub use encrypt::{EncryptRoller, ENCRYPT_QUEUE_LEN};
+#[cfg(any(test, feature = "test-support"))]
+pub use failing::{FailingFile, FailingFileSystem, FileOperation};
+use filesystem::{open_append_with, OpenWith};
+pub use filesystem::{
+    DiskFileSystem, FileHandle, FileSystem, MemoryFile, MemoryFileSystem, Metadata,
+    OpenOptionsHook, StdFileSystem,
+};
+use filter::{sanitize, Deduplicator, FnTransformer, LineTruncator};
+pub use filter::{SanitizeMode, Transformer};
+use std::borrow::Cow;
 use std::time::SystemTime;
 use std::{
     cmp,
-    fs::{self, remove_file, File, OpenOptions},
-    io,
-    time::Duration,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
+pub mod appender;
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(any(feature = "futures-io", feature = "tokio"))]
+mod async_file;
+mod background;
+mod buffer;
+#[cfg(feature = "checksum")]
+mod checksum;
+mod command;
+#[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+mod compress;
+mod config;
+#[cfg(unix)]
+mod dirfd;
+#[cfg(feature = "encryption")]
+mod encrypt;
+#[cfg(any(test, feature = "test-support"))]
+mod failing;
+mod filesystem;
+mod filter;
+mod group_commit;
+mod guard;
+pub mod inspect;
+#[cfg(all(unix, feature = "journald"))]
+mod journald;
+mod keyed;
+#[cfg(feature = "metrics")]
+mod latency;
+#[cfg(feature = "log-backend")]
+mod log_backend;
+#[cfg(feature = "object-store")]
+mod object_store_sink;
+#[cfg(all(unix, feature = "ownership"))]
+mod owner;
+pub mod parse;
+mod policy;
+mod rate_limit;
+mod set;
+mod sharded;
+mod shared;
+mod sink;
+#[cfg(feature = "slog")]
+mod slog_drain;
+#[cfg(feature = "syslog")]
+mod syslog;
+#[cfg(feature = "tracing")]
+mod tracing_writer;
+mod upload;
 mod utils;
-use regex::Regex;
-use utils::{filename_to_details, safe_unwrap_osstr};
+mod xattr;
+pub use guard::FlushGuard;
+pub use keyed::KeyedRotatingFiles;
+#[cfg(feature = "log-backend")]
+pub use log_backend::RotatingLogger;
+#[cfg(feature = "object-store")]
+pub use object_store_sink::ObjectStoreSpool;
+pub use policy::{
+    AgeTrigger, DeleteRoller, Roller, RotationContext, RotationHook, SizeTrigger, TimestampRoller,
+    Trigger,
+};
+use rate_limit::{Admit, RateLimiter, Sampler};
+pub use rate_limit::{LimitPolicy, RateLimit, Sampling, SamplingTrigger, Throughput};
+use regex::bytes::Regex;
+pub use set::RotatingFileSet;
+pub use sharded::{
+    ShardedBuilder, ShardedRotatingFile, DEFAULT_COMMIT_INTERVAL, DEFAULT_SHARD_CAPACITY,
+};
+pub use shared::SharedRotatingFile;
+pub use sink::{Rotating, SinkFactory};
+#[cfg(feature = "slog")]
+pub use slog_drain::{SlogDrain, SlogFormat, SlogLevelRouter};
+#[cfg(feature = "syslog")]
+pub use syslog::{SyslogFormat, SyslogTee};
+#[cfg(feature = "tracing")]
+pub use tracing_writer::{LevelRouter, SharedRotatingFileGuard};
+#[cfg(feature = "http")]
+pub use upload::HttpUploader;
+#[cfg(feature = "object-store")]
+pub use upload::ObjectStoreUploader;
+pub use upload::{AfterUpload, UploadPolicy, UploadRoller, Uploader};
+use utils::{filename_to_details, format_rfc3339, hostname, new_epoch_id};
+pub use xattr::{RotationXattrs, XattrHook};
 
 // TODO: template this maybe? Or just make it u128 and fugheddaboutit?
 type FileIndexInt = u32;
 const BYTES_TO_MB: u64 = 1_048_576;
 
 // Changed from prefix to suffix here to make wildcarding less of a faff.
-fn active_filename(root_filename: &str) -> String {
-    format!("{}{}", root_filename, ".ACTIVE")
+fn active_filename(root_filename: &OsStr) -> OsString {
+    with_suffix(root_filename, ".ACTIVE")
+}
+
+/// Spare file opened ahead of time to become the next active file, see `RotatingFile::with_precreate`.
+fn spare_filename(root_filename: &OsStr) -> OsString {
+    with_suffix(root_filename, ".NEXT")
+}
+
+/// `<filename>.<index>`, followed by `suffix` if it's been compressed as it was written.
+fn rotated_filename(root_filename: &OsStr, index: FileIndexInt, suffix: &str) -> OsString {
+    with_suffix(root_filename, &format!(".{}{}", index, suffix))
+}
+
+/// Names are built and taken apart by their suffixes, which are always text, so the rest can be any file name, UTF-8 or not.
+fn with_suffix(filename: &OsStr, suffix: &str) -> OsString {
+    let mut filename = filename.to_owned();
+    filename.push(suffix);
+    filename
+}
+
+/// `filename` less a suffix with no '.' in it but the first, if it ends with it.
+fn strip_suffix<'a>(filename: &'a OsStr, suffix: &str) -> Option<&'a OsStr> {
+    filename
+        .as_encoded_bytes()
+        .ends_with(suffix.as_bytes())
+        .then(|| Path::new(filename).file_stem())
+        .flatten()
+}
+
+/// What follows `<root_filename>.` in `filename`, if that's how it starts and the rest is UTF-8.
+fn name_suffix<'a>(root_filename: &OsStr, filename: &'a [u8]) -> Option<&'a str> {
+    let rest = filename
+        .strip_prefix(root_filename.as_encoded_bytes())?
+        .strip_prefix(b".")?;
+    std::str::from_utf8(rest).ok()
+}
+
+/// Lock file taken while rotating on a network filesystem, see `RotatingFile::with_network_filesystem`.
+fn lock_filename(root_filename: &OsStr) -> OsString {
+    with_suffix(root_filename, ".lock")
+}
+
+/// How long a rotation lock can be held before it's taken to have been left behind by a process which died.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(60);
+
+/// Whether an error is NFS's stale file handle, which trying again with the path sorts out.
+fn is_stale_handle(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        e.raw_os_error() == Some(libc::ESTALE)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = e;
+        false
+    }
+}
+
+/// Times a rename is retried when the file is in use, see `RotatingFile::with_close_before_rename`.
+const RENAME_RETRIES: u32 = 5;
+
+/// Rename, retrying for a moment if the file is in use, i.e. on Windows while a log shipper has it open without sharing delete, or
+/// the handle has gone stale on NFS.
+fn rename_retrying<FS: FileSystem>(fs: &FS, from: &Path, to: &Path) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match fs.rename(from, to) {
+            Err(e)
+                if (e.kind() == io::ErrorKind::PermissionDenied || is_stale_handle(&e))
+                    && attempt < RENAME_RETRIES =>
+            {
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(10 << attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Added to rotated files once they've been compressed with gzip, zstd or lz4, see `CompressRoller`.
+const COMPRESSED_SUFFIXES: [&str; 3] = [".gz", ".zst", ".lz4"];
+
+/// Added to rotated files once they've been encrypted, after any compressed suffix, see `EncryptRoller`.
+const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// Name of a rotated file without any compressed or encrypted suffix, i.e. `test.log.3` for `test.log.3.gz.age`.
+fn strip_rotated_suffixes(filename: &OsStr) -> &OsStr {
+    let filename = strip_suffix(filename, ENCRYPTED_SUFFIX).unwrap_or(filename);
+    COMPRESSED_SUFFIXES
+        .iter()
+        .find_map(|suffix| strip_suffix(filename, suffix))
+        .unwrap_or(filename)
+}
+
+/// Added to a rotated file's name, less any compressed or encrypted suffix, for its checksum sidecar, see `ChecksumHook`.
+const CHECKSUM_SUFFIX: &str = ".sha256";
+
+/// Checksum sidecar for a rotated file, i.e. `test.log.3.sha256` for `test.log.3.gz`.
+fn checksum_sidecar(filename: &OsStr) -> OsString {
+    with_suffix(strip_rotated_suffixes(filename), CHECKSUM_SUFFIX)
+}
+
+/// End of the monthly tarballs of rotated files, `<filename>.<YYYY-MM>.tar.gz`, see `RotatingFile::with_archive`.
+const ARCHIVE_SUFFIX: &str = ".tar.gz";
+
+fn is_archive(filename: &OsStr) -> bool {
+    filename
+        .as_encoded_bytes()
+        .ends_with(ARCHIVE_SUFFIX.as_bytes())
+}
+
+/// Matches the names of rotated files, `<filename>.<index>`, compressed and encrypted or not, along with tarballs of them. Matched
+/// against the bytes of a name (see `OsStr::as_encoded_bytes`) so names needn't be UTF-8.
+fn rotated_file_regex(root_filename: &OsStr) -> Result<Regex, std::io::Error> {
+    let mut root = String::new();
+    for &byte in root_filename.as_encoded_bytes() {
+        if byte.is_ascii() {
+            root.push_str(&regex::escape(char::from(byte).encode_utf8(&mut [0; 4])));
+        } else {
+            root.push_str(&format!(r"\x{:02x}", byte));
+        }
+    }
+    Regex::new(&format!(
+        r"(?-u)^{}\.([0-9]+(\.gz|\.zst|\.lz4)?(\.age)?|[0-9]{{4}}-[0-9]{{2}}\.tar\.gz)$",
+        root
+    ))
+    .map_err(|e| {
+        // Thanks I hate it.
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Regex failed with error {}", e),
+        )
+    })
 }
-#[derive(Debug)]
+/// Callback for errors which turnstiles catches rather than returns (see the crate docs on error handling), given a short
+/// description of where the error happened and the error itself. Without one these are printed to stdout as warnings.
+pub type ErrorHook = Box<dyn FnMut(&str, &anyhow::Error) + Send>;
+
+/// Pass an error we've decided not to return to the error hook, or print it if there isn't one.
+pub(crate) fn report_to(hook: Option<&mut ErrorHook>, context: &str, e: anyhow::Error) {
+    match hook {
+        Some(hook) => hook(context, &e),
+        None => println!("WARN: turnstiles caught error in {}.\nErr: {}", context, e),
+    }
+}
+
 /// Struct masquerades as a file handle and is written to by whatever you like
-pub struct RotatingFile {
-    filename_root: String,
-    active_file_path: String,
-    active_file_name: String,
+pub struct RotatingFile<FS: FileSystem = StdFileSystem> {
+    filename_root: OsString,
+    active_file_path: PathBuf,
+    active_file_name: OsString,
     rotation_method: RotationCondition,
     prune_method: PruneCondition,
-    current_file: File,
+    fs: FS,
+    current_file: FS::File,
+    /// Bytes, lines and records written, the size being what size-based conditions use rather than asking the filesystem
+    tally: Tally,
+    /// Size of the active file once it was started, i.e. its banner, before any records went in. Rotating a file no bigger than
+    /// this makes no room, so an oversized record goes in it whole.
+    header_size: u64,
+    /// Creation time of the active file, looked up when it's opened so age-based conditions don't ask on every write
+    created: Option<SystemTime>,
+    /// When a `RotationCondition::Duration` next comes due, worked out on the first check after the active file or the condition
+    /// changes so the rest only compare against the clock
+    rotation_deadline: Option<Instant>,
     index: FileIndexInt,
     require_newline: bool, // Should be type to avoid runtime cost?
-    parent: String,
+    parent: PathBuf,
     file_regex: Regex,
+    error_hook: Option<ErrorHook>,
+    config_watcher: Option<ConfigWatcher>,
+    rate_limiter: Option<RateLimiter>,
+    sampler: Option<Sampler>,
+    transformer: Option<Box<dyn Transformer + Send>>,
+    tees: Vec<Box<dyn Write + Send>>,
+    on_write: Option<Box<dyn FnMut(usize, FileIndexInt) + Send>>,
+    oversized_write_policy: OversizedWritePolicy,
+    /// Working around NFS and friends, see `with_network_filesystem`
+    network_filesystem: bool,
+    /// Writing to a FIFO or device rather than a file, so never rotating or pruning, and whether that's been reported yet
+    passthrough: bool,
+    passthrough_reported: bool,
+    /// What to set the modified time of rotated files to, see `with_rotated_mtime`
+    rotated_mtime: Option<RotatedMtime>,
+    /// When the last record was written, kept for `RotatedMtime::LastWrite`
+    last_write: Option<SystemTime>,
+    rotate_on_flush: bool,
+    /// Rotate away the last file in `close`, see `with_rotate_on_close`
+    rotate_on_close: bool,
+    banner: bool,
+    footer: bool,
+    epoch_id: String,
+    line_truncator: Option<LineTruncator>,
+    sanitize_mode: Option<SanitizeMode>,
+    deduplicator: Option<Deduplicator>,
+    trigger: Option<Box<dyn Trigger + Send>>,
+    rollers: Vec<Box<dyn Roller + Send>>,
+    rotation_hooks: Vec<Box<dyn RotationHook + Send>>,
+    #[cfg(all(unix, feature = "journald"))]
+    journald: Option<journald::Journald>,
+    background: Option<background::Background<FS::File>>,
+    precreate: bool,
+    spare: Option<FS::File>,
+    /// Unix permissions for the files we create, see `with_mode`
+    mode: Option<u32>,
+    /// User and group for the files we create, see `with_owner`
+    #[cfg(all(unix, feature = "ownership"))]
+    owner: Option<(Option<u32>, Option<u32>)>,
+    /// Changes to how the files we create are opened, see `with_open_options`
+    open_options: Option<OpenOptionsHook>,
+    /// Close the active file before renaming it at rotation, see `with_close_before_rename`
+    close_before_rename: bool,
+    /// Names of the rotated files, listed once and then kept up to date as we rotate and prune so pruning doesn't have to read
+    /// the directory. `None` when it needs listing again.
+    rotated_files: Option<Vec<OsString>>,
+    /// Bytes written but not yet passed to the active file, see `with_write_buffer`. Already counted in the tally's size.
+    buffer: Vec<u8>,
+    buffer_capacity: usize,
+    durability: Durability,
+    /// Rotated files last modified longer ago than this are packed into monthly tarballs, see `with_archive`
+    #[cfg(feature = "archive")]
+    archive_after: Option<Duration>,
+    /// Compresses what's written to the active file as it goes, see `with_streaming_compression`
+    #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+    stream: Option<compress::Stream>,
+    /// Hashes the active file as it's written for the next file's banner, see `with_hash_chain`
+    #[cfg(feature = "checksum")]
+    hash_chain: Option<checksum::HashChain>,
+}
+
+/// When writes to the active file are made durable, see `RotatingFile::with_sync_every_write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Durability {
+    /// Only synced on rotation or when asked to, the normal fast path
+    OnRotation,
+    /// Opened with `O_DSYNC`, so every write is durable when it returns
+    Dsync,
+    /// `sync_data` after every write, where `O_DSYNC` isn't available
+    SyncEachWrite,
+}
+
+/// What came of trying to take the rotation lock, see `RotatingFile::with_network_filesystem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RotationLock {
+    /// Lock file created, so it's ours to remove once rotated
+    Taken,
+    /// Another process holds it
+    Busy,
+    /// The filesystem can't create files exclusively, so rotating goes ahead without a lock file
+    Unsupported,
+}
+
+impl<FS: FileSystem> fmt::Debug for RotatingFile<FS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RotatingFile")
+            .field("active_file_path", &self.active_file_path)
+            .field("rotation_method", &self.rotation_method)
+            .field("prune_method", &self.prune_method)
+            .field("index", &self.index)
+            .field("require_newline", &self.require_newline)
+            .field("background_rotation", &self.background.is_some())
+            .finish_non_exhaustive()
+    }
 }
 
 impl RotatingFile {
     /// Create a new RotatingFile given a desired filename and rotation option. The filename represents the stem or root of the files
-    /// to be generated.
+    /// to be generated, and needn't be UTF-8.
     pub fn new(
-        path_str: &str,
+        path: impl AsRef<Path>,
+        rotation_method: RotationCondition,
+        prune_method: PruneCondition,
+        require_newline: bool,
+    ) -> Result<Self> {
+        Self::new_in(
+            StdFileSystem,
+            path,
+            rotation_method,
+            prune_method,
+            require_newline,
+        )
+    }
+
+    /// Create a new RotatingFile from environment variables named `<prefix>_<KEY>`, i.e. `TURNSTILES_PATH`, `TURNSTILES_ROTATE_SIZE=100MB`,
+    /// `TURNSTILES_ROTATE_AGE=1d`, `TURNSTILES_MAX_FILES=10`, `TURNSTILES_MAX_AGE=7d` and `TURNSTILES_REQUIRE_NEWLINE=true` for a prefix of `TURNSTILES`.
+    /// Only the path is required, see [`parse`] for the accepted size and duration formats.
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let config = Config::from_env(prefix)?;
+        let path = config
+            .path
+            .with_context(|| format!("{}_PATH must be set", prefix))?;
+        Self::new(&path, config.rotation, config.prune, config.require_newline)
+    }
+
+    #[deprecated(
+        since = "0.5.0",
+        note = "seeking or truncating the handle breaks internal bookkeeping, use file_info() or sync_data() instead"
+    )]
+    pub fn current_file(&self) -> &File {
+        &self.current_file
+    }
+
+    /// Move the file onto a background thread, so writes only cost a copy into a queue and the writing, rotation and pruning happen
+    /// elsewhere. Shorthand for [`appender::non_blocking`], see there for the details. Keep the guard alive for as long as the writer
+    /// is in use.
+    pub fn into_non_blocking(self) -> (appender::NonBlocking, appender::WorkerGuard) {
+        appender::non_blocking(self)
+    }
+}
+
+impl<FS: FileSystem> RotatingFile<FS> {
+    /// As [`RotatingFile::new`] but on the given [`FileSystem`] rather than the real one, i.e. a [`MemoryFileSystem`] for tests.
+    pub fn new_in(
+        fs: FS,
+        path: impl AsRef<Path>,
         rotation_method: RotationCondition,
         prune_method: PruneCondition,
         require_newline: bool,
     ) -> Result<Self> {
         Self::check_options(&rotation_method, &prune_method)?;
-        // TODO: throw error if path_str (rootname) ends in digit as this will break the numbering stuff
-        let (path_filename, parent) = filename_to_details(path_str)?;
-        let file_regex = Regex::new(&format!(r"^{}.[0-9]+$", path_filename)).map_err(|e| {
-            // Thanks I hate it.
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Regex failed with error {}", e),
-            )
-        })?;
-
-        let active_file_name = active_filename(&path_filename);
-        let active_file_path = format!("{}/{}", parent, &active_file_name);
-        let current_index = Self::detect_latest_file_index(&file_regex, &parent)?;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(active_file_path.clone())?;
+        // TODO: throw error if path (rootname) ends in digit as this will break the numbering stuff
+        let (path_filename, parent) = filename_to_details(path.as_ref())?;
+        let file_regex = rotated_file_regex(&path_filename)?;
+
+        let mut active_file_name = active_filename(&path_filename);
+        let mut active_file_path = parent.join(&active_file_name);
+        // Something which can be written to but not renamed or measured, i.e. /dev/stdout or a named pipe, is just passed through
+        let root_path = parent.join(&path_filename);
+        let passthrough = if fs.is_stream(&root_path) {
+            active_file_name = path_filename.clone();
+            active_file_path = root_path;
+            true
+        } else {
+            fs.is_stream(&active_file_path)
+        };
+        let rotated_files = if passthrough {
+            vec![]
+        } else {
+            Self::list_rotated_log_files(&fs, &file_regex, &parent)?
+        };
+        let current_index = Self::latest_file_index(&rotated_files)?;
+        let file = fs.open_append(&active_file_path)?;
+        let metadata = file.metadata()?;
         Ok(Self {
+            fs,
             rotation_method,
             prune_method,
             current_file: file,
+            tally: Tally {
+                size: metadata.len,
+                ..Tally::default()
+            },
+            header_size: 0,
+            created: metadata.created_or_modified(),
+            rotation_deadline: None,
             index: current_index,
             filename_root: path_filename,
             require_newline,
@@ -205,9 +707,541 @@ impl RotatingFile {
             active_file_name,
             parent,
             file_regex,
+            error_hook: None,
+            config_watcher: None,
+            rate_limiter: None,
+            sampler: None,
+            transformer: None,
+            tees: vec![],
+            on_write: None,
+            oversized_write_policy: OversizedWritePolicy::Allow,
+            network_filesystem: false,
+            passthrough,
+            passthrough_reported: false,
+            rotated_mtime: None,
+            last_write: None,
+            rotate_on_flush: false,
+            rotate_on_close: false,
+            banner: false,
+            footer: false,
+            epoch_id: new_epoch_id(),
+            line_truncator: None,
+            sanitize_mode: None,
+            deduplicator: None,
+            trigger: None,
+            rollers: vec![],
+            rotation_hooks: vec![],
+            #[cfg(all(unix, feature = "journald"))]
+            journald: None,
+            background: None,
+            precreate: false,
+            spare: None,
+            mode: None,
+            #[cfg(all(unix, feature = "ownership"))]
+            owner: None,
+            open_options: None,
+            close_before_rename: cfg!(windows),
+            rotated_files: Some(rotated_files),
+            buffer: vec![],
+            buffer_capacity: 0,
+            durability: Durability::OnRotation,
+            #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+            stream: None,
+            #[cfg(feature = "archive")]
+            archive_after: None,
+            #[cfg(feature = "checksum")]
+            hash_chain: None,
         })
     }
 
+    /// Send errors which are caught internally to a callback instead of printing them to stdout.
+    pub fn with_error_hook(
+        mut self,
+        hook: impl FnMut(&str, &anyhow::Error) + Send + 'static,
+    ) -> Self {
+        self.error_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Take the rotation and prune conditions from a config file (see [`RotatingFile::set_rotation_condition`] for how changes are applied),
+    /// and check it for changes at most once every `poll_interval`, checked when `write` is called.
+    /// The file holds `KEY = value` lines with the same keys as [`RotatingFile::from_env`] minus the prefix, i.e. `ROTATE_SIZE = 100MB`,
+    /// any `PATH` or `REQUIRE_NEWLINE` given is ignored. A missing key resets that condition to `None`.
+    ///
+    /// An invalid config when this is called is returned as an error, whereas an invalid config found later is passed to the error
+    /// hook and the current conditions are kept.
+    pub fn with_config_file<P: AsRef<Path>>(
+        mut self,
+        path: P,
+        poll_interval: Duration,
+    ) -> Result<Self> {
+        let (watcher, config) = ConfigWatcher::new(path.as_ref().to_path_buf(), poll_interval)?;
+        self.apply_config(config)?;
+        self.config_watcher = Some(watcher);
+        Ok(self)
+    }
+
+    /// Limit how much can be written per second, see [`LimitPolicy`] for what happens to writes over the limit. Note a write which is dropped
+    /// still reports all bytes as written so callers don't retry it.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(limit));
+        self
+    }
+
+    /// Number of writes dropped by the rate limit so far.
+    pub fn suppressed_count(&self) -> u64 {
+        self.rate_limiter.as_ref().map_or(0, |l| l.suppressed)
+    }
+
+    /// Under pressure, either a large active file or a high write rate, only keep every Nth record (call to `write`) to degrade
+    /// gracefully rather than fill the disk. See [`Sampling`].
+    pub fn with_sampling(mut self, sampling: Sampling) -> Result<Self> {
+        if sampling.keep_every == 0 {
+            bail!("Invalid option: Sampling::keep_every must be at least 1");
+        }
+        if let SamplingTrigger::FileSizeMB(mb) = sampling.trigger {
+            if mb.checked_mul(BYTES_TO_MB).is_none() {
+                bail!(
+                    "Invalid option: SamplingTrigger::FileSizeMB({}) is too large",
+                    mb
+                );
+            }
+        }
+        self.sampler = Some(Sampler::new(sampling));
+        Ok(self)
+    }
+
+    /// Number of writes dropped by sampling so far.
+    pub fn sampled_out_count(&self) -> u64 {
+        self.sampler.as_ref().map_or(0, |s| s.dropped)
+    }
+
+    /// Pass every buffer through a [`Transformer`] before anything else is done with it, i.e. to redact secrets at the sink
+    /// whatever produced the line.
+    pub fn with_transform(mut self, transformer: impl Transformer + Send + 'static) -> Self {
+        self.transformer = Some(Box::new(transformer));
+        self
+    }
+
+    /// As [`RotatingFile::with_transform`] but for a closure.
+    pub fn with_transform_fn<F>(self, f: F) -> Self
+    where
+        F: for<'a> FnMut(&'a [u8]) -> Cow<'a, [u8]> + Send + 'static,
+    {
+        self.with_transform(FnTransformer(f))
+    }
+
+    /// Copy everything written to the file to another writer as well, i.e. a socket or a ring buffer for a crash reporter. This gets the
+    /// bytes after any filtering, and only once they've made it to the file. Errors from the tee go to the error hook rather than failing
+    /// the write. Can be called more than once to add several tees, which are numbered from 0 in error reports.
+    pub fn with_tee(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.tees.push(Box::new(writer));
+        self
+    }
+
+    /// Choose what to do with a write which would take the file over a `RotationCondition::SizeMB` limit, see [`OversizedWritePolicy`].
+    /// Has no effect for other rotation conditions.
+    pub fn with_oversized_write_policy(mut self, policy: OversizedWritePolicy) -> Self {
+        self.oversized_write_policy = policy;
+        self
+    }
+
+    /// Set the modified time of each file as it's rotated, for retention tools which go by it, see [`RotatedMtime`]. Otherwise it's
+    /// left as the filesystem has it, which is when the last bytes reached the file: usually the last record, but the footer or
+    /// anything buffered is written at rotation. `CompressRoller` and `EncryptRoller` give their output the modified time of the
+    /// file they started from either way.
+    pub fn with_rotated_mtime(mut self, mtime: RotatedMtime) -> Self {
+        self.rotated_mtime = Some(mtime);
+        self
+    }
+
+    /// Hold up to `capacity` bytes in memory and pass them to the file in one go, rather than making a write call for each record.
+    /// Unlike wrapping this in a `BufWriter`, buffered bytes count towards `RotationCondition::SizeMB` and are always written out before
+    /// a rotation, so records still land in the right file. The buffer is written out on `flush`, rotation and drop. Writes of at
+    /// least `capacity` bytes go straight to the file. Off by default, and a `capacity` of 0 turns it off again.
+    pub fn with_write_buffer(mut self, capacity: usize) -> Self {
+        self.buffer = Vec::with_capacity(capacity);
+        self.buffer_capacity = capacity;
+        self
+    }
+
+    /// Compress the active file as it's written rather than once it's rotated, so nothing is read back and written again. The active
+    /// file becomes i.e. `test.log.ACTIVE.gz` and rotated files `test.log.N.gz`, each a complete stream which the usual tools can
+    /// read. `flush` pushes out everything compressed so far, so the active file can be read up to that point, though flushing
+    /// often costs some ratio. `basis` chooses whether a `RotationCondition::SizeMB` counts bytes before or after compression;
+    /// after compression any [`OversizedWritePolicy`] splitting is only approximate. The `min_size` of the config is ignored.
+    ///
+    /// Anything already in the uncompressed active file is rotated away first as it is. A compressed active file left by a
+    /// previous run may end part way through its stream, so that's rotated away too rather than appended to.
+    #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+    pub fn with_streaming_compression(
+        mut self,
+        config: CompressionConfig,
+        basis: SizeBasis,
+    ) -> Result<Self> {
+        if self.passthrough {
+            bail!(
+                "Invalid option: streaming compression of {}, which is a FIFO or device rather than a file",
+                self.active_file_path.display()
+            );
+        }
+        let stream = compress::Stream::new(config, basis)?;
+        io::Write::flush(&mut self)?;
+        if self.tally.size > self.header_size && !self.rotate_current_file()? {
+            bail!(
+                "Couldn't rotate away {} before compressing, another process holds the rotation lock",
+                self.active_file_path.display()
+            );
+        }
+        self.fs.remove_file(&self.active_file_path)?;
+        self.active_file_name = with_suffix(&active_filename(&self.filename_root), stream.suffix());
+        self.active_file_path = self.parent.join(&self.active_file_name);
+        let left_over = self
+            .fs
+            .metadata(&self.active_file_path)
+            .is_ok_and(|metadata| metadata.len > 0);
+        if left_over {
+            self.redetect_index_if_taken()?;
+            let rotated_name =
+                rotated_filename(&self.filename_root, self.index + 1, stream.suffix());
+            self.fs
+                .rename(&self.active_file_path, &self.parent.join(&rotated_name))?;
+            if let Some(rotated_files) = &mut self.rotated_files {
+                rotated_files.push(rotated_name);
+            }
+            self.index += 1;
+        }
+        self.current_file = self.open_append(&self.active_file_path)?;
+        let metadata = self.current_file.metadata()?;
+        self.tally.size = metadata.len;
+        self.header_size = 0;
+        self.created = self.file_created(metadata);
+        self.rotation_deadline = None;
+        self.stream = Some(stream);
+        Ok(self)
+    }
+
+    /// Make every write durable before it returns, for i.e. audit logs where nothing accepted can be lost. The active file is opened
+    /// with `O_DSYNC` where the platform has it, otherwise `sync_data` is called after each write. Either way this is much slower
+    /// than the default, where files are only synced on rotation or by [`RotatingFile::sync_data`]. Any [`RotatingFile::with_write_buffer`]
+    /// is bypassed, as buffered bytes wouldn't be durable.
+    pub fn with_sync_every_write(mut self) -> Result<Self> {
+        let dsync = self.fs.open_append_dsync(&self.active_file_path)?;
+        self.flush_buffer()?;
+        if let Some(file) = dsync {
+            self.durability = Durability::Dsync;
+            self.current_file = match self.open_options {
+                // Opened again so the customization is applied along with O_DSYNC
+                Some(_) => self.open_append(&self.active_file_path)?,
+                None => file,
+            };
+        } else {
+            self.durability = Durability::SyncEachWrite;
+        }
+        // Anything written before now isn't covered by O_DSYNC
+        self.current_file.sync_data()?;
+        if self.spare.is_some() {
+            self.spare = Some(self.open_append(&self.spare_file_path())?);
+        }
+        Ok(self)
+    }
+
+    /// Also check for rotation when `flush()` is called, after flushing. Useful with `require_newline` for async drains which call `flush()`
+    /// at the end of each record even when the individual writes making it up don't end in a newline.
+    pub fn with_rotate_on_flush(mut self) -> Self {
+        self.rotate_on_flush = true;
+        self
+    }
+
+    /// Have [`RotatingFile::close`] rotate the active file if anything's been written to it, so it goes through the rollers like any
+    /// other and a cleanly stopped service leaves i.e. only compressed files behind with `CompressRoller` or
+    /// `with_streaming_compression`. The empty active file opened by that rotation is removed, to be created again on restart.
+    pub fn with_rotate_on_close(mut self) -> Self {
+        self.rotate_on_close = true;
+        self
+    }
+
+    /// Start each new active file with a banner line giving the time it was created, the hostname, the turnstiles version and the name
+    /// of the file it follows on from, i.e.
+    /// `# turnstiles 0.5.0 | epoch 5c1e0f9a2b7d4e31 | created 2022-01-31T13:45:00Z | host web-1 | previous test.log.3`, where the
+    /// epoch is the file's [`RotatingFile::epoch_id`].
+    /// If the current active file is empty it gets a banner straight away. Note this will trip up anything expecting every line in the
+    /// file to be, say, JSON.
+    pub fn with_banner(mut self) -> Result<Self> {
+        self.banner = true;
+        if self.tally.size == 0 {
+            let previous = match self.index {
+                0 => None,
+                i => Some(rotated_filename(&self.filename_root, i, "")),
+            };
+            self.write_banner(previous.as_deref(), None)?;
+            self.header_size = self.tally.size;
+        }
+        Ok(self)
+    }
+
+    /// `previous_sha256` is the hash of the previous file for `with_hash_chain`, if known.
+    fn write_banner(
+        &mut self,
+        previous: Option<&OsStr>,
+        previous_sha256: Option<&str>,
+    ) -> Result<(), std::io::Error> {
+        let mut banner = format!(
+            "# turnstiles {} | epoch {} | created {} | host {} | previous {}",
+            env!("CARGO_PKG_VERSION"),
+            self.epoch_id,
+            format_rfc3339(SystemTime::now()),
+            hostname(),
+            previous.map_or(Cow::Borrowed("none"), OsStr::to_string_lossy)
+        );
+        if let Some(sha256) = previous_sha256 {
+            banner.push_str(" | previous sha256 ");
+            banner.push_str(sha256);
+        }
+        banner.push('\n');
+        self.write_file_bytes(banner.as_bytes())
+    }
+
+    /// Chain the files together so deleting or changing any but the newest can be spotted: each file's banner ends with the
+    /// SHA-256 of the file before it, i.e. `... | previous test.log.3 | previous sha256 9f86d0...`, which [`inspect::verify`] checks.
+    /// Turns on [`RotatingFile::with_banner`]. The hash is worked out as the file is written, so there's no reading it back at
+    /// rotation, but it only covers what this `RotatingFile` wrote: a non-empty active file is rotated away first so the chain
+    /// starts with a whole file, and after a `reopen` the next banner goes without. Files later compressed or encrypted by a
+    /// roller can't be checked against it.
+    #[cfg(feature = "checksum")]
+    pub fn with_hash_chain(mut self) -> Result<Self> {
+        io::Write::flush(&mut self)?;
+        let whole = self.tally.size == 0 || self.rotate_current_file()?;
+        let mut chain = checksum::HashChain::new();
+        if !whole {
+            // Another process holds the rotation lock, so the first file in the chain isn't all ours
+            chain.invalidate();
+        }
+        self.hash_chain = Some(chain);
+        self.with_banner()
+    }
+
+    /// Hash of the file just rotated for the next file's banner, see `with_hash_chain`.
+    fn finish_hash_chain(&mut self) -> Option<String> {
+        #[cfg(feature = "checksum")]
+        if let Some(chain) = &mut self.hash_chain {
+            return chain.finish();
+        }
+        None
+    }
+
+    /// End each file with a footer line when it's rotated, giving its epoch id and that of the file which follows, i.e.
+    /// `# turnstiles epoch 5c1e0f9a2b7d4e31 | ended 2022-01-31T14:45:00Z | next epoch 0b9e3d2f7c6a1e44`. Along with the banner this lets
+    /// files be chained back together even after they've been renamed or shipped elsewhere.
+    pub fn with_footer(mut self) -> Self {
+        self.footer = true;
+        self
+    }
+
+    /// Unique id for the lifetime of the current active file, regenerated on every rotation. Also written to the banner and footer
+    /// if enabled, so aggregation pipelines can tell which chunks of data belong together.
+    pub fn epoch_id(&self) -> &str {
+        &self.epoch_id
+    }
+
+    /// Call `hook` after every successful write to a file with the number of bytes written and the index of the file they went to
+    /// (the active file's index, as returned by [`RotatingFile::index`]). The count is what went to the file after any filtering,
+    /// including banners, footers and summary lines written by turnstiles itself, so it can be used for accurate accounting of log
+    /// volume. A write split across a rotation is reported in parts, each against the file it went to. With
+    /// `with_streaming_compression` the bytes are counted before they're compressed.
+    pub fn with_on_write(mut self, hook: impl FnMut(usize, FileIndexInt) + Send + 'static) -> Self {
+        self.on_write = Some(Box::new(hook));
+        self
+    }
+
+    /// Also write everything to stdout or stderr, i.e. so `kubectl logs` works while still keeping rotated files around. This is a
+    /// shortcut for [`RotatingFile::with_tee`] so the same error handling applies.
+    pub fn with_mirror(self, mirror: Mirror) -> Self {
+        match mirror {
+            Mirror::Stdout => self.with_tee(io::stdout()),
+            Mirror::Stderr => self.with_tee(io::stderr()),
+        }
+    }
+
+    /// Cap each line at `max` bytes (not counting the newline), anything past that is replaced by `marker`, i.e. `"…[truncated]"`.
+    /// Lines split over several writes are handled, so this can be used with async loggers too. The cap applies after
+    /// [`RotatingFile::with_sanitize`], so escaped control characters count at their escaped length.
+    pub fn with_max_line_length(mut self, max: usize, marker: impl Into<Vec<u8>>) -> Self {
+        self.line_truncator = Some(LineTruncator::new(max, marker.into()));
+        self
+    }
+
+    /// Strip or escape control characters (other than newline and tab) and ANSI escape sequences from everything written,
+    /// to stop log lines being able to mess with whoever `cat`s the file later.
+    pub fn with_sanitize(mut self, mode: SanitizeMode) -> Self {
+        self.sanitize_mode = Some(mode);
+        self
+    }
+
+    /// Collapse consecutive identical lines into one followed by `last message repeated N times`, as syslog does, so a tight error loop
+    /// doesn't burn through the whole retention window. The summary is written when a different line arrives, on `flush()`,
+    /// `close()` or drop. Note this holds back partial lines until their newline is written (or until one of those).
+    pub fn with_deduplication(mut self) -> Self {
+        self.deduplicator = Some(Deduplicator::default());
+        self
+    }
+
+    /// Decide when to rotate with a `Trigger` rather than the `RotationCondition`, which is then ignored, including for the
+    /// oversized write policy. See the [`Trigger`] docs.
+    pub fn with_trigger(mut self, trigger: impl Trigger + Send + 'static) -> Self {
+        self.trigger = Some(Box::new(trigger));
+        self
+    }
+
+    /// Change the rotation condition at runtime, which takes effect on the next write. The current file is judged by the new condition,
+    /// so i.e. shrinking the size limit below the current file's size will cause a rotation on the next write.
+    pub fn set_rotation_condition(&mut self, rotation_method: RotationCondition) -> Result<()> {
+        Self::check_options(&rotation_method, &self.prune_method)?;
+        self.rotation_method = rotation_method;
+        self.rotation_deadline = None;
+        Ok(())
+    }
+
+    /// Change the prune condition at runtime. This is applied the next time a rotation happens.
+    pub fn set_prune_condition(&mut self, prune_method: PruneCondition) -> Result<()> {
+        Self::check_options(&self.rotation_method, &prune_method)?;
+        self.prune_method = prune_method;
+        Ok(())
+    }
+
+    /// Apply both conditions from a config, or neither if either is invalid.
+    fn apply_config(&mut self, config: Config) -> Result<()> {
+        Self::check_options(&config.rotation, &config.prune)?;
+        self.rotation_method = config.rotation;
+        self.rotation_deadline = None;
+        self.prune_method = config.prune;
+        Ok(())
+    }
+
+    /// Check the config file, if any, for changes and apply them.
+    fn poll_config(&mut self) {
+        let result = match self.config_watcher.as_mut().and_then(|w| w.poll()) {
+            None => return,
+            Some(config) => config.and_then(|config| self.apply_config(config)),
+        };
+        if let Err(e) = result {
+            self.report_error("config reload, keeping current conditions", e);
+        }
+    }
+
+    /// Pass an error we've decided not to return to the error hook, or print it if there isn't one.
+    fn report_error(&mut self, context: &str, e: anyhow::Error) {
+        #[cfg(all(unix, feature = "journald"))]
+        if let Some(journald) = self.journald.as_ref() {
+            // Nowhere left to report a failure here, journald not running is the likely cause anyway
+            let message = format!("turnstiles caught error in {}: {}", context, e);
+            let _ = journald.send(&message, &self.active_file_path.to_string_lossy());
+        }
+        report_to(self.error_hook.as_mut(), context, e);
+    }
+
+    /// Also send errors which are caught rather than returned to systemd-journald, as warnings tagged with `TURNSTILES_FILE=<active file>`.
+    /// This is in addition to the error hook or printing. If journald isn't running the messages are silently lost.
+    #[cfg(all(unix, feature = "journald"))]
+    pub fn with_journald_errors(mut self) -> Result<Self> {
+        self.journald = Some(journald::Journald::new()?);
+        Ok(self)
+    }
+
+    /// Open the next active file ahead of time, as `<path>.NEXT`, so a rotation only has to rename it into place rather than create a
+    /// file while the write which triggered it waits. A new spare is opened straight after each rotation, on the background thread with
+    /// [`RotatingFile::with_background_rotation`]. The spare is removed when the `RotatingFile` is dropped. Does nothing when writing
+    /// to a FIFO or device, which is never rotated.
+    pub fn with_precreate(mut self) -> Result<Self> {
+        if self.passthrough {
+            return Ok(self);
+        }
+        self.precreate = true;
+        self.spare = Some(self.open_append(&self.spare_file_path())?);
+        Ok(self)
+    }
+
+    /// Give the active file, and every active file after it, these Unix permissions (i.e. `0o640`) rather than leaving them to the
+    /// umask. Rotated files keep them, being the same files renamed, and `CompressRoller` and `EncryptRoller` give their output the
+    /// permissions of the file they started from. Set on the active file straight away. Ignored where files don't have Unix permissions.
+    /// The group is left alone, so to have files take the directory's group set the setgid bit on the directory, or see `with_owner`.
+    pub fn with_mode(mut self, mode: u32) -> Result<Self> {
+        self.mode = Some(mode);
+        self.current_file.set_mode(mode)?;
+        if let Some(spare) = &self.spare {
+            spare.set_mode(mode)?;
+        }
+        Ok(self)
+    }
+
+    /// Change how the active file, and every active file after it, is opened, i.e. to add `O_NOFOLLOW` with
+    /// `std::os::unix::fs::OpenOptionsExt::custom_flags` or a share mode on Windows. `customize` is given the `OpenOptions` already
+    /// set to create and append, and is called for every file opened, so it should be cheap. `custom_flags` replaces the `O_DSYNC`
+    /// which `with_sync_every_write` asks for, so include it if using both. The active file is reopened with it straight away,
+    /// giving the error if it won't open that way. Ignored by filesystems which don't open files with `OpenOptions`, such as
+    /// [`MemoryFileSystem`].
+    pub fn with_open_options(
+        mut self,
+        customize: impl Fn(&mut std::fs::OpenOptions) + Send + Sync + 'static,
+    ) -> Result<Self> {
+        self.open_options = Some(Arc::new(customize));
+        self.flush_buffer()?;
+        self.current_file = self.open_append(&self.active_file_path)?;
+        if self.spare.is_some() {
+            self.spare = Some(self.open_append(&self.spare_file_path())?);
+        }
+        Ok(self)
+    }
+
+    /// Have the active file, and every active file after it, owned by this user and group, leaving either alone if `None`. For a
+    /// daemon which starts as root and drops privileges, so the files it goes on writing belong to the service user. Rotated files
+    /// keep the owner, and `CompressRoller` and `EncryptRoller` give their output the owner of the file they started from. Set on
+    /// the active file straight away, which needs the privileges to do so, so call this before dropping them.
+    #[cfg(all(unix, feature = "ownership"))]
+    pub fn with_owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Result<Self> {
+        self.owner = Some((uid, gid));
+        self.current_file.set_owner(uid, gid)?;
+        if let Some(spare) = &self.spare {
+            spare.set_owner(uid, gid)?;
+        }
+        Ok(self)
+    }
+
+    /// As [`RotatingFile::with_owner`] but by user and group name, looked up now.
+    #[cfg(all(unix, feature = "ownership"))]
+    pub fn with_owner_names(self, user: Option<&str>, group: Option<&str>) -> Result<Self> {
+        let uid = user.map(owner::uid_by_name).transpose()?;
+        let gid = group.map(owner::gid_by_name).transpose()?;
+        self.with_owner(uid, gid)
+    }
+
+    /// Close the active file before renaming it at rotation, rather than renaming it while it's still open. This is the default on
+    /// Windows, where renaming an open file can fail, and can be turned on elsewhere for filesystems with the same problem. The next
+    /// active file is opened under the spare name used by [`RotatingFile::with_precreate`] (or the precreated file is used) and
+    /// swapped in, closing the old one, which is then renamed, retrying for a moment if something else has it open. Only once it's
+    /// moved is the new file renamed into place. If the old file can't be moved it's reopened and written to as before.
+    pub fn with_close_before_rename(mut self) -> Self {
+        self.close_before_rename = true;
+        self
+    }
+
+    /// For log directories on NFS or another network filesystem, where creation times, file attributes and file handles can't be
+    /// relied on in the same way:
+    /// - A `RotationCondition::Duration` counts from when this process opened the active file, by its own clock, rather than going
+    ///   by the creation time on the server, so restarting starts the clock again.
+    /// - The size is only ever counted as we write, never checked against the file's cached length, so something else truncating
+    ///   the file isn't noticed.
+    /// - Rotation takes `<path>.lock`, created exclusively, so processes on different hosts sharing the directory don't rotate at
+    ///   once. A process which finds it taken leaves the rotation to whoever has it and tries again on its next write. A lock left
+    ///   for over a minute, i.e. by a process which died, is broken.
+    /// - A stale file handle (`ESTALE`) while writing reopens the active file by name and writes again, and renames are retried.
+    pub fn with_network_filesystem(mut self) -> Self {
+        self.network_filesystem = true;
+        self.created = Some(SystemTime::now());
+        self.rotation_deadline = None;
+        self
+    }
+
     /// Check we're given valid options on startup
     fn check_options(
         rotation_method: &RotationCondition,
@@ -216,6 +1250,14 @@ impl RotatingFile {
         if let RotationCondition::SizeMB(0) = rotation_method {
             bail!("Invalid option: RotationCondition::SizeMB(0)");
         }
+        if let RotationCondition::SizeMB(size) = rotation_method {
+            if size.checked_mul(BYTES_TO_MB).is_none() {
+                bail!(
+                    "Invalid option: RotationCondition::SizeMB({}) is too large",
+                    size
+                );
+            }
+        }
         if let PruneCondition::MaxFiles(0) = prune_method {
             bail!("Invalid option: PruneCondition::MaxFiles(0)");
         }
@@ -225,16 +1267,16 @@ impl RotatingFile {
     /// Given a filename stem and folder path, list all files which are the `filename.<index>` (where filename includes the extension).
     /// Uses regex to match on `r"^<filename>.[0-9]+$"`
     fn list_rotated_log_files(
+        fs: &FS,
         file_regex: &Regex,
-        folder_path: &str,
-    ) -> Result<Vec<String>, std::io::Error> {
-        let files = fs::read_dir(folder_path)?;
+        folder_path: &Path,
+    ) -> Result<Vec<OsString>, std::io::Error> {
+        let files = fs.read_dir(folder_path)?;
 
         let mut log_files = vec![];
-        for f in files {
-            let filename_str = safe_unwrap_osstr(&f?.file_name())?;
-            if file_regex.is_match(&filename_str) {
-                log_files.push(filename_str);
+        for filename in files {
+            if file_regex.is_match(filename.as_encoded_bytes()) {
+                log_files.push(filename);
             }
         }
 
@@ -246,43 +1288,170 @@ impl RotatingFile {
         self.index
     }
     /// Given a filename stem and folder path find the highest index so where know where to pick up after we left off in a previous incarnation
-    fn detect_latest_file_index(file_regex: &Regex, folder_path: &str) -> Result<FileIndexInt> {
-        let log_files = Self::list_rotated_log_files(file_regex, folder_path)?;
+    fn detect_latest_file_index(
+        fs: &FS,
+        file_regex: &Regex,
+        folder_path: &Path,
+    ) -> Result<FileIndexInt> {
+        let log_files = Self::list_rotated_log_files(fs, file_regex, folder_path)?;
+        Self::latest_file_index(&log_files)
+    }
+
+    fn latest_file_index(log_files: &[OsString]) -> Result<FileIndexInt> {
         let mut max_index = 0;
-        for filename_string in log_files {
-            let i = Self::rotated_file_index(&filename_string)?;
+        for filename_string in log_files.iter().filter(|name| !is_archive(name)) {
+            let i = Self::rotated_file_index(filename_string)?;
             max_index = cmp::max(i, max_index);
         }
 
         Ok(max_index)
     }
 
-    fn rotated_file_index(filename: &str) -> Result<FileIndexInt> {
-        let file_index = match filename.split('.').last() {
-            None => bail!("Found log file ending in '.', can't process index."),
+    fn rotated_file_index(filename: &OsStr) -> Result<FileIndexInt> {
+        let filename = strip_rotated_suffixes(filename);
+        let file_index = match Path::new(filename).extension().and_then(OsStr::to_str) {
+            None => bail!("Found log file without an index, can't process index."),
             Some(s) => s,
         };
         Ok(file_index.parse::<FileIndexInt>()?)
     }
 
-    /// Perform file rotation
-    fn rotate_current_file(&mut self) -> Result<(), std::io::Error> {
+    /// Perform file rotation, returning whether it happened: it doesn't for a FIFO or device, or on a network filesystem when
+    /// another process holds the rotation lock, in which case the active file is left as it is.
+    fn rotate_current_file(&mut self) -> Result<bool, std::io::Error> {
+        if self.passthrough {
+            return Ok(false);
+        }
+        if !self.network_filesystem {
+            return self.rotate_locked().map(|_| true);
+        }
+        let lock = self.parent.join(lock_filename(&self.filename_root));
+        match self.take_rotation_lock(&lock)? {
+            // Another process is rotating, so leave it to them and try again on the next write
+            RotationLock::Busy => Ok(false),
+            RotationLock::Unsupported => self.rotate_locked().map(|_| true),
+            RotationLock::Taken => {
+                let result = self.rotate_locked();
+                if let Err(e) = self.fs.remove_file(&lock) {
+                    self.report_error("removing rotation lock", e.into());
+                }
+                result.map(|_| true)
+            }
+        }
+    }
+
+    /// Take the lock file which stops processes on different hosts rotating at once, see `with_network_filesystem`. Busy if
+    /// another process holds it, though one held for [`STALE_LOCK_AGE`] is taken to be left behind and broken.
+    fn take_rotation_lock(&self, lock: &Path) -> Result<RotationLock, std::io::Error> {
+        for _ in 0..2 {
+            match self.fs.create_new(lock) {
+                Ok(()) => return Ok(RotationLock::Taken),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = self
+                        .fs
+                        .metadata(lock)
+                        .ok()
+                        .and_then(|metadata| metadata.modified?.elapsed().ok())
+                        .is_some_and(|held| held > STALE_LOCK_AGE);
+                    if !stale {
+                        return Ok(RotationLock::Busy);
+                    }
+                    let _ = self.fs.remove_file(lock);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                    return Ok(RotationLock::Unsupported)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(RotationLock::Busy)
+    }
+
+    /// Rotate the active file, holding the rotation lock if there is one.
+    fn rotate_locked(&mut self) -> Result<(), std::io::Error> {
+        #[cfg(feature = "metrics")]
+        let _timer = latency::Timer::start("turnstiles_rotation_seconds");
         // TODO: think about if we want to be more careful here, i.e. append to a random file which may already exist and be a totally different format?
         // Could throw an exception, or print a warning and skip that file index. Who logs the loggers...
 
-        // TODO: fix naughtyness of renaming file while handle still open, should prob be an option which we take and shutdown
+        // Renaming the file with the handle still open is fine on unix, see with_close_before_rename for elsewhere
         // let mut result = || -> Result<(), std::io::Error> {
         // fsync before rotation
-        self.current_file.sync_all()?;
-
-        let new_file = &format!("{}/{}.{}", self.parent, self.filename_root, self.index + 1);
-        fs::rename(&self.active_file_path, new_file)?;
-        self.current_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.active_file_path)?;
+        let next_epoch_id = new_epoch_id();
+        if self.footer {
+            let footer = format!(
+                "# turnstiles epoch {} | ended {} | next epoch {}\n",
+                self.epoch_id,
+                format_rfc3339(SystemTime::now()),
+                next_epoch_id
+            );
+            self.write_file_bytes(footer.as_bytes())?;
+        }
+        self.end_stream()?;
+        self.flush_buffer()?;
+        let mtime = match self.rotated_mtime {
+            Some(RotatedMtime::LastWrite) => self.last_write.take(),
+            Some(RotatedMtime::Rotation) => Some(SystemTime::now()),
+            None => None,
+        };
+        if let Some(mtime) = mtime {
+            if let Err(e) = self.current_file.set_modified(mtime) {
+                self.report_error("setting modified time of rotated file", e.into());
+            }
+        }
+        // With background rotation the old handle is synced on the worker once it's been swapped out
+        if self.background.is_none() {
+            self.current_file.sync_all()?;
+        }
+        let old_path = self.active_file_path.clone();
+        self.run_rotation_hooks(|hook| hook.on_before_rotate(&old_path));
+        self.redetect_index_if_taken()?;
+
+        let rotated_name =
+            rotated_filename(&self.filename_root, self.index + 1, self.rotated_suffix());
+        let new_path = self.parent.join(&rotated_name);
+        let old_file = if self.close_before_rename {
+            self.rotate_closed(&new_path)?;
+            None
+        } else {
+            if self.network_filesystem {
+                rename_retrying(&self.fs, &self.active_file_path, &new_path)?;
+            } else {
+                self.fs.rename(&self.active_file_path, &new_path)?;
+            }
+            let next_file = self.open_next_active_file()?;
+            Some(std::mem::replace(&mut self.current_file, next_file))
+        };
+        let previous_sha256 = self.finish_hash_chain();
+        if let Some(rotated_files) = &mut self.rotated_files {
+            rotated_files.push(rotated_name);
+        }
+        if let (Some(background), Some(old_file)) = (&self.background, old_file) {
+            if let Err(job) = background.send(background::Job::Retire(old_file)) {
+                if let Err(e) = background::run(&self.fs, job) {
+                    self.report_error("syncing rotated file", e.into());
+                }
+            }
+        }
+        // Should be a fresh file, but if something else has created it in the meantime we'll be appending to it
+        let metadata = self.current_file.metadata().ok();
+        self.tally.start(metadata.map_or(0, |m| m.len));
+        self.created = metadata.and_then(|m| self.file_created(m));
+        self.rotation_deadline = None;
         self.index += 1; // Only do this once the above results have passed.
+        self.epoch_id = next_epoch_id;
 
+        if self.banner {
+            let previous = rotated_filename(&self.filename_root, self.index, "");
+            if let Err(e) = self.write_banner(Some(&previous), previous_sha256.as_deref()) {
+                self.report_error("writing banner to new file", e.into());
+            }
+        }
+        self.header_size = self.tally.size;
+        let index = self.index;
+        self.run_rotation_hooks(|hook| hook.on_after_rotate(&old_path, &new_path, index));
+        self.run_rollers(new_path);
+        self.replenish_spare();
         Ok(())
         // };
         // if let Err(e) = result() {
@@ -293,126 +1462,1118 @@ impl RotatingFile {
         // };
     }
 
-    /// Given the RotationCondition chosen when the struct was created, check if a rotation is in order
-    /// NOTE: this currently does no check to see if the file rotation option has changed for a given set of logs, but this will never result in dataloss
-    /// just maybe some confusingly-sized logs
-    fn rotation_required(&mut self) -> bool {
-        // NOTE: we used to fsync before getting metadata for this but was removed as veeery slow, seems reasonable?
-        // Now we juts explicitly fsync before rotation
-        let result = || -> Result<bool, std::io::Error> {
-            let rotate = match self.rotation_method {
-                RotationCondition::None => false,
-                RotationCondition::SizeMB(size) => {
-                    self.current_file.metadata()?.len() > size * BYTES_TO_MB
-                }
-                // RotationCondition::SizeLines(len) => false,
-                RotationCondition::Duration(duration) => {
-                    match self.current_file.metadata()?.created()?.elapsed() {
-                        Ok(elapsed) => elapsed > duration,
-                        Err(e) => {
-                            println!("WARN: failed to determine time since log file created - not rotating, got error {}.", e);
-                            false
-                        }
-                    }
-                }
-            };
-            Ok(rotate)
+    /// If something else has put a file where the next rotation is going, i.e. renumbered the files, re-detect the index from disk
+    /// so it isn't overwritten. Only lists the directory when that happens, otherwise it's a lookup or two.
+    fn redetect_index_if_taken(&mut self) -> Result<(), std::io::Error> {
+        let taken = std::iter::once("")
+            .chain(COMPRESSED_SUFFIXES)
+            .map(|suffix| rotated_filename(&self.filename_root, self.index + 1, suffix))
+            .any(|name| self.fs.metadata(&self.parent.join(name)).is_ok());
+        if taken {
+            self.refresh()?;
+            let latest = Self::latest_file_index(self.rotated_files.as_deref().unwrap_or_default())
+                .map_err(io::Error::other)?;
+            self.index = cmp::max(self.index, latest);
+        }
+        Ok(())
+    }
+
+    fn spare_file_path(&self) -> PathBuf {
+        self.parent.join(spare_filename(&self.filename_root))
+    }
+
+    /// Handle for the new active file once the old one has been renamed, swapping in the spare if there's one ready.
+    fn open_next_active_file(&mut self) -> Result<FS::File, std::io::Error> {
+        let spare = match self.spare.take() {
+            Some(spare) => Some(spare),
+            None => self.background.as_ref().and_then(|b| b.take_spare()),
         };
-        match result() {
-            Ok(r) => r,
-            Err(e) => {
-                println!("WARN: turnstiles caught error in rotation_required(), defaulting to not rotating.\nErr: {}",e);
-                false
+        if let Some(spare) = spare {
+            let spare_path = self.spare_file_path();
+            match self.fs.rename(&spare_path, &self.active_file_path) {
+                Ok(()) => return Ok(spare),
+                Err(e) => self.report_error("swapping in precreated file", e.into()),
             }
         }
+        self.open_append(&self.active_file_path)
     }
 
-    fn prune_logs(&mut self) {
-        // TODO: tidy this horribleness and seek out corner cases
-        let result = || -> Result<(), std::io::Error> {
-            let log_file_list = Self::list_rotated_log_files(&self.file_regex, &self.parent)?;
-            match self.prune_method {
-                PruneCondition::None => {}
-                PruneCondition::MaxAge(d) => {
-                    let modified_cutoff = SystemTime::now() - d;
-                    for filename in log_file_list {
-                        let path = format!("{}/{}", self.parent, filename);
-                        let metadata = fs::metadata(&path)?;
-                        if metadata.modified()? < modified_cutoff {
-                            remove_file(path)?;
-                        }
+    /// Rotate the active file to `rotated` with our handle on it closed first, see `with_close_before_rename`.
+    fn rotate_closed(&mut self, rotated: &Path) -> Result<(), std::io::Error> {
+        let spare_path = self.spare_file_path();
+        let spare = match self.spare.take() {
+            Some(spare) => spare,
+            None => match self.background.as_ref().and_then(|b| b.take_spare()) {
+                Some(spare) => spare,
+                None => self.open_append(&spare_path)?,
+            },
+        };
+        // With background rotation it would be synced on the worker, but it's about to be closed
+        if self.background.is_some() {
+            self.current_file.sync_all()?;
+        }
+        drop(std::mem::replace(&mut self.current_file, spare));
+
+        if let Err(e) = rename_retrying(&self.fs, &self.active_file_path, rotated) {
+            let old_file = self.open_append(&self.active_file_path)?;
+            let spare = std::mem::replace(&mut self.current_file, old_file);
+            if self.precreate {
+                self.spare = Some(spare);
+            } else {
+                drop(spare);
+                let _ = self.fs.remove_file(&spare_path);
+            }
+            return Err(e);
+        }
+        if let Err(e) = rename_retrying(&self.fs, &spare_path, &self.active_file_path) {
+            // Nothing's been written to the spare, so start the active file afresh rather than carry on under the wrong name
+            self.report_error("moving new active file into place", e.into());
+            let active = self.open_append(&self.active_file_path)?;
+            drop(std::mem::replace(&mut self.current_file, active));
+            let _ = self.fs.remove_file(&spare_path);
+        }
+        Ok(())
+    }
+
+    /// Open a new spare file after one has been used, on the background thread if there is one.
+    fn replenish_spare(&mut self) {
+        if !self.precreate {
+            return;
+        }
+        let job = background::Job::OpenSpare {
+            path: self.spare_file_path(),
+            with: self.open_with(),
+        };
+        let job = match &self.background {
+            Some(background) => match background.send(job) {
+                Ok(()) => return,
+                Err(job) => job,
+            },
+            None => job,
+        };
+        match background::run(&self.fs, job) {
+            Ok(spare) => self.spare = spare,
+            Err(e) => self.report_error("opening precreated file", e.into()),
+        }
+    }
+
+    /// Given the RotationCondition chosen when the struct was created, check if a rotation is in order
+    /// NOTE: this currently does no check to see if the file rotation option has changed for a given set of logs, but this will never result in dataloss
+    /// just maybe some confusingly-sized logs
+    fn rotation_required(&mut self) -> bool {
+        // NOTE: we used to fsync before getting metadata for this but was removed as veeery slow, seems reasonable?
+        // Now we juts explicitly fsync before rotation
+        // The trigger is taken out for the call as it needs to look at the rest of self
+        let result = match self.trigger.take() {
+            None if matches!(self.rotation_method, RotationCondition::Duration(_)) => {
+                self.rotation_deadline_passed()
+            }
+            mut trigger => {
+                let result = policy::should_rotate(trigger.as_mut(), &self.rotation_method, self);
+                self.trigger = trigger;
+                result
+            }
+        };
+        // The size is counted as we write rather than asked of the filesystem each time, so before rotating on it check the count
+        // against the file in case something else has truncated or appended to it, i.e. logrotate's copytruncate
+        let result = match result {
+            Ok(true) if self.trigger.is_none() && self.resync_size() => {
+                self.rotation_method.clone().trigger(self)
+            }
+            result => result,
+        };
+        match result {
+            Ok(r) => r,
+            Err(e) => {
+                self.report_error("rotation_required(), defaulting to not rotating", e.into());
+                false
+            }
+        }
+    }
+
+    /// Whether a `RotationCondition::Duration` has come due, only looking at the active file's creation time when the deadline isn't
+    /// already known.
+    fn rotation_deadline_passed(&mut self) -> io::Result<bool> {
+        let deadline = match self.rotation_deadline {
+            Some(deadline) => deadline,
+            None => {
+                let RotationCondition::Duration(max_age) = self.rotation_method else {
+                    return Ok(false);
+                };
+                let age = self.created()?.elapsed().map_err(|e| {
+                    io::Error::other(format!(
+                        "failed to determine time since log file created: {}",
+                        e
+                    ))
+                })?;
+                let deadline = Instant::now() + max_age.saturating_sub(age);
+                *self.rotation_deadline.insert(deadline)
+            }
+        };
+        Ok(Instant::now() > deadline)
+    }
+
+    /// Update the size from the active file's metadata if it's a size based rotation and the two disagree, returning whether it changed.
+    fn resync_size(&mut self) -> bool {
+        // Attributes are cached on the client, so the file's length could be behind what we've written
+        if self.network_filesystem || !matches!(self.rotation_method, RotationCondition::SizeMB(_))
+        {
+            return false;
+        }
+        // Counting bytes before compression, which the file's length can't be checked against
+        #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+        if matches!(&self.stream, Some(stream) if stream.basis == SizeBasis::Uncompressed) {
+            return false;
+        }
+        match self.current_file.metadata() {
+            Ok(metadata) if metadata.len + self.buffer.len() as u64 != self.tally.size => {
+                self.tally.size = metadata.len + self.buffer.len() as u64;
+                true
+            }
+            Ok(_) => false,
+            Err(e) => {
+                self.report_error("checking size of active file", e.into());
+                false
+            }
+        }
+    }
+
+    /// Call each rotation hook, reporting any errors.
+    fn run_rotation_hooks(&mut self, mut f: impl FnMut(&mut dyn RotationHook) -> io::Result<()>) {
+        let mut hooks = std::mem::take(&mut self.rotation_hooks);
+        for (i, hook) in hooks.iter_mut().enumerate() {
+            if let Err(e) = f(hook.as_mut()) {
+                self.report_error(&format!("rotation hook {}", i), e.into());
+            }
+        }
+        self.rotation_hooks = hooks;
+    }
+
+    /// Pass a freshly rotated file down the chain of rollers, reporting rather than returning errors as the rotation has happened.
+    fn run_rollers(&mut self, rotated: PathBuf) {
+        let mut rollers = std::mem::take(&mut self.rollers);
+        let mut path = Some(rotated.clone());
+        for roller in rollers.iter_mut() {
+            let current = match path.take() {
+                Some(current) => current,
+                None => break,
+            };
+            match roller.roll(&current) {
+                Ok(next) => path = next,
+                Err(e) => {
+                    self.report_error(&format!("rolling {}", current.display()), e.into());
+                    // No telling where the file has ended up
+                    self.rotated_files = None;
+                    break;
+                }
+            }
+        }
+        self.rollers = rollers;
+        if path.as_ref() != Some(&rotated) {
+            self.track_rolled_file(&rotated, path.as_deref());
+        }
+    }
+
+    /// Update the cached listing after a roller has moved or removed a rotated file.
+    fn track_rolled_file(&mut self, rotated: &Path, rolled: Option<&Path>) {
+        let parent = self.parent.as_path();
+        let name_in_parent = |path: &Path| {
+            (path.parent() == Some(parent))
+                .then(|| path.file_name())
+                .flatten()
+                .map(OsStr::to_os_string)
+        };
+        let file_regex = &self.file_regex;
+        if let Some(rotated_files) = &mut self.rotated_files {
+            if let Some(name) = name_in_parent(rotated) {
+                rotated_files.retain(|n| *n != name);
+            }
+            if let Some(name) = rolled.and_then(name_in_parent) {
+                if file_regex.is_match(name.as_encoded_bytes()) {
+                    rotated_files.push(name);
+                }
+            }
+        }
+    }
+
+    /// Paths of the rotated files the prune condition says should go, given the latest index. Shared with [`inspect::prune`] so
+    /// offline pruning never disagrees with what the library would do.
+    fn files_to_prune(
+        fs: &FS,
+        file_regex: &Regex,
+        parent: &Path,
+        filename_root: &OsStr,
+        index: FileIndexInt,
+        prune_method: &PruneCondition,
+    ) -> Result<Vec<PathBuf>, std::io::Error> {
+        let log_file_list = Self::list_rotated_log_files(fs, file_regex, parent)?;
+        let to_delete = Self::select_files_to_prune(
+            fs,
+            &log_file_list,
+            parent,
+            filename_root,
+            index,
+            prune_method,
+        )?;
+        Ok(to_delete
+            .into_iter()
+            .map(|name| parent.join(name))
+            .collect())
+    }
+
+    /// Names from `log_file_list` which should go, as for `files_to_prune`.
+    fn select_files_to_prune(
+        fs: &FS,
+        log_file_list: &[OsString],
+        parent: &Path,
+        filename_root: &OsStr,
+        index: FileIndexInt,
+        prune_method: &PruneCondition,
+    ) -> Result<Vec<OsString>, std::io::Error> {
+        // TODO: tidy this horribleness and seek out corner cases
+        let mut to_delete = vec![];
+        match *prune_method {
+            PruneCondition::None => {}
+            PruneCondition::MaxAge(d) => {
+                let modified_cutoff = SystemTime::now() - d;
+                for filename in log_file_list {
+                    let modified =
+                        fs.metadata(&parent.join(filename))?
+                            .modified
+                            .ok_or_else(|| {
+                                io::Error::new(
+                                    io::ErrorKind::Unsupported,
+                                    "modified time not available on this filesystem",
+                                )
+                            })?;
+                    if modified < modified_cutoff {
+                        to_delete.push(filename.clone());
                     }
                 }
-                PruneCondition::MaxFiles(n) => {
-                    let index_u = self.index as usize;
-                    // This works but I hate it; juggling usize stuff
-                    // TODO: invert search to make more performant
-                    if log_file_list.len() > n - 1 && index_u + 2 > 1 + n {
-                        for i in 1..index_u - n + 2 {
-                            let file_to_delete = &format!("{}.{}", self.filename_root, i);
-                            if log_file_list.contains(file_to_delete) {
-                                remove_file(format!("{}/{}", self.parent, file_to_delete))?;
-                            }
+            }
+            PruneCondition::MaxFiles(n) => {
+                let index_u = index as usize;
+                // This works but I hate it; juggling usize stuff
+                if log_file_list.len() > n - 1 && index_u + 2 > 1 + n {
+                    // Go through the files there are rather than every index which could have been pruned, which grows forever
+                    let cutoff = index_u + 1 - n;
+                    for filename in log_file_list {
+                        let i = name_suffix(filename_root, filename.as_encoded_bytes())
+                            .map(|rest| strip_rotated_suffixes(OsStr::new(rest)))
+                            .and_then(|i| i.to_str()?.parse::<usize>().ok());
+                        if matches!(i, Some(i) if (1..=cutoff).contains(&i)) {
+                            to_delete.push(filename.clone());
                         }
                     }
                 }
+                // Each tarball counts as one file, and they're older than any rotated file still loose, so they go first to make
+                // room for what's left
+                let mut archives: Vec<&OsString> = log_file_list
+                    .iter()
+                    .filter(|name| is_archive(name))
+                    .collect();
+                let loose = log_file_list.len() - archives.len() - to_delete.len();
+                let keep = n.saturating_sub(1).saturating_sub(loose);
+                if archives.len() > keep {
+                    archives.sort();
+                    let excess = archives.len() - keep;
+                    to_delete.extend(archives.into_iter().take(excess).cloned());
+                }
+            }
+        };
+        Ok(to_delete)
+    }
+
+    fn prune_logs(&mut self) {
+        if self.passthrough {
+            return;
+        }
+        if let Some(background) = &self.background {
+            let job = background::Job::Prune {
+                file_regex: self.file_regex.clone(),
+                parent: self.parent.clone(),
+                filename_root: self.filename_root.clone(),
+                index: self.index,
+                prune_method: self.prune_method.clone(),
+                #[cfg(feature = "archive")]
+                archive_after: self.archive_after,
             };
-            Ok(())
-        }();
-        match result {
-            Ok(r) => r,
-            Err(e) => {
-                println!("WARN: turnstiles caught error in prune_logs().\nErr: {}", e);
+            // The worker lists the directory itself, so ours is out of date once it's done. If the worker has gone carry on here.
+            if background.send(job).is_ok() {
+                self.rotated_files = None;
+                return;
             }
         }
+        #[cfg(feature = "archive")]
+        self.archive_logs();
+        let result = match self.prune_listed_logs() {
+            // Something else has been at the files since we listed them, so look again
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.refresh().and_then(|_| self.prune_listed_logs())
+            }
+            result => result,
+        };
+        if let Err(e) = result {
+            self.report_error("prune_logs()", e.into());
+        }
     }
 
-    pub fn current_file(&self) -> &File {
-        &self.current_file
+    /// Pack aged rotated files into tarballs, see `with_archive`, keeping the listing up to date.
+    #[cfg(feature = "archive")]
+    fn archive_logs(&mut self) {
+        let Some(older_than) = self.archive_after else {
+            return;
+        };
+        let result = match self.rotated_files.take() {
+            Some(rotated_files) => Ok(rotated_files),
+            None => Self::list_rotated_log_files(&self.fs, &self.file_regex, &self.parent),
+        }
+        .and_then(|mut rotated_files| {
+            let result = archive::archive_logs(
+                &self.parent,
+                &self.filename_root,
+                &rotated_files,
+                older_than,
+            );
+            if let Ok((archived, archives)) = &result {
+                rotated_files.retain(|name| !archived.contains(name));
+                for archive in archives {
+                    if !rotated_files.contains(archive) {
+                        rotated_files.push(archive.clone());
+                    }
+                }
+                self.rotated_files = Some(rotated_files);
+            }
+            result
+        });
+        if let Err(e) = result {
+            // Whatever got as far as being archived is no longer where the listing says
+            self.rotated_files = None;
+            self.report_error("archiving rotated files", e.into());
+        }
     }
 
-    pub fn current_file_path_str(&self) -> &str {
+    /// Remove a rotated file along with its checksum sidecar, if it has one.
+    fn remove_rotated_file(fs: &FS, path: &Path) -> Result<(), std::io::Error> {
+        fs.remove_file(path)?;
+        let Some(name) = path.file_name() else {
+            return Ok(());
+        };
+        if is_archive(name) {
+            return Ok(());
+        }
+        match fs.remove_file(&path.with_file_name(checksum_sidecar(name))) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Prune going by the cached listing, listing the directory only if there isn't one.
+    fn prune_listed_logs(&mut self) -> Result<(), std::io::Error> {
+        let mut rotated_files = match self.rotated_files.take() {
+            Some(rotated_files) => rotated_files,
+            None => Self::list_rotated_log_files(&self.fs, &self.file_regex, &self.parent)?,
+        };
+        let result = Self::select_files_to_prune(
+            &self.fs,
+            &rotated_files,
+            &self.parent,
+            &self.filename_root,
+            self.index,
+            &self.prune_method,
+        )
+        .and_then(|to_delete| {
+            for name in to_delete {
+                Self::remove_rotated_file(&self.fs, &self.parent.join(&name))?;
+                rotated_files.retain(|n| *n != name);
+            }
+            Ok(())
+        });
+        self.rotated_files = Some(rotated_files);
+        result
+    }
+
+    /// List the rotated files again. The listing is kept up to date as files are rotated and pruned, so this is only needed if
+    /// something else adds rotated files, i.e. ones restored from a backup, which would otherwise not be pruned. Files removed by
+    /// something else are noticed when pruning, so don't need this.
+    pub fn refresh(&mut self) -> Result<(), std::io::Error> {
+        self.rotated_files = Some(Self::list_rotated_log_files(
+            &self.fs,
+            &self.file_regex,
+            &self.parent,
+        )?);
+        Ok(())
+    }
+
+    /// Reopen the active file and re-detect the latest index from the files on disk, i.e. after something external has moved or
+    /// deleted log files. Anything held back internally is flushed to the old handle first. The per-file line and record counts start
+    /// again from zero, as the active file may now be a different one.
+    pub fn reopen(&mut self) -> Result<(), std::io::Error> {
+        io::Write::flush(self)?;
+        self.end_stream()?;
+        self.flush_buffer()?;
+        self.refresh()?;
+        let index = Self::latest_file_index(self.rotated_files.as_deref().unwrap_or_default())
+            .map_err(io::Error::other)?;
+        self.current_file = self.open_append(&self.active_file_path)?;
+        let metadata = self.current_file.metadata()?;
+        self.tally.start(metadata.len);
+        self.header_size = 0;
+        self.created = self.file_created(metadata);
+        self.rotation_deadline = None;
+        self.index = index;
+        #[cfg(feature = "checksum")]
+        if let Some(chain) = &mut self.hash_chain {
+            chain.invalidate();
+        }
+        Ok(())
+    }
+
+    /// Flush, then wait for work already handed to background threads to finish: the syncing and pruning with
+    /// [`RotatingFile::with_background_rotation`] and whatever rollers are doing, i.e. `CompressRoller`. For when the `RotatingFile`
+    /// won't be dropped, i.e. it's behind a global logger, see [`FlushGuard`].
+    pub fn drain(&mut self) -> Result<(), std::io::Error> {
+        io::Write::flush(self)?;
+        if let Some(background) = &self.background {
+            background.drain();
+        }
+        let mut rollers = std::mem::take(&mut self.rollers);
+        let result = rollers.iter_mut().try_for_each(|roller| roller.drain());
+        self.rollers = rollers;
+        result
+    }
+
+    /// Shut down cleanly: flush, rotate the active file if [`RotatingFile::with_rotate_on_close`] was set, and wait for background
+    /// work to finish as for `drain`. Unlike dropping, errors are returned rather than reported.
+    pub fn close(mut self) -> Result<(), std::io::Error> {
+        io::Write::flush(&mut self)?;
+        // A file holding only its banner has nothing worth rotating away
+        let rotated = self.rotate_on_close
+            && self.tally.size > self.header_size
+            && self.rotate_current_file()?;
+        if rotated {
+            self.prune_logs();
+        }
+        self.drain()?;
+        // Only the fresh file opened by the rotation is removed, anything not rotated away is left for next time
+        if rotated {
+            self.end_stream()?;
+            self.flush_buffer()?;
+            self.fs.remove_file(&self.active_file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Rotate now, regardless of the rotation condition, then prune as usual. Anything held back internally is flushed into the
+    /// current file first.
+    pub fn rotate(&mut self) -> Result<(), std::io::Error> {
+        io::Write::flush(self)?;
+        self.rotate_current_file()?;
+        self.prune_logs();
+        Ok(())
+    }
+
+    /// Apply the prune condition now rather than waiting for the next rotation. Errors are reported rather than returned, as when
+    /// pruning after a rotation.
+    pub fn prune(&mut self) {
+        self.prune_logs();
+    }
+
+    /// Snapshot of the active file's details.
+    pub fn file_info(&self) -> Result<FileInfo, std::io::Error> {
+        let metadata = self.current_file.metadata()?;
+        Ok(FileInfo {
+            path: self.active_file_path.clone(),
+            size: self.tally.size,
+            created: metadata.created,
+            index: self.index,
+        })
+    }
+
+    /// Make everything written so far durable: flushes anything held back internally (i.e. by deduplication) and then syncs the active
+    /// file's data, but not necessarily its metadata, to disk. See `File::sync_data`. Otherwise the only sync is the one just before rotation.
+    pub fn sync_data(&mut self) -> Result<(), std::io::Error> {
+        io::Write::flush(self)?;
+        self.current_file.sync_data()
+    }
+
+    /// As [`RotatingFile::sync_data`] but also syncs metadata, see `File::sync_all`.
+    pub fn sync_all(&mut self) -> Result<(), std::io::Error> {
+        io::Write::flush(self)?;
+        self.current_file.sync_all()
+    }
+
+    /// Number of newlines written, to the active file and in total. Only counts what this `RotatingFile` has written, so a file picked
+    /// up on restart starts from zero. Includes banner and footer lines if those are enabled.
+    pub fn lines_written(&self) -> WriteCounts {
+        self.tally.lines
+    }
+
+    /// Number of records written, to the active file and in total, where a record is a single call to `write` which made it through
+    /// any filtering. Summary lines written by turnstiles itself (i.e. for rate limiting) count as records.
+    pub fn records_written(&self) -> WriteCounts {
+        self.tally.records
+    }
+
+    /// Size of the active file in bytes. This is counted as bytes are written rather than asked of the filesystem, so it includes anything
+    /// still sitting in OS or internal buffers, and is what size-based rotation goes by. Only when the count says it's time to rotate is it
+    /// checked against the file, and corrected if something else has changed the file's size.
+    pub fn current_file_size(&self) -> u64 {
+        self.tally.size
+    }
+
+    pub fn current_file_path(&self) -> &Path {
         &self.active_file_path
     }
 
-    pub fn current_file_name_str(&self) -> &str {
+    /// As [`RotatingFile::current_file_path`], but empty if the path isn't valid UTF-8.
+    pub fn current_file_path_str(&self) -> &str {
+        self.active_file_path.to_str().unwrap_or_default()
+    }
+
+    pub fn current_file_name(&self) -> &OsStr {
         &self.active_file_name
     }
+
+    /// As [`RotatingFile::current_file_name`], but empty if the name isn't valid UTF-8.
+    pub fn current_file_name_str(&self) -> &str {
+        self.active_file_name.to_str().unwrap_or_default()
+    }
 }
 
-impl io::Write for RotatingFile {
+/// Options which work on rotated files with `std::fs`, so are only there when the files are on disk, see [`DiskFileSystem`].
+impl<FS: DiskFileSystem> RotatingFile<FS> {
+    /// Pack rotated files last modified more than `older_than` ago into a tarball for the month they were last modified,
+    /// `<filename>.<YYYY-MM>.tar.gz`, for when thousands of small files are a pain, i.e. for backups. Done along with pruning, so on
+    /// the background thread with [`RotatingFile::with_background_rotation`]. A month's tarball is rewritten with the new files added
+    /// to the end, and renamed into place once complete. The newest rotated file is always left out so the index can still be
+    /// found from the directory.
+    ///
+    /// Tarballs are pruned along with the rotated files: `PruneCondition::MaxAge` goes by when each was last added to, and
+    /// `PruneCondition::MaxFiles` counts each as one file, removing the oldest months first.
+    #[cfg(feature = "archive")]
+    pub fn with_archive(mut self, older_than: Duration) -> Self {
+        self.archive_after = Some(older_than);
+        self
+    }
+
+    /// Add a `Roller` to run on each file after it's rotated, in the order they were added. The `PruneCondition` is still applied
+    /// afterwards. See the [`Roller`] docs.
+    pub fn with_roller(mut self, roller: impl Roller + Send + 'static) -> Self {
+        self.rollers.push(Box::new(roller));
+        self
+    }
+
+    /// Gzip each file once it's rotated to `<file>.gz` and remove the original, the same as `with_roller(CompressRoller::new()?)`.
+    /// The `.gz` files are still found when working out the index and still pruned by the `PruneCondition`. See `CompressRoller`
+    /// for the details, and use it directly to pick the compression level.
+    #[cfg(feature = "compression")]
+    pub fn with_compression(self) -> Result<Self, std::io::Error> {
+        Ok(self.with_roller(CompressRoller::new()?))
+    }
+
+    /// Encrypt each file once it's rotated to `<file>.age` for an age X25519 recipient (`age1...`) and remove the plaintext, the
+    /// same as `with_roller(EncryptRoller::new(recipient)?)`. See `EncryptRoller` for the details, and use it directly to
+    /// encrypt with a passphrase instead.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(self, recipient: &str) -> Result<Self> {
+        Ok(self.with_roller(EncryptRoller::new(recipient)?))
+    }
+
+    /// Write the SHA-256 of each file as it's rotated to `<file>.sha256` alongside it, the same as
+    /// `with_rotation_hook(ChecksumHook)`. [`inspect::verify`] checks them. See `ChecksumHook` for the details.
+    #[cfg(feature = "checksum")]
+    pub fn with_checksums(self) -> Self {
+        self.with_rotation_hook(ChecksumHook)
+    }
+
+    /// Stamp each file as it's rotated with its index and rotation time as extended attributes, the same as
+    /// `with_rotation_hook(XattrHook)`. See [`XattrHook`] for the details.
+    pub fn with_xattrs(self) -> Self {
+        self.with_rotation_hook(XattrHook)
+    }
+
+    /// Add a hook to be called before and after each rotation, see [`RotationHook`].
+    pub fn with_rotation_hook(mut self, hook: impl RotationHook + Send + 'static) -> Self {
+        self.rotation_hooks.push(Box::new(hook));
+        self
+    }
+}
+
+impl<FS> RotatingFile<FS>
+where
+    FS: FileSystem + Clone + Send + 'static,
+    FS::File: Send + 'static,
+{
+    /// Take the slow parts of rotation off the writing thread. The old file is still renamed and a fresh active file opened straight
+    /// away, as the active file's name has to be free for it, but syncing the old file and pruning happen on a background thread so
+    /// the write which triggers a rotation doesn't pay for them. Errors on the background thread are printed, as they can't reach the
+    /// error hook. Dropping the `RotatingFile` waits for the background work to finish.
+    pub fn with_background_rotation(mut self) -> Result<Self> {
+        self.background = Some(background::Background::new(self.fs.clone())?);
+        Ok(self)
+    }
+}
+
+impl<FS: FileSystem> Drop for RotatingFile<FS> {
+    fn drop(&mut self) {
+        let result = self
+            .write_pending()
+            .and_then(|_| self.end_stream())
+            .and_then(|_| self.flush_buffer());
+        if let Err(e) = result {
+            self.report_error("writing out buffer on drop", e.into());
+        }
+        if self.precreate {
+            // Wait for the background thread first so it can't open the spare again afterwards
+            self.background.take();
+            let _ = self.fs.remove_file(&self.spare_file_path());
+        }
+    }
+}
+
+impl<FS: FileSystem> io::Write for RotatingFile<FS> {
     fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        #[cfg(feature = "metrics")]
+        let _timer = latency::Timer::start("turnstiles_write_seconds");
+        self.poll_config();
+
+        if let Some(limiter) = self.rate_limiter.as_mut() {
+            match limiter.admit(bytes.len()) {
+                Admit::Drop => return Ok(bytes.len()),
+                Admit::WriteWithSummary(n) => {
+                    let summary = format!("turnstiles: {} records suppressed by rate limit\n", n);
+                    self.write_record(summary.as_bytes())?;
+                }
+                Admit::Write => {}
+            }
+        }
+
+        if let Some(sampler) = self.sampler.as_mut() {
+            let keep_every = sampler.sampling.keep_every;
+            match sampler.admit(self.tally.size) {
+                Admit::Drop => return Ok(bytes.len()),
+                Admit::WriteWithSummary(n) => {
+                    let summary = format!(
+                        "turnstiles: sampling 1 in {} records, {} dropped\n",
+                        keep_every, n
+                    );
+                    self.write_record(summary.as_bytes())?;
+                }
+                Admit::Write => {}
+            }
+        }
+
+        let mut data = match self.transformer.as_mut() {
+            Some(transformer) => transformer.transform(bytes),
+            None => Cow::Borrowed(bytes),
+        };
+        // Sanitizing first, so escaping can't take a line back over the cap
+        if let Some(mode) = self.sanitize_mode {
+            data = sanitize(mode, data);
+        }
+        if let Some(truncator) = self.line_truncator.as_mut() {
+            data = truncator.apply(data);
+        }
+        if let Some(deduplicator) = self.deduplicator.as_mut() {
+            data = deduplicator.apply(data);
+        }
+
+        self.write_record(&data)?;
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.write_pending()?;
+        let mut errors = vec![];
+        for (i, tee) in self.tees.iter_mut().enumerate() {
+            if let Err(e) = tee.flush() {
+                errors.push((i, e));
+            }
+        }
+        for (i, e) in errors {
+            self.report_error(&format!("tee {} flush", i), e.into());
+        }
+        self.flush_stream()?;
+        self.flush_buffer()?;
+        self.current_file.flush()?;
+        // Callers flushing at record boundaries makes this a safe place to rotate even if writes don't end in newlines
+        if self.rotate_on_flush && self.rotation_required() {
+            self.rotate_current_file()?;
+            self.prune_logs();
+        }
+        Ok(())
+    }
+}
+
+/// Lets `write!` and friends be used with anything expecting a `fmt::Write`. Strings go through the same path as `io::Write::write`,
+/// so rotation behaves exactly the same. As `fmt::Error` carries no information the underlying `io::Error` is sent to the error hook.
+impl<FS: FileSystem> fmt::Write for RotatingFile<FS> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match io::Write::write_all(self, s.as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.report_error("fmt::Write::write_str()", e.into());
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
+impl<FS: FileSystem> RotatingFile<FS> {
+    /// Write to the active file, keeping track of its size and reporting it to the `on_write` hook.
+    fn write_file_bytes(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+        let counted = match self.stream.as_mut() {
+            Some(stream) => {
+                let compressed = stream.compress(bytes)?;
+                let counted = match stream.basis {
+                    SizeBasis::Uncompressed => bytes.len(),
+                    SizeBasis::Compressed => compressed.len(),
+                };
+                self.write_raw(&compressed)?;
+                counted
+            }
+            None => {
+                self.write_raw(bytes)?;
+                bytes.len()
+            }
+        };
+        #[cfg(not(any(feature = "compression", feature = "zstd", feature = "lz4")))]
+        let counted = {
+            self.write_raw(bytes)?;
+            bytes.len()
+        };
+        self.tally.wrote(bytes, counted as u64);
+        if let Some(hook) = self.on_write.as_mut() {
+            hook(bytes.len(), self.index);
+        }
+        Ok(())
+    }
+
+    /// Write bytes as they are to the active file, through the write buffer and with the chosen durability.
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        let mut result = self.write_raw_unhashed(bytes);
+        if self.network_filesystem && matches!(&result, Err(e) if is_stale_handle(e)) {
+            // The server has lost track of the file handle, which opening the file again by name sorts out
+            self.current_file = self.open_append(&self.active_file_path)?;
+            result = self.write_raw_unhashed(bytes);
+        }
+        #[cfg(feature = "checksum")]
+        if let Some(chain) = &mut self.hash_chain {
+            match result {
+                Ok(()) => chain.update(bytes),
+                // No telling how much got to the file
+                Err(_) => chain.invalidate(),
+            }
+        }
+        result
+    }
+
+    /// As `write_raw` without updating the hash chain.
+    fn write_raw_unhashed(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        match self.durability {
+            Durability::OnRotation if self.buffer_capacity > 0 => {
+                if self.buffer.len() + bytes.len() > self.buffer_capacity {
+                    self.flush_buffer()?;
+                }
+                if bytes.len() >= self.buffer_capacity {
+                    self.current_file.write_all(bytes)?;
+                } else {
+                    self.buffer.extend_from_slice(bytes);
+                }
+            }
+            Durability::OnRotation | Durability::Dsync => self.current_file.write_all(bytes)?,
+            Durability::SyncEachWrite => {
+                self.current_file.write_all(bytes)?;
+                self.current_file.sync_data()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out everything the streaming compression has so far, see `with_streaming_compression`.
+    fn flush_stream(&mut self) -> Result<(), std::io::Error> {
+        #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+        if let Some(stream) = self.stream.as_mut() {
+            let compressed = stream.flush()?;
+            self.write_compressed(&compressed)?;
+        }
+        Ok(())
+    }
+
+    /// Finish the active file's compressed stream, if there is one, ready for it to be rotated or closed.
+    fn end_stream(&mut self) -> Result<(), std::io::Error> {
+        #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+        if let Some(stream) = self.stream.as_mut() {
+            let compressed = stream.finish()?;
+            self.write_compressed(&compressed)?;
+        }
+        Ok(())
+    }
+
+    /// Write out compressed bytes which weren't the result of a write, counting them if that's what the size is based on.
+    #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+    fn write_compressed(&mut self, compressed: &[u8]) -> Result<(), std::io::Error> {
+        self.write_raw(compressed)?;
+        if matches!(&self.stream, Some(stream) if stream.basis == SizeBasis::Compressed) {
+            self.tally.size += compressed.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Added to the names of rotated files, for the codec if the active file is compressed as it's written.
+    fn rotated_suffix(&self) -> &'static str {
+        #[cfg(any(feature = "compression", feature = "zstd", feature = "lz4"))]
+        if let Some(stream) = &self.stream {
+            return stream.suffix();
+        }
+        ""
+    }
+
+    /// Open a file for appending, with `O_DSYNC` if that's what `with_sync_every_write` settled on and the permissions and owner
+    /// we've been given.
+    fn open_append(&self, path: &Path) -> Result<FS::File, std::io::Error> {
+        open_append_with(&self.fs, path, self.open_with())
+    }
+
+    /// When a file we've just opened was created, as far as age based rotation is concerned.
+    fn file_created(&self, metadata: Metadata) -> Option<SystemTime> {
+        if self.network_filesystem {
+            // The server's clock, and it may not record creation at all
+            Some(SystemTime::now())
+        } else {
+            metadata.created_or_modified()
+        }
+    }
+
+    fn open_with(&self) -> OpenWith {
+        OpenWith {
+            dsync: self.durability == Durability::Dsync,
+            mode: self.mode,
+            #[cfg(all(unix, feature = "ownership"))]
+            owner: self.owner,
+            customize: self.open_options.clone(),
+        }
+    }
+
+    /// Pass anything held in the write buffer to the active file.
+    fn flush_buffer(&mut self) -> Result<(), std::io::Error> {
+        if !self.buffer.is_empty() {
+            self.current_file.write_all(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Write bytes to the active file and any tees.
+    fn write_record(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        self.write_to_file(bytes)?;
+        self.tally.record();
+        if self.rotated_mtime == Some(RotatedMtime::LastWrite) {
+            self.last_write = Some(SystemTime::now());
+        }
+        self.write_tees(bytes);
+        Ok(())
+    }
+
+    /// Write out what deduplication is holding back, the partial line and any `last message repeated` summary.
+    fn write_pending(&mut self) -> Result<(), std::io::Error> {
+        if let Some(deduplicator) = self.deduplicator.as_mut() {
+            let pending = deduplicator.pending();
+            if !pending.is_empty() {
+                self.write_record(&pending)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy bytes to the tees, reporting rather than returning errors as the primary write has already succeeded.
+    fn write_tees(&mut self, bytes: &[u8]) {
+        let mut errors = vec![];
+        for (i, tee) in self.tees.iter_mut().enumerate() {
+            if let Err(e) = tee.write_all(bytes) {
+                errors.push((i, e));
+            }
+        }
+        for (i, e) in errors {
+            self.report_error(&format!("tee {}, skipping this write", i), e.into());
+        }
+    }
+
+    /// Write bytes across as many files as needed to keep each under `limit` bytes, splitting according to the oversized write policy.
+    fn write_split(&mut self, bytes: &[u8], limit: u64) -> Result<(), std::io::Error> {
+        let mut rest = bytes;
+        loop {
+            let size = self.tally.size;
+            // Holding nothing but what was written when it was started, so rotating again wouldn't make any room
+            let fresh = size <= self.header_size;
+            let capacity = cmp::min(limit.saturating_sub(size), rest.len() as u64) as usize;
+            if rest.len() == capacity {
+                return self.write_file_bytes(rest);
+            }
+            let split = match self.oversized_write_policy {
+                OversizedWritePolicy::SplitAtLimit | OversizedWritePolicy::Allow => capacity,
+                OversizedWritePolicy::SplitAtNewline => {
+                    match memchr::memrchr(b'\n', &rest[..capacity]) {
+                        Some(i) => i + 1,
+                        // A single record bigger than the limit gets a file to itself
+                        None if fresh => memchr::memchr(b'\n', rest).map_or(rest.len(), |i| i + 1),
+                        None => 0,
+                    }
+                }
+            };
+            self.write_file_bytes(&rest[..split])?;
+            rest = &rest[split..];
+            if rest.is_empty() {
+                return Ok(());
+            }
+            if !self.rotate_current_file()? {
+                // Left to another process for now, so the rest goes in this file rather than trying again straight away
+                return self.write_file_bytes(rest);
+            }
+            self.prune_logs();
+        }
+    }
+
+    /// Write bytes to the active file, rotating first if needed.
+    fn write_to_file(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        if self.passthrough {
+            if !self.passthrough_reported {
+                self.passthrough_reported = true;
+                let e = anyhow::anyhow!(
+                    "{} is a FIFO or device rather than a file, so it's written to as is and never rotated or pruned",
+                    self.active_file_path.display()
+                );
+                self.report_error("opening active file", e);
+            }
+            return self.write_file_bytes(bytes);
+        }
+        if let RotationCondition::SizeMB(size) = self.rotation_method {
+            if self.oversized_write_policy != OversizedWritePolicy::Allow && self.trigger.is_none()
+            {
+                return self.write_split(bytes, size * BYTES_TO_MB);
+            }
+        }
         // Note: only the rotate and write methods here can return errors, the errors in prune and rotation_required are suppressed to try ensure max uptime of logging
         // If rotation_required() fails it will return false so the current file will continue to be written to (or at least, attempted)
-
         if !self.require_newline {
             if self.rotation_required() {
                 self.rotate_current_file()?;
                 self.prune_logs();
             }
-        } else if let Some(last_char) = bytes.last() {
-            // Note this will prevent writing just a newline and so could break some stuff
-            // TODO: be smarter here in future, not sure how best to distinguish between genuine newline write and broken up log from slog async
-            if *last_char == b'\n' && self.rotation_required() {
-                self.rotate_current_file()?;
-                if bytes.len() != 1 {
-                    self.current_file.write_all(bytes)?;
+        } else if let Some(boundary) = memchr::memrchr(b'\n', bytes) {
+            // Only rotate where a record ends, which is the last newline in the buffer rather than necessarily its last byte. With
+            // rotate_on_flush the caller marks the ends of records by flushing, so a newline part way through is left alone.
+            let (complete, rest) = bytes.split_at(boundary + 1);
+            if (rest.is_empty() || !self.rotate_on_flush) && self.rotation_required() {
+                if rest.is_empty() {
+                    // Note this will prevent writing just a newline and so could break some stuff
+                    // TODO: be smarter here in future, not sure how best to distinguish between genuine newline write and broken up log from slog async
+                    self.rotate_current_file()?;
+                    if bytes.len() != 1 {
+                        self.write_file_bytes(bytes)?;
+                    }
+                } else {
+                    // Finish the records in this buffer in the old file and start the partial one in the new
+                    self.write_file_bytes(complete)?;
+                    self.rotate_current_file()?;
+                    self.write_file_bytes(rest)?;
                 }
                 self.prune_logs();
-                return Ok(bytes.len());
+                return Ok(());
             }
         }
 
-        self.current_file.write_all(bytes)?;
-        Ok(bytes.len())
+        self.write_file_bytes(bytes)
     }
-    fn flush(&mut self) -> Result<(), std::io::Error> {
-        self.current_file.flush()
+}
+
+/// Details of the active file at a point in time, from [`RotatingFile::file_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    /// Bytes written to the file, as used for size-based rotation
+    pub size: u64,
+    /// Creation time according to the filesystem, if it supports it
+    pub created: Option<SystemTime>,
+    /// Index the file will be given when rotated, minus one (see [`RotatingFile::index`])
+    pub index: FileIndexInt,
+}
+
+/// Running counts for the active file and across all files, see [`RotatingFile::lines_written`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteCounts {
+    pub current_file: u64,
+    pub total: u64,
+}
+
+impl WriteCounts {
+    fn add(&mut self, n: u64) {
+        self.current_file += n;
+        self.total += n;
+    }
+}
+
+/// Bytes, lines and records written so far, kept the same way by `RotatingFile` and [`Rotating`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Tally {
+    /// Bytes written to the active file or sink
+    pub(crate) size: u64,
+    pub(crate) lines: WriteCounts,
+    pub(crate) records: WriteCounts,
+}
+
+impl Tally {
+    /// Count `bytes` having gone out, `counted` being how much of the size they take up.
+    pub(crate) fn wrote(&mut self, bytes: &[u8], counted: u64) {
+        self.size += counted;
+        self.lines
+            .add(memchr::memchr_iter(b'\n', bytes).count() as u64);
     }
+
+    pub(crate) fn record(&mut self) {
+        self.records.add(1);
+    }
+
+    /// Start counting a new file or sink which already holds `size` bytes.
+    pub(crate) fn start(&mut self, size: u64) {
+        self.size = size;
+        self.lines.current_file = 0;
+        self.records.current_file = 0;
+    }
+}
+
+/// What to do with a write that would take the active file past a size limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedWritePolicy {
+    /// The default: the size is checked before each write and the whole write goes to the same file, so a large write can
+    /// leave a file arbitrarily bigger than the limit.
+    Allow,
+    /// Split the write at the last newline that fits and carry on in the next file, so files stay under the limit and records
+    /// stay whole. A single line bigger than the limit gets a file of its own, after the banner if there is one.
+    SplitAtNewline,
+    /// Split exactly at the limit regardless of content, so no file ever exceeds it. Only sensible for data with no records to break.
+    SplitAtLimit,
+}
+
+/// What to set the modified time of a file to as it's rotated, see [`RotatingFile::with_rotated_mtime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotatedMtime {
+    /// When the last record was written to it, whatever was written after. Left alone if nothing was written since it was opened.
+    LastWrite,
+    /// When it was rotated.
+    Rotation,
+}
+
+/// Standard stream to mirror writes to, see [`RotatingFile::with_mirror`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirror {
+    Stdout,
+    Stderr,
 }
 
 /// Enum for possible file rotation options.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RotationCondition {
     None,
     SizeMB(u64),
@@ -420,7 +2581,7 @@ pub enum RotationCondition {
     // SizeLines(u64),
 }
 /// Enum for possible file prune options.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PruneCondition {
     None,
     MaxFiles(usize),
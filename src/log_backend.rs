@@ -0,0 +1,86 @@
+use crate::{utils::format_rfc3339, SharedRotatingFile};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::{io::Write, time::SystemTime};
+
+const DEFAULT_TEMPLATE: &str = "{timestamp} {level} {target}: {message}";
+
+/// Minimal `log` backend writing through a `RotatingFile`, for when you just want rotating file logs with a single init call.
+///
+/// Each record is formatted with a template, where `{timestamp}` (RFC 3339, UTC), `{level}`, `{target}`, `{module}`, `{file}`,
+/// `{line}` and `{message}` are substituted, and a newline added. The default is `"{timestamp} {level} {target}: {message}"`.
+/// A record is formatted in full before being written in a single call, so records are never split across files.
+#[derive(Debug)]
+pub struct RotatingLogger {
+    file: SharedRotatingFile,
+    level: LevelFilter,
+    template: String,
+}
+
+impl RotatingLogger {
+    pub fn new(file: impl Into<SharedRotatingFile>) -> Self {
+        Self {
+            file: file.into(),
+            level: LevelFilter::Info,
+            template: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+
+    /// Most verbose level to log, `Info` by default.
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    /// Install as the global logger, which can only be done once per process.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut out = self
+            .template
+            .replace("{timestamp}", &format_rfc3339(SystemTime::now()))
+            .replace("{level}", record.level().as_str())
+            .replace("{target}", record.target())
+            .replace("{module}", record.module_path().unwrap_or(""))
+            .replace("{file}", record.file().unwrap_or(""))
+            .replace(
+                "{line}",
+                &record.line().map(|l| l.to_string()).unwrap_or_default(),
+            )
+            // Last so braces in the message aren't treated as placeholders
+            .replace("{message}", &record.args().to_string());
+        out.push('\n');
+        out
+    }
+}
+
+impl Log for RotatingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        let mut file = self.file.lock();
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            file.report_error("log backend, dropping record", e.into());
+        }
+    }
+
+    fn flush(&self) {
+        let mut file = self.file.lock();
+        if let Err(e) = file.flush() {
+            file.report_error("log backend flush", e.into());
+        }
+    }
+}
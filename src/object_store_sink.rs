@@ -0,0 +1,160 @@
+use crate::{FileIndexInt, PruneCondition, SinkFactory};
+use anyhow::{bail, Context, Result};
+use object_store::{path::Path as ObjectPath, ObjectStore};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// `SinkFactory` for rotating straight into object storage, for use with [`Rotating`](crate::Rotating) where there's no persistent
+/// disk. Writes go to a local spool file, `<spool_dir>/<name>.ACTIVE`, and each rotation uploads it as `<prefix>/<name>.<index>`
+/// (the same naming as rotated files on disk) before starting a fresh spool, then prunes old objects according to the
+/// `PruneCondition`. Call `Rotating::finish` at shutdown to upload the last, partial, file.
+///
+/// Indices carry on from the highest already in the bucket, and a spool file left over from a crash is appended to rather than lost.
+/// Uploads happen during the write which triggers rotation, so that write takes as long as the upload.
+pub struct ObjectStoreSpool {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    name: String,
+    spool_path: PathBuf,
+    prune: PruneCondition,
+    /// Highest index in the bucket when we started
+    first_index: FileIndexInt,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl fmt::Debug for ObjectStoreSpool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectStoreSpool")
+            .field("store", &self.store.to_string())
+            .field("prefix", &self.prefix)
+            .field("name", &self.name)
+            .field("spool_path", &self.spool_path)
+            .field("prune", &self.prune)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ObjectStoreSpool {
+    pub fn new(
+        store: Arc<dyn ObjectStore>,
+        prefix: &str,
+        name: &str,
+        spool_dir: impl Into<PathBuf>,
+        prune: PruneCondition,
+    ) -> Result<Self> {
+        if let PruneCondition::MaxFiles(0) = prune {
+            bail!("Invalid option: PruneCondition::MaxFiles(0)");
+        }
+        let spool_dir = spool_dir.into();
+        fs::create_dir_all(&spool_dir)
+            .with_context(|| format!("Could not create spool directory {}", spool_dir.display()))?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let mut spool = Self {
+            store,
+            prefix: ObjectPath::from(prefix),
+            name: name.to_string(),
+            spool_path: spool_dir.join(format!("{}.ACTIVE", name)),
+            prune,
+            first_index: 0,
+            runtime,
+        };
+        spool.first_index = spool
+            .list_rotated()?
+            .iter()
+            .map(|(index, _)| *index)
+            .max()
+            .unwrap_or(0);
+        Ok(spool)
+    }
+
+    /// Rotated objects in the bucket with their index and last modified time in seconds since the epoch.
+    fn list_rotated(&self) -> io::Result<Vec<(FileIndexInt, i64)>> {
+        let listing = self
+            .runtime
+            .block_on(self.store.list_with_delimiter(Some(&self.prefix)))
+            .map_err(io::Error::other)?;
+        let root = format!("{}.", self.name);
+        Ok(listing
+            .objects
+            .iter()
+            .filter_map(|meta| {
+                let index = meta
+                    .location
+                    .filename()?
+                    .strip_prefix(&root)?
+                    .parse()
+                    .ok()?;
+                Some((index, meta.last_modified.timestamp()))
+            })
+            .collect())
+    }
+
+    fn location(&self, index: FileIndexInt) -> ObjectPath {
+        self.prefix.child(format!("{}.{}", self.name, index))
+    }
+
+    fn prune(&self, latest: FileIndexInt) -> io::Result<()> {
+        let to_delete: Vec<FileIndexInt> = match self.prune {
+            PruneCondition::None => return Ok(()),
+            // As with files on disk the active file counts as one
+            PruneCondition::MaxFiles(n) => {
+                let keep_from = (latest + 1).saturating_sub(n as FileIndexInt - 1);
+                self.list_rotated()?
+                    .into_iter()
+                    .filter(|(index, _)| *index < keep_from)
+                    .map(|(index, _)| index)
+                    .collect()
+            }
+            PruneCondition::MaxAge(age) => {
+                let cutoff = SystemTime::now()
+                    .checked_sub(age)
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs() as i64);
+                self.list_rotated()?
+                    .into_iter()
+                    .filter(|(_, modified)| *modified < cutoff)
+                    .map(|(index, _)| index)
+                    .collect()
+            }
+        };
+        for index in to_delete {
+            self.runtime
+                .block_on(self.store.delete(&self.location(index)))
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+impl SinkFactory for ObjectStoreSpool {
+    type Sink = File;
+
+    fn open_next(&mut self, _: FileIndexInt) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool_path)
+    }
+
+    /// Upload the spool file and start a fresh one. If the upload fails the spool file is kept, so its contents are included in the
+    /// next upload rather than lost.
+    fn finish(&mut self, sink: File, index: FileIndexInt) -> io::Result<()> {
+        sink.sync_all()?;
+        drop(sink);
+        let data = fs::read(&self.spool_path)?;
+        let object_index = self.first_index + index + 1;
+        self.runtime
+            .block_on(self.store.put(&self.location(object_index), data.into()))
+            .map_err(io::Error::other)?;
+        fs::remove_file(&self.spool_path)?;
+        self.prune(object_index)
+    }
+}
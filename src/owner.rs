@@ -0,0 +1,95 @@
+//! Changing who owns the files we create, see `RotatingFile::with_owner`.
+use std::{ffi::CString, io, ptr};
+
+/// Largest buffer given to `getpwnam_r`/`getgrnam_r` before giving up, well past any sane entry.
+const MAX_BUFFER: usize = 1 << 20;
+
+/// Call a `get*nam_r` function, growing the buffer for the strings until the entry fits. `call` gives back the error code, or
+/// what it wanted from the entry if there was one.
+fn lookup<T>(
+    mut call: impl FnMut(&mut [libc::c_char]) -> Result<Option<T>, i32>,
+) -> io::Result<Option<T>> {
+    let mut buf = vec![0; 1024];
+    loop {
+        match call(&mut buf) {
+            Ok(found) => return Ok(found),
+            Err(libc::ERANGE) if buf.len() < MAX_BUFFER => buf.resize(buf.len() * 2, 0),
+            Err(code) => return Err(io::Error::from_raw_os_error(code)),
+        }
+    }
+}
+
+fn not_found(kind: &str, name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No {} named '{}'", kind, name),
+    )
+}
+
+/// Uid of a user, going through NSS like everything else so i.e. LDAP users work.
+pub(crate) fn uid_by_name(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(|_| not_found("user", name))?;
+    lookup(|buf| {
+        let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut found = ptr::null_mut();
+        // SAFETY: the name is nul terminated, `buf` is valid for its length, and `found` is only read through if it was set to
+        // point at `passwd`, which means it was filled in
+        let code = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                passwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        match code {
+            0 if found.is_null() => Ok(None),
+            0 => Ok(Some(unsafe { (*found).pw_uid })),
+            code => Err(code),
+        }
+    })?
+    .ok_or_else(|| not_found("user", name))
+}
+
+/// Gid of a group, as `uid_by_name`.
+pub(crate) fn gid_by_name(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(|_| not_found("group", name))?;
+    lookup(|buf| {
+        let mut group = std::mem::MaybeUninit::<libc::group>::uninit();
+        let mut found = ptr::null_mut();
+        // SAFETY: as for `uid_by_name`
+        let code = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                group.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        match code {
+            0 if found.is_null() => Ok(None),
+            0 => Ok(Some(unsafe { (*found).gr_gid })),
+            code => Err(code),
+        }
+    })?
+    .ok_or_else(|| not_found("group", name))
+}
+
+/// Give a file written by a roller the owner of the file it was made from. Only changes anything if they differ, so a process
+/// which isn't root and owns both files isn't asking for something it may not be allowed.
+#[cfg(any(
+    feature = "compression",
+    feature = "zstd",
+    feature = "lz4",
+    feature = "encryption"
+))]
+pub(crate) fn copy_owner(original: &std::fs::File, out: &std::fs::File) -> io::Result<()> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    let (original, current) = (original.metadata()?, out.metadata()?);
+    if (original.uid(), original.gid()) == (current.uid(), current.gid()) {
+        return Ok(());
+    }
+    fchown(out, Some(original.uid()), Some(original.gid()))
+}
@@ -0,0 +1,136 @@
+//! Parsers for human-readable sizes and durations, i.e. `"100MB"` or `"7d"`, as used by the config loaders.
+//!
+//! The conditions themselves can also be parsed from strings of the form `"<kind>: <value>"`:
+//!
+//! | String         | Condition                                  |
+//! |----------------|--------------------------------------------|
+//! | `none`         | `RotationCondition::None`/`PruneCondition::None` |
+//! | `size: 100MB`  | `RotationCondition::SizeMB(100)`           |
+//! | `age: 1d`      | `RotationCondition::Duration(..)`/`PruneCondition::MaxAge(..)` |
+//! | `files: 10`    | `PruneCondition::MaxFiles(10)`             |
+//!
+//! With the `serde` feature enabled this is also how they're deserialized, so they can sit directly in a config struct.
+use crate::{PruneCondition, RotationCondition};
+use anyhow::{bail, Error, Result};
+use std::{str::FromStr, time::Duration};
+
+/// Split `"100MB"` into `(100, "mb")`, allowing whitespace between the number and the unit.
+fn split_number_unit(s: &str) -> Result<(u64, String)> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    if number.is_empty() {
+        bail!("Expected a number at the start of '{}'", s);
+    }
+    Ok((number.parse()?, unit.trim().to_ascii_lowercase()))
+}
+
+/// Parse a size into whole megabytes (where, as for `RotationCondition::SizeMB`, a megabyte is 1024*1024 bytes).
+/// Accepts `MB`, `GB` and `TB` suffixes (case-insensitive, `MiB` etc. also fine), a bare number is taken to be in MB.
+pub fn parse_size_mb(s: &str) -> Result<u64> {
+    let (n, unit) = split_number_unit(s)?;
+    let multiplier: u64 = match unit.as_str() {
+        "" | "m" | "mb" | "mib" => 1,
+        "g" | "gb" | "gib" => 1024,
+        "t" | "tb" | "tib" => 1024 * 1024,
+        _ => bail!("Unrecognised size unit '{}' in '{}'", unit, s),
+    };
+    // Still has to fit in a u64 once converted to bytes
+    match n.checked_mul(multiplier) {
+        Some(mb) if mb.checked_mul(crate::BYTES_TO_MB).is_some() => Ok(mb),
+        _ => bail!("Size '{}' is too large", s),
+    }
+}
+
+/// Parse a duration such as `500ms`, `30s`, `5m`, `12h`, `7d` or `2w`. A bare number is taken to be in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let (n, unit) = split_number_unit(s)?;
+    let secs = match unit.as_str() {
+        "ms" => return Ok(Duration::from_millis(n)),
+        "" | "s" | "sec" | "secs" => 1,
+        "m" | "min" | "mins" => 60,
+        "h" | "hr" | "hrs" => 60 * 60,
+        "d" | "day" | "days" => 24 * 60 * 60,
+        "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+        _ => bail!("Unrecognised duration unit '{}' in '{}'", unit, s),
+    };
+    match n.checked_mul(secs) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => bail!("Duration '{}' is too large", s),
+    }
+}
+
+/// Parse `true`/`false` (or `1`/`0`, `yes`/`no`).
+pub fn parse_bool(s: &str) -> Result<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => bail!("Could not parse '{}' as a bool", s),
+    }
+}
+
+/// Split `"size: 100MB"` into `("size", "100MB")`, a bare `"none"` gives `("none", "")`.
+fn split_kind(s: &str) -> (String, &str) {
+    match s.split_once(':') {
+        Some((kind, value)) => (kind.trim().to_ascii_lowercase(), value.trim()),
+        None => (s.trim().to_ascii_lowercase(), ""),
+    }
+}
+
+impl FromStr for RotationCondition {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match split_kind(s) {
+            (kind, "") if kind == "none" => Ok(RotationCondition::None),
+            (kind, value) if kind == "size" => Ok(RotationCondition::SizeMB(parse_size_mb(value)?)),
+            (kind, value) if kind == "age" || kind == "duration" => {
+                Ok(RotationCondition::Duration(parse_duration(value)?))
+            }
+            _ => bail!("Could not parse '{}' as a RotationCondition, expected 'none', 'size: <size>' or 'age: <duration>'", s),
+        }
+    }
+}
+
+impl FromStr for PruneCondition {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match split_kind(s) {
+            (kind, "") if kind == "none" => Ok(PruneCondition::None),
+            (kind, value) if kind == "files" => Ok(PruneCondition::MaxFiles(value.parse()?)),
+            (kind, value) if kind == "age" => Ok(PruneCondition::MaxAge(parse_duration(value)?)),
+            _ => bail!("Could not parse '{}' as a PruneCondition, expected 'none', 'files: <n>' or 'age: <duration>'", s),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod de {
+    use super::*;
+    use serde::de::{self, Deserialize, Deserializer, Visitor};
+    use std::{fmt, marker::PhantomData};
+
+    /// Deserializes anything FromStr from a string.
+    struct FromStrVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: FromStr<Err = Error>> Visitor<'de> for FromStrVisitor<T> {
+        type Value = T;
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a condition string such as 'size: 100MB', 'age: 7d' or 'files: 10'")
+        }
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<T, E> {
+            v.parse().map_err(E::custom)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RotationCondition {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_str(FromStrVisitor(PhantomData))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for PruneCondition {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_str(FromStrVisitor(PhantomData))
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Rotation split into the two halves log4rs uses: a `Trigger` deciding when the active file is rotated, and a chain of `Roller`s
+//! deciding what happens to the file once it has been. For the common cases `RotationCondition` and `PruneCondition` are simpler,
+//! this is for mixing and matching, i.e. rotating on size while naming rotated files by time:
+//!
+//! ```
+//! use std::io::Write;
+//! use turnstiles::{PruneCondition, RotatingFile, RotationCondition, SizeTrigger, TimestampRoller};
+//! use tempdir::TempDir; // Subcrate provided for testing
+//! let dir = TempDir::new();
+//! let path = &vec![dir.path.clone(), "test.log".to_string()].join("/");
+//! let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+//!     .unwrap()
+//!     .with_trigger(SizeTrigger(1_000_000))
+//!     .with_roller(TimestampRoller::new().with_max_files(5));
+//! file.write_all(b"hello\n").unwrap();
+//! ```
+use crate::{
+    utils::format_rfc3339, FileHandle, FileIndexInt, FileSystem, RotatingFile, RotationCondition,
+    WriteCounts,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// What a `Trigger` gets to look at: the state of whatever is currently being written to, be it a `RotatingFile`'s active file or
+/// the current sink of a [`Rotating`](crate::Rotating).
+pub trait RotationContext {
+    /// Bytes written to the current file or sink
+    fn size(&self) -> u64;
+    /// When the current file or sink was created
+    fn created(&self) -> io::Result<SystemTime>;
+    /// Number of rotations so far
+    fn index(&self) -> FileIndexInt;
+    fn records_written(&self) -> WriteCounts;
+}
+
+impl<FS: FileSystem> RotationContext for RotatingFile<FS> {
+    fn size(&self) -> u64 {
+        self.current_file_size()
+    }
+    fn created(&self) -> io::Result<SystemTime> {
+        // Only ask the filesystem if it couldn't tell us when the file was opened
+        match self.created {
+            Some(created) => Ok(created),
+            None => self
+                .current_file
+                .metadata()?
+                .created_or_modified()
+                .ok_or_else(|| io::Error::other("filesystem does not support creation times")),
+        }
+    }
+    fn index(&self) -> FileIndexInt {
+        RotatingFile::index(self)
+    }
+    fn records_written(&self) -> WriteCounts {
+        RotatingFile::records_written(self)
+    }
+}
+
+/// Decides whether to rotate, checked before each write in place of the `RotationCondition`. Errors are reported to the error hook
+/// and treated as not rotating.
+pub trait Trigger {
+    fn trigger(&mut self, current: &dyn RotationContext) -> io::Result<bool>;
+}
+
+/// Rotate once the active file is bigger than this many bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeTrigger(pub u64);
+
+impl Trigger for SizeTrigger {
+    fn trigger(&mut self, current: &dyn RotationContext) -> io::Result<bool> {
+        Ok(current.size() > self.0)
+    }
+}
+
+/// Rotate once the active file is older than this, going by its creation time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgeTrigger(pub Duration);
+
+impl Trigger for AgeTrigger {
+    fn trigger(&mut self, current: &dyn RotationContext) -> io::Result<bool> {
+        match current.created()?.elapsed() {
+            Ok(elapsed) => Ok(elapsed > self.0),
+            Err(e) => Err(io::Error::other(format!(
+                "failed to determine time since log file created: {}",
+                e
+            ))),
+        }
+    }
+}
+
+impl Trigger for RotationCondition {
+    fn trigger(&mut self, current: &dyn RotationContext) -> io::Result<bool> {
+        match *self {
+            RotationCondition::None => Ok(false),
+            RotationCondition::SizeMB(size) => {
+                SizeTrigger(size.saturating_mul(crate::BYTES_TO_MB)).trigger(current)
+            }
+            RotationCondition::Duration(duration) => AgeTrigger(duration).trigger(current),
+        }
+    }
+}
+
+/// Ask `trigger` whether to rotate, or `condition` if there isn't one, as both `RotatingFile` and [`Rotating`](crate::Rotating) do
+/// before each write.
+pub(crate) fn should_rotate(
+    trigger: Option<&mut Box<dyn Trigger + Send>>,
+    condition: &RotationCondition,
+    current: &dyn RotationContext,
+) -> io::Result<bool> {
+    match trigger {
+        Some(trigger) => trigger.trigger(current),
+        None => condition.clone().trigger(current),
+    }
+}
+
+/// Notified around each rotation, for anything that wants to act on rotated files without changing what happens to them, i.e.
+/// checksumming or notifications. Any number can be registered with `RotatingFile::with_rotation_hook`, and are called in the order
+/// they were added. Errors are reported to the error hook and don't stop the rotation. Only for files on a [`DiskFileSystem`](crate::DiskFileSystem).
+pub trait RotationHook {
+    /// Called just before the active file at `old_path` is renamed, once any footer has been written and the file synced.
+    fn on_before_rotate(&mut self, old_path: &Path) -> io::Result<()> {
+        let _ = old_path;
+        Ok(())
+    }
+
+    /// Called once the file has been renamed from `old_path` to `new_path`, `index` being its index, and a new active file opened.
+    /// Runs before any `Roller`s, so `new_path` is still there.
+    fn on_after_rotate(
+        &mut self,
+        old_path: &Path,
+        new_path: &Path,
+        index: FileIndexInt,
+    ) -> io::Result<()> {
+        let _ = (old_path, new_path, index);
+        Ok(())
+    }
+}
+
+/// Acts on a file which has just been rotated. Given its current path, returns where it ended up for the next roller in the chain,
+/// or `None` if it's gone. Errors are reported to the error hook and stop the rest of the chain, the rotation itself having already
+/// happened. Only for files on a [`DiskFileSystem`](crate::DiskFileSystem).
+pub trait Roller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>>;
+    /// Wait for anything the roller is still doing in the background, see `RotatingFile::drain`. Nothing to wait for by default.
+    fn drain(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Deletes rotated files, so only the active file is ever kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeleteRoller;
+
+impl Roller for DeleteRoller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>> {
+        fs::remove_file(rotated)?;
+        Ok(None)
+    }
+}
+
+/// Renames `test.log.3` to `test.log.20220131T134500Z.3`, i.e. the time of rotation followed by the index so names stay unique.
+/// Renamed files are no longer seen by `PruneCondition`, so use `with_max_files` to limit how many are kept. Must be the first roller
+/// in the chain as it expects the file to still have its index name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimestampRoller {
+    max_files: Option<usize>,
+}
+
+impl TimestampRoller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most this many timestamped files, deleting the oldest.
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+}
+
+impl Roller for TimestampRoller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>> {
+        let name = Path::new(rotated.file_name().unwrap_or_default());
+        let (root, index) = name
+            .file_stem()
+            .zip(name.extension().and_then(|index| index.to_str()))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("expected an index on rotated file '{}'", name.display()),
+                )
+            })?;
+        // Compact form of RFC 3339 as colons aren't allowed in Windows filenames
+        let timestamp: String = format_rfc3339(SystemTime::now())
+            .chars()
+            .filter(|c| *c != '-' && *c != ':')
+            .collect();
+        let renamed = rotated.with_file_name(crate::with_suffix(
+            root,
+            &format!(".{}.{}", timestamp, index),
+        ));
+        fs::rename(rotated, &renamed)?;
+
+        if let Some(max_files) = self.max_files {
+            let dir = renamed.parent().unwrap_or_else(|| Path::new("."));
+            let mut existing = vec![];
+            for entry in fs::read_dir(dir)? {
+                let entry_name = entry?.file_name();
+                if let Some(rest) = crate::name_suffix(root, entry_name.as_encoded_bytes()) {
+                    if let Some((ts, index)) = rest.split_once('.') {
+                        if let Ok(index) = index.parse::<u64>() {
+                            if ts.ends_with('Z') {
+                                existing.push((ts.to_string(), index, entry_name));
+                            }
+                        }
+                    }
+                }
+            }
+            existing.sort();
+            let n_delete = existing.len().saturating_sub(max_files);
+            for (_, _, old) in existing.into_iter().take(n_delete) {
+                fs::remove_file(dir.join(old))?;
+            }
+        }
+        Ok(renamed.exists().then_some(renamed))
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// How much may be written per second before the limit kicks in. A record here is a single call to `write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throughput {
+    BytesPerSec(u64),
+    RecordsPerSec(u64),
+}
+
+/// What to do with a write which would go over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitPolicy {
+    /// Sleep the writing thread until the next window has room.
+    Block,
+    /// Silently drop the write, the count of which is available from `RotatingFile::suppressed_count()`.
+    Drop,
+    /// Drop the write as above, but also write a line saying how many records were suppressed before the next one which makes it through.
+    Summarize,
+}
+
+/// Optional throughput limit for a `RotatingFile`, set with `RotatingFile::with_rate_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub throughput: Throughput,
+    pub policy: LimitPolicy,
+}
+
+/// Fixed-window limiter, windows being one second long.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    window_start: Instant,
+    used: u64,
+    /// Total dropped over the lifetime of the limiter
+    pub suppressed: u64,
+    /// Dropped since the last summary was written
+    pub unreported: u64,
+}
+
+/// Outcome of asking the limiter whether a write can go ahead.
+pub(crate) enum Admit {
+    Write,
+    /// Write, but first write a summary line for this many suppressed records.
+    WriteWithSummary(u64),
+    Drop,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            window_start: Instant::now(),
+            used: 0,
+            suppressed: 0,
+            unreported: 0,
+        }
+    }
+
+    pub fn admit(&mut self, n_bytes: usize) -> Admit {
+        let (cost, max) = match self.limit.throughput {
+            Throughput::BytesPerSec(max) => (n_bytes as u64, max),
+            Throughput::RecordsPerSec(max) => (1, max),
+        };
+        if self.window_start.elapsed() >= WINDOW {
+            self.reset_window();
+        }
+        // Always let something through in a fresh window, otherwise a single write bigger than the limit would never go out
+        if self.used > 0 && self.used.saturating_add(cost) > max {
+            match self.limit.policy {
+                LimitPolicy::Block => {
+                    sleep(WINDOW.saturating_sub(self.window_start.elapsed()));
+                    self.reset_window();
+                }
+                LimitPolicy::Drop | LimitPolicy::Summarize => {
+                    self.suppressed += 1;
+                    self.unreported += 1;
+                    return Admit::Drop;
+                }
+            }
+        }
+        self.used += cost;
+        if self.limit.policy == LimitPolicy::Summarize && self.unreported > 0 {
+            let n = self.unreported;
+            self.unreported = 0;
+            return Admit::WriteWithSummary(n);
+        }
+        Admit::Write
+    }
+
+    fn reset_window(&mut self) {
+        self.window_start = Instant::now();
+        self.used = 0;
+    }
+}
+
+/// When sampling kicks in, see `Sampling`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingTrigger {
+    /// The active file is bigger than this many MB.
+    FileSizeMB(u64),
+    /// More than this many records (calls to `write`) have been made in the current second.
+    RecordsPerSec(u64),
+}
+
+/// Optional sampling for a `RotatingFile`, set with `RotatingFile::with_sampling`. While the trigger condition holds only every
+/// `keep_every`th record is written, and a line saying how many were dropped is written along with the next one kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampling {
+    pub trigger: SamplingTrigger,
+    pub keep_every: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    pub sampling: Sampling,
+    window_start: Instant,
+    records_in_window: u64,
+    /// Records seen while under pressure, to pick every Nth
+    seen: u64,
+    /// Dropped since the last summary
+    unreported: u64,
+    /// Total dropped over the lifetime of the sampler
+    pub dropped: u64,
+}
+
+impl Sampler {
+    pub fn new(sampling: Sampling) -> Self {
+        Self {
+            sampling,
+            window_start: Instant::now(),
+            records_in_window: 0,
+            seen: 0,
+            unreported: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Decide on a record given the current size of the active file in bytes.
+    pub fn admit(&mut self, file_size: u64) -> Admit {
+        if self.window_start.elapsed() >= WINDOW {
+            self.window_start = Instant::now();
+            self.records_in_window = 0;
+        }
+        self.records_in_window += 1;
+        let under_pressure = match self.sampling.trigger {
+            SamplingTrigger::FileSizeMB(mb) => file_size > mb.saturating_mul(crate::BYTES_TO_MB),
+            SamplingTrigger::RecordsPerSec(max) => self.records_in_window > max,
+        };
+        if under_pressure {
+            self.seen += 1;
+            if !self.seen.is_multiple_of(self.sampling.keep_every.max(1)) {
+                self.unreported += 1;
+                self.dropped += 1;
+                return Admit::Drop;
+            }
+        } else {
+            self.seen = 0;
+        }
+        match self.unreported {
+            0 => Admit::Write,
+            n => {
+                self.unreported = 0;
+                Admit::WriteWithSummary(n)
+            }
+        }
+    }
+}
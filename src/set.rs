@@ -0,0 +1,115 @@
+use crate::{config::read_values, config::Config, RotatingFile};
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, io, path::Path};
+
+/// Owns several named `RotatingFile`s, i.e. one per log stream of a service, so they can be flushed, rotated and shut down together.
+///
+/// Operations on all files carry on past a failing file, returning the first error once every file has been tried.
+#[derive(Debug, Default)]
+pub struct RotatingFileSet {
+    files: BTreeMap<String, RotatingFile>,
+}
+
+impl RotatingFileSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a file per name from one config file (see [`RotatingFile::with_config_file`] for the format). Each stream's keys are
+    /// prefixed with its upper-cased name, i.e. `ACCESS_PATH` and `ACCESS_ROTATE_SIZE` for `"access"`, and unprefixed keys apply to
+    /// every stream which doesn't set its own. Every stream needs its own `<NAME>_PATH`.
+    pub fn from_config_file(path: impl AsRef<Path>, names: &[&str]) -> Result<Self> {
+        let values = read_values(path.as_ref())?;
+        let mut set = Self::new();
+        for name in names {
+            let prefix = name.to_ascii_uppercase();
+            let config = Config::from_lookup(&prefix, |key| {
+                values.get(key).cloned().or_else(|| {
+                    // PATH has no default as files can't share one
+                    key.strip_prefix(&format!("{}_", prefix))
+                        .filter(|key| *key != "PATH")
+                        .and_then(|key| values.get(key).cloned())
+                })
+            })?;
+            let file_path = config
+                .path
+                .with_context(|| format!("{}_PATH must be set", prefix))?;
+            let file = RotatingFile::new(
+                &file_path,
+                config.rotation,
+                config.prune,
+                config.require_newline,
+            )?;
+            set.insert(name, file);
+        }
+        Ok(set)
+    }
+
+    /// Add a file, returning any previously under the same name.
+    pub fn insert(&mut self, name: &str, file: RotatingFile) -> Option<RotatingFile> {
+        self.files.insert(name.to_string(), file)
+    }
+
+    /// As `insert`, builder style.
+    pub fn with_file(mut self, name: &str, file: RotatingFile) -> Self {
+        self.insert(name, file);
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<RotatingFile> {
+        self.files.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RotatingFile> {
+        self.files.get(name)
+    }
+
+    /// The file to write to for a stream.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut RotatingFile> {
+        self.files.get_mut(name)
+    }
+
+    /// Names of the files, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|name| name.as_str())
+    }
+
+    /// Run `f` on every file, returning the first error with the name of the file it came from.
+    fn for_each(
+        &mut self,
+        mut f: impl FnMut(&mut RotatingFile) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut first_error = None;
+        for (name, file) in self.files.iter_mut() {
+            if let Err(e) = f(file) {
+                first_error
+                    .get_or_insert_with(|| io::Error::new(e.kind(), format!("{}: {}", name, e)));
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.for_each(io::Write::flush)
+    }
+
+    /// Rotate every file now, regardless of its rotation condition, see [`RotatingFile::rotate`].
+    pub fn rotate_all(&mut self) -> io::Result<()> {
+        self.for_each(|file| file.rotate())
+    }
+
+    /// Apply every file's prune condition now, see [`RotatingFile::prune`].
+    pub fn prune_all(&mut self) {
+        for file in self.files.values_mut() {
+            file.prune();
+        }
+    }
+
+    /// Flush and sync every file to disk before closing them.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.for_each(|file| file.sync_all())
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{FlushGuard, RotatingFile};
+use anyhow::{bail, Result};
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Bytes a shard holds before the thread writing to it commits it itself, by default.
+pub const DEFAULT_SHARD_CAPACITY: usize = 64 * 1024;
+/// How long the committer thread waits after a write before writing out every shard, by default.
+pub const DEFAULT_COMMIT_INTERVAL: Duration = Duration::from_millis(100);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Records written to one shard and not yet committed, kept whole so each is written to the file in one go.
+#[derive(Debug, Default)]
+struct Shard {
+    data: Vec<u8>,
+    /// End of each record in `data`
+    ends: Vec<usize>,
+}
+
+/// Wakes the committer, which otherwise sleeps until there's something to commit so an idle file costs nothing.
+#[derive(Debug, Default)]
+struct Wakeup {
+    state: Mutex<WakeupState>,
+    condvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct WakeupState {
+    /// Something has been written to a shard since the committer last looked
+    pending: bool,
+    /// The last `ShardedRotatingFile` has gone
+    stop: bool,
+}
+
+impl Wakeup {
+    fn notify(&self, f: impl FnOnce(&mut WakeupState)) {
+        f(&mut lock(&self.state));
+        self.condvar.notify_one();
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: Mutex<RotatingFile>,
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity: usize,
+    wakeup: Arc<Wakeup>,
+}
+
+impl Inner {
+    /// Write out everything in a shard. The shard is only taken once the file's lock is held, so two commits of the same shard can't
+    /// pass each other and each thread's records stay in order.
+    fn commit_shard(&self, file: &mut RotatingFile, i: usize) -> io::Result<()> {
+        let shard = std::mem::take(&mut *lock(&self.shards[i]));
+        let mut start = 0;
+        for end in shard.ends {
+            file.write_all(&shard.data[start..end])?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    fn commit_all(&self, file: &mut RotatingFile) -> io::Result<()> {
+        for i in 0..self.shards.len() {
+            self.commit_shard(file, i)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.wakeup.notify(|state| state.stop = true);
+        let file = self.file.get_mut().unwrap_or_else(|e| e.into_inner());
+        let mut result = Ok(());
+        for shard in &mut self.shards {
+            let shard = std::mem::take(shard.get_mut().unwrap_or_else(|e| e.into_inner()));
+            let mut start = 0;
+            for end in shard.ends {
+                result = result.and_then(|_| file.write_all(&shard.data[start..end]));
+                start = end;
+            }
+        }
+        if let Err(e) = result.and_then(|_| file.flush()) {
+            file.report_error("committing shards on drop", e.into());
+        }
+    }
+}
+
+/// Which shard this thread writes to, handed out round robin the first time each thread writes.
+fn shard_index(shards: usize) -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    SHARD.with(|shard| *shard % shards)
+}
+
+/// Options for a [`ShardedRotatingFile`], i.e. `ShardedBuilder::new().with_shards(8)?.finish(file)`.
+#[derive(Debug, Clone)]
+pub struct ShardedBuilder {
+    shards: usize,
+    shard_capacity: usize,
+    commit_interval: Duration,
+}
+
+impl Default for ShardedBuilder {
+    fn default() -> Self {
+        Self {
+            shards: thread::available_parallelism().map_or(4, |n| n.get()),
+            shard_capacity: DEFAULT_SHARD_CAPACITY,
+            commit_interval: DEFAULT_COMMIT_INTERVAL,
+        }
+    }
+}
+
+impl ShardedBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of shards, the number of CPUs by default. Threads beyond this share shards.
+    pub fn with_shards(mut self, shards: usize) -> Result<Self> {
+        if shards == 0 {
+            bail!("Invalid option: 0 shards");
+        }
+        self.shards = shards;
+        Ok(self)
+    }
+
+    /// Bytes a shard holds before the thread filling it commits it to the file itself, [`DEFAULT_SHARD_CAPACITY`] by default.
+    pub fn with_shard_capacity(mut self, shard_capacity: usize) -> Result<Self> {
+        if shard_capacity == 0 {
+            bail!("Invalid option: shard capacity of 0");
+        }
+        self.shard_capacity = shard_capacity;
+        Ok(self)
+    }
+
+    /// How long the committer thread waits after a write before writing out every shard, [`DEFAULT_COMMIT_INTERVAL`] by default.
+    /// This is the longest a record waits before reaching the file unless `flush` is called. With nothing written the committer
+    /// sleeps until there is.
+    pub fn with_commit_interval(mut self, commit_interval: Duration) -> Self {
+        self.commit_interval = commit_interval;
+        self
+    }
+
+    pub fn finish(self, file: RotatingFile) -> io::Result<ShardedRotatingFile> {
+        let wakeup = Arc::new(Wakeup::default());
+        let inner = Arc::new(Inner {
+            file: Mutex::new(file),
+            shards: (0..self.shards).map(|_| Mutex::default()).collect(),
+            shard_capacity: self.shard_capacity,
+            wakeup: wakeup.clone(),
+        });
+        let weak = Arc::downgrade(&inner);
+        thread::Builder::new()
+            .name("turnstiles-committer".to_string())
+            .spawn(move || committer(weak, &wakeup, self.commit_interval))?;
+        Ok(ShardedRotatingFile { inner })
+    }
+}
+
+/// Commits every shard an interval after something is written to one, until the last `ShardedRotatingFile` has gone. Sleeps
+/// until woken while there's nothing written, rather than polling.
+fn committer(inner: Weak<Inner>, wakeup: &Wakeup, interval: Duration) {
+    loop {
+        let mut state = lock(&wakeup.state);
+        while !state.pending && !state.stop {
+            state = wakeup
+                .condvar
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        // Give the interval for more to be written, so they're committed together
+        let deadline = Instant::now() + interval;
+        while !state.stop {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            state = wakeup
+                .condvar
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        if state.stop {
+            // What's left is committed as the last handle is dropped
+            return;
+        }
+        state.pending = false;
+        drop(state);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let mut file = lock(&inner.file);
+        if let Err(e) = inner.commit_all(&mut file) {
+            file.report_error("committing shards", e.into());
+        }
+    }
+}
+
+/// Alternative to [`SharedRotatingFile`](crate::SharedRotatingFile) for many producer threads. Rather than every write taking the
+/// one lock around the `RotatingFile`, each thread appends to one of several shards, and a committer thread moves them into the file
+/// within [`ShardedBuilder::with_commit_interval`] of them being written. A thread which fills its shard commits it there and then, so memory is bounded.
+///
+/// Each `write` is a record and is kept whole, so it's never interleaved with another thread's bytes or split across files, and
+/// rotation is checked for each record as it's committed just as if it had been written directly. Records from the same thread stay
+/// in order, but records from different threads are only ordered by when their shard was committed. Call `flush` to commit
+/// everything written so far.
+///
+/// Cheap to clone. The committer thread stops, and anything left is committed, once the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct ShardedRotatingFile {
+    inner: Arc<Inner>,
+}
+
+impl ShardedRotatingFile {
+    /// With the default options, see [`ShardedBuilder`] to change them.
+    pub fn new(file: RotatingFile) -> io::Result<Self> {
+        ShardedBuilder::new().finish(file)
+    }
+
+    /// Guard which commits every shard, flushes and waits for the file's background work (see `RotatingFile::drain`) when dropped,
+    /// for when this won't be dropped itself, i.e. it's been given to a global logger.
+    pub fn flush_guard(&self) -> FlushGuard {
+        let inner = self.inner.clone();
+        FlushGuard::new(move || {
+            let mut file = lock(&inner.file);
+            if let Err(e) = inner.commit_all(&mut file).and_then(|_| file.drain()) {
+                file.report_error("FlushGuard", e.into());
+            }
+        })
+    }
+
+    /// Commit every shard and take the lock on the underlying `RotatingFile`, i.e. to rotate or look at the index.
+    pub fn lock(&self) -> io::Result<MutexGuard<'_, RotatingFile>> {
+        let mut file = lock(&self.inner.file);
+        self.inner.commit_all(&mut file)?;
+        Ok(file)
+    }
+}
+
+impl io::Write for &ShardedRotatingFile {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        let i = shard_index(self.inner.shards.len());
+        let (first, full) = {
+            let mut shard = lock(&self.inner.shards[i]);
+            let first = shard.data.is_empty();
+            shard.data.extend_from_slice(bytes);
+            let end = shard.data.len();
+            shard.ends.push(end);
+            (first, end >= self.inner.shard_capacity)
+        };
+        // Only the first record into an empty shard needs to wake the committer, it'll pick up the rest along with it
+        if first {
+            self.inner.wakeup.notify(|state| state.pending = true);
+        }
+        if full {
+            let mut file = lock(&self.inner.file);
+            self.inner.commit_shard(&mut file, i)?;
+        }
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.lock()?.flush()
+    }
+}
+
+impl io::Write for ShardedRotatingFile {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        (&*self).write(bytes)
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        (&*self).flush()
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{group_commit::GroupCommit, FlushGuard, RotatingFile};
+use std::{
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Thread-safe handle to a `RotatingFile` which can be cloned and handed out to as many threads as you like.
+///
+/// ## Locking behaviour
+/// All clones share a single `Mutex` around the underlying `RotatingFile`. The lock is taken once per `write`/`flush` call
+/// and held for the whole call, which includes any rotation and pruning triggered by it, so a single `write` is never
+/// interleaved with another thread's bytes and never split across two files. Note `write_all` may call `write` more than once
+/// if the underlying file does a short write, in which case another thread can sneak in between; if you need a whole batch to
+/// be atomic then take the lock yourself with `lock()` and write to the guard.
+///
+/// If a thread panics while holding the lock the mutex is poisoned; rather than propagate the panic to every other writer we
+/// carry on using the inner `RotatingFile`, as its state is only ever updated once the fallible filesystem calls have succeeded.
+#[derive(Debug, Clone)]
+pub struct SharedRotatingFile {
+    inner: Arc<Mutex<RotatingFile>>,
+    commit: Option<Arc<GroupCommit>>,
+}
+
+impl SharedRotatingFile {
+    pub fn new(file: RotatingFile) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(file)),
+            commit: None,
+        }
+    }
+
+    /// Make each `write` and `flush` durable before it returns, as `RotatingFile::with_sync_every_write` does, but with writes from
+    /// different threads sharing a sync. A worker thread syncs everything written so far while writers wait with the lock released,
+    /// so the writes which come in during one sync are all covered by the next, rather than each paying for its own.
+    ///
+    /// Only covers writes through this handle's `io::Write` (and clones made after this is called), not ones made to the guard from
+    /// `lock()`, which includes the tracing `MakeWriter`. Any `RotatingFile::with_write_buffer` is written out on every write. If a
+    /// sync fails the error is returned from every write from then on, as there's no telling what made it to disk.
+    pub fn with_group_commit(mut self) -> io::Result<Self> {
+        self.commit = Some(Arc::new(GroupCommit::new()?));
+        Ok(self)
+    }
+
+    /// Take the lock on the underlying `RotatingFile`, i.e. for a batch of writes or to inspect the index.
+    pub fn lock(&self) -> MutexGuard<'_, RotatingFile> {
+        match self.inner.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Guard which flushes this file and waits for its background work (see `RotatingFile::drain`) when dropped, for when the
+    /// file itself won't be, i.e. it's been given to a global logger.
+    pub fn flush_guard(&self) -> FlushGuard {
+        let file = self.clone();
+        FlushGuard::new(move || {
+            // Through io::Write first so it waits for any group commit
+            let result = io::Write::flush(&mut &file).and_then(|_| file.lock().drain());
+            if let Err(e) = result {
+                file.lock().report_error("FlushGuard", e.into());
+            }
+        })
+    }
+
+    /// Boxed writer for `env_logger::Target::Pipe`, which wants a `Box<dyn Write + Send>`. env_logger formats each record in full
+    /// before writing it, so records aren't split across files. The box holds a clone of this handle, so keep the original to
+    /// flush, rotate or inspect the file.
+    pub fn pipe_target(&self) -> Box<dyn io::Write + Send> {
+        Box::new(self.clone())
+    }
+}
+
+impl From<RotatingFile> for SharedRotatingFile {
+    fn from(file: RotatingFile) -> Self {
+        Self::new(file)
+    }
+}
+
+impl SharedRotatingFile {
+    /// Do something with the lock held, then with group commit wait for it to be synced once the lock is released.
+    fn committed<T>(&self, f: impl FnOnce(&mut RotatingFile) -> io::Result<T>) -> io::Result<T> {
+        let (result, ticket) = {
+            let mut file = self.lock();
+            let result = f(&mut file)?;
+            let ticket = match &self.commit {
+                Some(commit) => Some(commit.enqueue(&mut file)?),
+                None => None,
+            };
+            (result, ticket)
+        };
+        if let (Some(commit), Some(ticket)) = (&self.commit, ticket) {
+            commit.wait(ticket)?;
+        }
+        Ok(result)
+    }
+}
+
+impl io::Write for &SharedRotatingFile {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        self.committed(|file| file.write(bytes))
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.committed(|file| file.flush())
+    }
+}
+
+impl io::Write for SharedRotatingFile {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        (&*self).write(bytes)
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        (&*self).flush()
+    }
+}
+
+#[cfg(feature = "reopen")]
+impl SharedRotatingFile {
+    /// Wrap in a [`reopen::Reopen`], so daemons which already hook SIGHUP up to a `reopen::Handle` get the handle refreshed and the
+    /// index re-detected (see `RotatingFile::reopen`) in one go. Other clones of this `SharedRotatingFile` see the reopened file too.
+    pub fn into_reopen(self) -> io::Result<reopen::Reopen<SharedRotatingFile>> {
+        reopen::Reopen::new(Box::new(move || {
+            self.lock().reopen()?;
+            if let Some(commit) = &self.commit {
+                commit.reset();
+            }
+            Ok(self.clone())
+        }))
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    policy::{self, RotationContext, Trigger},
+    report_to, ErrorHook, FileIndexInt, RotationCondition, Tally, WriteCounts,
+};
+use anyhow::{bail, Result};
+use std::{
+    fmt,
+    io::{self, Write},
+    time::SystemTime,
+};
+
+/// Creates the sinks a [`Rotating`] writes to, i.e. gzip encoders, network connections or in-memory buffers.
+pub trait SinkFactory {
+    type Sink: Write;
+
+    /// Open the sink to write to next, `index` being the number of rotations so far (so 0 for the first).
+    fn open_next(&mut self, index: FileIndexInt) -> io::Result<Self::Sink>;
+
+    /// Called with a sink once it's been rotated away from, i.e. to finish a compressed stream or close a connection. By default
+    /// it's flushed and dropped.
+    fn finish(&mut self, mut sink: Self::Sink, index: FileIndexInt) -> io::Result<()> {
+        let _ = index;
+        sink.flush()
+    }
+}
+
+/// The rotation conditions and bookkeeping of a `RotatingFile`, but writing to any `io::Write` given by a `SinkFactory` rather than
+/// files on disk. As there are no files there's no pruning, renaming or any of the file-specific options, which is left to the factory.
+///
+/// Rotation is checked before each write and a write always goes to a single sink, so write whole records at a time.
+/// `RotationCondition::Duration` goes by when the sink was opened.
+pub struct Rotating<F: SinkFactory> {
+    factory: F,
+    /// Only `None` if opening the next sink failed, in which case we try again on the next write
+    sink: Option<F::Sink>,
+    rotation_method: RotationCondition,
+    trigger: Option<Box<dyn Trigger + Send>>,
+    index: FileIndexInt,
+    tally: Tally,
+    opened: SystemTime,
+    error_hook: Option<ErrorHook>,
+}
+
+impl<F: SinkFactory> fmt::Debug for Rotating<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rotating")
+            .field("rotation_method", &self.rotation_method)
+            .field("index", &self.index)
+            .field("current_size", &self.tally.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: SinkFactory> Rotating<F> {
+    /// Opens the first sink straight away.
+    pub fn new(mut factory: F, rotation_method: RotationCondition) -> Result<Self> {
+        if let RotationCondition::SizeMB(0) = rotation_method {
+            bail!("Invalid option: RotationCondition::SizeMB(0)");
+        }
+        let sink = factory.open_next(0)?;
+        Ok(Self {
+            factory,
+            sink: Some(sink),
+            rotation_method,
+            trigger: None,
+            index: 0,
+            tally: Tally::default(),
+            opened: SystemTime::now(),
+            error_hook: None,
+        })
+    }
+
+    /// Send errors which are caught rather than returned to a callback instead of printing them, as for `RotatingFile::with_error_hook`.
+    pub fn with_error_hook(
+        mut self,
+        hook: impl FnMut(&str, &anyhow::Error) + Send + 'static,
+    ) -> Self {
+        self.error_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Decide when to rotate with a `Trigger` rather than the `RotationCondition`, as for `RotatingFile::with_trigger`.
+    pub fn with_trigger(mut self, trigger: impl Trigger + Send + 'static) -> Self {
+        self.trigger = Some(Box::new(trigger));
+        self
+    }
+
+    /// Finish the current sink and open the next, regardless of the rotation condition.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        if let Some(sink) = self.sink.take() {
+            self.factory.finish(sink, self.index)?;
+        }
+        self.index += 1;
+        self.open_sink()
+    }
+
+    fn open_sink(&mut self) -> io::Result<()> {
+        self.sink = Some(self.factory.open_next(self.index)?);
+        self.tally.start(0);
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+
+    /// Whether to rotate before the next write. As with `RotatingFile`, an error from the trigger is reported and taken to mean no.
+    fn rotation_required(&mut self) -> bool {
+        let mut trigger = self.trigger.take();
+        let result = policy::should_rotate(trigger.as_mut(), &self.rotation_method, self);
+        self.trigger = trigger;
+        match result {
+            Ok(r) => r,
+            Err(e) => {
+                report_to(
+                    self.error_hook.as_mut(),
+                    "rotation_required(), defaulting to not rotating",
+                    e.into(),
+                );
+                false
+            }
+        }
+    }
+
+    /// Finish the current sink, i.e. at shutdown, returning the factory.
+    pub fn finish(mut self) -> io::Result<F> {
+        if let Some(sink) = self.sink.take() {
+            self.factory.finish(sink, self.index)?;
+        }
+        Ok(self.factory)
+    }
+
+    /// Number of rotations so far.
+    pub fn index(&self) -> FileIndexInt {
+        self.index
+    }
+
+    /// Bytes written to the current sink.
+    pub fn current_size(&self) -> u64 {
+        self.tally.size
+    }
+
+    /// As [`RotatingFile::lines_written`](crate::RotatingFile::lines_written).
+    pub fn lines_written(&self) -> WriteCounts {
+        self.tally.lines
+    }
+
+    /// As [`RotatingFile::records_written`](crate::RotatingFile::records_written).
+    pub fn records_written(&self) -> WriteCounts {
+        self.tally.records
+    }
+
+    /// The current sink, if one is open.
+    pub fn get_ref(&self) -> Option<&F::Sink> {
+        self.sink.as_ref()
+    }
+
+    pub fn factory(&self) -> &F {
+        &self.factory
+    }
+}
+
+impl<F: SinkFactory> RotationContext for Rotating<F> {
+    fn size(&self) -> u64 {
+        self.tally.size
+    }
+    fn created(&self) -> io::Result<SystemTime> {
+        Ok(self.opened)
+    }
+    fn index(&self) -> FileIndexInt {
+        self.index
+    }
+    fn records_written(&self) -> WriteCounts {
+        self.tally.records
+    }
+}
+
+impl<F: SinkFactory> Write for Rotating<F> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.sink.is_none() {
+            self.open_sink()?;
+        } else if self.rotation_required() {
+            self.rotate()?;
+        }
+        let sink = self
+            .sink
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no sink open"))?;
+        sink.write_all(bytes)?;
+        self.tally.wrote(bytes, bytes.len() as u64);
+        self.tally.record();
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Tee which forwards records to syslog, see `SyslogTee`.
+use crate::utils::{format_rfc3339, hostname};
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    time::SystemTime,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixDatagram, path::PathBuf};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Message format for `SyslogTee`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFormat {
+    /// BSD syslog, `<PRI>Mmm dd hh:mm:ss HOST APP[PID]: MSG`.
+    Rfc3164,
+    /// `<PRI>1 TIMESTAMP HOST APP PID - - MSG`.
+    Rfc5424,
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram, PathBuf),
+}
+
+/// Writer which sends each line to a syslog daemon as its own message, for use with `RotatingFile::with_tee` so records go to central
+/// syslog as well as the local files. Partial lines are held back until their newline arrives, or until `flush`.
+///
+/// Timestamps are in UTC. Messages are sent at `user.info` unless changed with `with_facility` and `with_severity`.
+#[derive(Debug)]
+pub struct SyslogTee {
+    transport: Transport,
+    format: SyslogFormat,
+    facility: u8,
+    severity: u8,
+    hostname: String,
+    app_name: String,
+    partial: Vec<u8>,
+}
+
+impl SyslogTee {
+    fn new(transport: Transport, format: SyslogFormat) -> Self {
+        let app_name = std::env::current_exe()
+            .ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "turnstiles".to_string());
+        Self {
+            transport,
+            format,
+            facility: 1,
+            severity: 6,
+            hostname: hostname(),
+            app_name,
+            partial: vec![],
+        }
+    }
+
+    /// Send to a syslog server over UDP, i.e. `"logs.example.com:514"`.
+    pub fn udp(addr: impl ToSocketAddrs, format: SyslogFormat) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        Ok(Self::new(Transport::Udp(socket), format))
+    }
+
+    /// Send to a local syslog daemon over a UNIX datagram socket, usually `/dev/log`.
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<PathBuf>, format: SyslogFormat) -> io::Result<Self> {
+        Ok(Self::new(
+            Transport::Unix(UnixDatagram::unbound()?, path.into()),
+            format,
+        ))
+    }
+
+    /// Facility code, 0 to 23, i.e. 16 for `local0`. Anything higher is clamped.
+    pub fn with_facility(mut self, facility: u8) -> Self {
+        self.facility = facility.min(23);
+        self
+    }
+
+    /// Severity code, 0 (emergency) to 7 (debug). Anything higher is clamped.
+    pub fn with_severity(mut self, severity: u8) -> Self {
+        self.severity = severity.min(7);
+        self
+    }
+
+    /// Name to send messages under, by default the name of the executable.
+    pub fn with_app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.app_name = app_name.into();
+        self
+    }
+
+    fn format_message(&self, line: &[u8]) -> Vec<u8> {
+        let pri = u32::from(self.facility) * 8 + u32::from(self.severity);
+        let timestamp = format_rfc3339(SystemTime::now());
+        let header = match self.format {
+            SyslogFormat::Rfc3164 => {
+                // From YYYY-MM-DDThh:mm:ssZ to Mmm dd hh:mm:ss, with the day padded by a space
+                let month = timestamp[5..7].parse::<usize>().unwrap_or(1);
+                let day = timestamp[8..10].trim_start_matches('0');
+                format!(
+                    "<{}>{} {:>2} {} {} {}[{}]: ",
+                    pri,
+                    MONTHS[month.saturating_sub(1) % 12],
+                    day,
+                    &timestamp[11..19],
+                    self.hostname,
+                    self.app_name,
+                    std::process::id()
+                )
+            }
+            SyslogFormat::Rfc5424 => format!(
+                "<{}>1 {} {} {} {} - - ",
+                pri,
+                timestamp,
+                self.hostname,
+                self.app_name,
+                std::process::id()
+            ),
+        };
+        let mut message = header.into_bytes();
+        message.extend_from_slice(line);
+        message
+    }
+
+    fn send(&self, line: &[u8]) -> io::Result<()> {
+        let message = self.format_message(line);
+        match &self.transport {
+            Transport::Udp(socket) => socket.send(&message)?,
+            #[cfg(unix)]
+            Transport::Unix(socket, path) => socket.send_to(&message, path)?,
+        };
+        Ok(())
+    }
+}
+
+impl io::Write for SyslogTee {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            match line.strip_suffix(b"\n") {
+                Some(line) => {
+                    if self.partial.is_empty() {
+                        if !line.is_empty() {
+                            self.send(line)?;
+                        }
+                    } else {
+                        let mut full = std::mem::take(&mut self.partial);
+                        full.extend_from_slice(line);
+                        self.send(&full)?;
+                    }
+                }
+                None => self.partial.extend_from_slice(line),
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            self.send(&partial)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{RotatingFile, SharedRotatingFile};
+use std::{io, sync::MutexGuard};
+use tracing_core::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Writer handed to `tracing_subscriber` for a single event, holding the lock on the `RotatingFile` until it's dropped so each event is
+/// written in one piece and never split across a rotation.
+pub struct SharedRotatingFileGuard<'a> {
+    guard: MutexGuard<'a, RotatingFile>,
+}
+
+impl io::Write for SharedRotatingFileGuard<'_> {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize, std::io::Error> {
+        self.guard.write(bytes)
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.guard.flush()
+    }
+}
+
+/// Lets a `SharedRotatingFile` be passed straight to `tracing_subscriber::fmt().with_writer(...)`.
+impl<'a> MakeWriter<'a> for SharedRotatingFile {
+    type Writer = SharedRotatingFileGuard<'a>;
+    fn make_writer(&'a self) -> Self::Writer {
+        SharedRotatingFileGuard { guard: self.lock() }
+    }
+}
+
+/// `MakeWriter` sending events at or above a level (i.e. `ERROR`) to one file and everything else to another, each with their own
+/// rotation and prune settings, so one subscriber can write `app.error.log` and `app.log`.
+#[derive(Debug, Clone)]
+pub struct LevelRouter {
+    severe: SharedRotatingFile,
+    rest: SharedRotatingFile,
+    threshold: Level,
+}
+
+impl LevelRouter {
+    /// Events at `threshold` or more severe go to `severe`, the rest to `rest`.
+    pub fn new(
+        severe: impl Into<SharedRotatingFile>,
+        rest: impl Into<SharedRotatingFile>,
+        threshold: Level,
+    ) -> Self {
+        Self {
+            severe: severe.into(),
+            rest: rest.into(),
+            threshold,
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LevelRouter {
+    type Writer = SharedRotatingFileGuard<'a>;
+    /// Only used when there's no event to go by, so goes to the non-severe file.
+    fn make_writer(&'a self) -> Self::Writer {
+        self.rest.make_writer()
+    }
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        // tracing orders levels by verbosity, so more severe compares as less
+        if *meta.level() <= self.threshold {
+            self.severe.make_writer()
+        } else {
+            self.rest.make_writer()
+        }
+    }
+}
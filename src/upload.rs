@@ -0,0 +1,280 @@
+//! Shipping rotated files elsewhere, see `UploadRoller`.
+use crate::{ErrorHook, Roller};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::Duration,
+};
+
+/// Sends a rotated file somewhere, i.e. object storage. Called from `UploadRoller`'s background thread, so it's fine to block.
+pub trait Uploader {
+    fn upload(&mut self, path: &Path) -> anyhow::Result<()>;
+}
+
+/// What to do with the local copy once a file has been uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AfterUpload {
+    Keep,
+    /// Delete the local file, only once the upload has succeeded. Files which fail to upload are always kept.
+    Delete,
+}
+
+/// Settings for `UploadRoller`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadPolicy {
+    /// Attempts after the first before giving up on a file
+    pub retries: u32,
+    /// Wait before the first retry, doubling after each one
+    pub backoff: Duration,
+    pub after_upload: AfterUpload,
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            after_upload: AfterUpload::Keep,
+        }
+    }
+}
+
+/// `Roller` handing each rotated file to an `Uploader` on a background thread, so uploads don't hold up writes. Failed uploads are
+/// retried with exponential backoff according to the `UploadPolicy`, and if they still fail passed to the error hook, or printed as
+/// warnings if there isn't one. When dropped it waits for queued uploads to finish.
+///
+/// The file is passed on unchanged to any later rollers, so put this last in the chain; as uploads happen later a later roller
+/// (or the `PruneCondition`) removing the file would race with them.
+pub struct UploadRoller {
+    sender: Option<Sender<PathBuf>>,
+    handle: Option<JoinHandle<()>>,
+    error_hook: Arc<Mutex<Option<ErrorHook>>>,
+}
+
+impl fmt::Debug for UploadRoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadRoller").finish_non_exhaustive()
+    }
+}
+
+impl UploadRoller {
+    pub fn new(uploader: impl Uploader + Send + 'static, policy: UploadPolicy) -> io::Result<Self> {
+        let (sender, receiver) = channel::<PathBuf>();
+        let error_hook: Arc<Mutex<Option<ErrorHook>>> = Arc::new(Mutex::new(None));
+        let worker_hook = error_hook.clone();
+        let mut uploader = uploader;
+        let handle = thread::Builder::new()
+            .name("turnstiles-upload".to_string())
+            .spawn(move || {
+                for path in receiver {
+                    if let Err((context, e)) = upload_with_retries(&mut uploader, &path, &policy) {
+                        let mut hook = worker_hook.lock().unwrap_or_else(|e| e.into_inner());
+                        match hook.as_mut() {
+                            Some(hook) => hook(&context, &e),
+                            None => println!(
+                                "WARN: turnstiles caught error in {}.\nErr: {}",
+                                context, e
+                            ),
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            error_hook,
+        })
+    }
+
+    /// Send failed uploads here rather than printing them, as for `RotatingFile::with_error_hook`. Called from the upload thread.
+    pub fn with_error_hook(self, hook: impl FnMut(&str, &anyhow::Error) + Send + 'static) -> Self {
+        *self.error_hook.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(hook));
+        self
+    }
+}
+
+/// Upload a file, retrying as the policy says, returning the context and error if it couldn't be done.
+fn upload_with_retries(
+    uploader: &mut impl Uploader,
+    path: &Path,
+    policy: &UploadPolicy,
+) -> Result<(), (String, anyhow::Error)> {
+    let mut backoff = policy.backoff;
+    let mut attempt = 0;
+    loop {
+        match uploader.upload(path) {
+            Ok(()) => break,
+            Err(e) if attempt == policy.retries => {
+                return Err((format!("uploading {}, giving up", path.display()), e));
+            }
+            Err(_) => {
+                sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
+        }
+    }
+    if policy.after_upload == AfterUpload::Delete {
+        fs::remove_file(path).map_err(|e| {
+            (
+                format!("deleting uploaded file {}", path.display()),
+                e.into(),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+impl Roller for UploadRoller {
+    fn roll(&mut self, rotated: &Path) -> io::Result<Option<PathBuf>> {
+        let sent = self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(rotated.to_path_buf()).is_ok());
+        if !sent {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "turnstiles upload thread has stopped",
+            ));
+        }
+        Ok(Some(rotated.to_path_buf()))
+    }
+}
+
+impl Drop for UploadRoller {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish what's queued and stop
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Uploads to any `object_store` backend (S3, GCS, Azure, ...) as `<prefix>/<file name>`. Enable the backend you need with a
+/// dependency on `object_store` in your own crate, i.e. `features = ["aws"]`.
+#[cfg(feature = "object-store")]
+pub struct ObjectStoreUploader {
+    store: std::sync::Arc<dyn object_store::ObjectStore>,
+    prefix: String,
+    runtime: tokio::runtime::Runtime,
+}
+
+#[cfg(feature = "object-store")]
+impl fmt::Debug for ObjectStoreUploader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObjectStoreUploader")
+            .field("store", &self.store.to_string())
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "object-store")]
+impl ObjectStoreUploader {
+    /// The uploader runs its own single threaded runtime for the async `object_store` calls, so doesn't need to be called from one.
+    pub fn new(
+        store: std::sync::Arc<dyn object_store::ObjectStore>,
+        prefix: impl Into<String>,
+    ) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            store,
+            prefix: prefix.into(),
+            runtime,
+        })
+    }
+}
+
+#[cfg(feature = "object-store")]
+impl Uploader for ObjectStoreUploader {
+    fn upload(&mut self, path: &Path) -> anyhow::Result<()> {
+        use anyhow::Context;
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .with_context(|| format!("invalid file name {}", path.display()))?;
+        let location = object_store::path::Path::from(format!(
+            "{}/{}",
+            self.prefix.trim_end_matches('/'),
+            name
+        ));
+        let data = fs::read(path)?;
+        self.runtime
+            .block_on(self.store.put(&location, data.into()))?;
+        Ok(())
+    }
+}
+
+/// POSTs each file to an HTTP(S) endpoint, optionally gzipped, with the file name in an `X-Turnstiles-Filename` header. Any non-2xx
+/// response counts as a failure, to be retried by the `UploadRoller`.
+#[cfg(feature = "http")]
+#[derive(Debug)]
+pub struct HttpUploader {
+    url: String,
+    headers: Vec<(String, String)>,
+    gzip: bool,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http")]
+impl HttpUploader {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: vec![],
+            gzip: false,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    /// Add a header to every request, i.e. `("Authorization", "Bearer <token>")`.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Gzip the body, setting `Content-Encoding: gzip`.
+    pub fn with_gzip(mut self) -> Self {
+        self.gzip = true;
+        self
+    }
+}
+
+#[cfg(feature = "http")]
+impl Uploader for HttpUploader {
+    fn upload(&mut self, path: &Path) -> anyhow::Result<()> {
+        use anyhow::Context;
+        use std::io::Write;
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .with_context(|| format!("invalid file name {}", path.display()))?;
+        let mut body = fs::read(path)?;
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/octet-stream")
+            .set("X-Turnstiles-Filename", name);
+        if self.gzip {
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(&body)?;
+            body = encoder.finish()?;
+            request = request.set("Content-Encoding", "gzip");
+        }
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        request.send_bytes(&body)?;
+        Ok(())
+    }
+}
@@ -1,36 +1,127 @@
 use anyhow::{bail, Result};
-use std::{ffi::OsStr, path::PathBuf};
-pub fn filename_to_details(path_str: &str) -> Result<(String, String)> {
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+pub fn filename_to_details(pathbuf: &Path) -> Result<(OsString, PathBuf)> {
     // TODO: make this std::io::err as well for consistency?
-    let pathbuf = PathBuf::from(path_str);
-
-    let filename: String = match pathbuf.file_name() {
+    let filename = match pathbuf.file_name() {
         None => bail!("Could not get filename"),
-        Some(f_osstr) => safe_unwrap_osstr(f_osstr)?,
+        Some(f_osstr) => f_osstr.to_os_string(),
     };
 
     let parent = match pathbuf.parent() {
-        None => "/",
-        Some(s) => match s.to_str() {
-            None => bail!("Could not convert OsStr to &str"),
-            Some("") => ".",
-            Some(s) => s,
-        },
+        None => Path::new("/"),
+        Some(s) if s.as_os_str().is_empty() => Path::new("."),
+        Some(s) => s,
     }
-    .to_string();
+    .to_path_buf();
+    #[cfg(windows)]
+    let parent = if pathbuf.as_os_str().len() + SUFFIX_ROOM >= MAX_PATH {
+        long_path(&parent)?
+    } else {
+        parent
+    };
     Ok((filename, parent))
 }
 
-pub fn safe_unwrap_osstr(s: &OsStr) -> Result<String, std::io::Error> {
-    // Had just used bail here before but really only can return std::io::Error from all of this stuff...
-    let string = match s.to_str() {
-        None => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Could not convert OsStr to &str",
-            ))
+/// Longest path the Win32 APIs take unless it's in the `\\?\` form.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Room for the suffixes we add to the root, i.e. `.123.gz.age.partial`.
+#[cfg(windows)]
+const SUFFIX_ROOM: usize = 32;
+
+/// A directory as an extended-length `\\?\` path, so the files in it can be opened however deep it is. Made absolute first as
+/// nothing is normalised in that form, so `/` and `..` would otherwise be taken literally.
+#[cfg(windows)]
+fn long_path(dir: &Path) -> Result<PathBuf> {
+    use std::path::{Component, Prefix};
+    let absolute = std::path::absolute(dir)?;
+    let mut components = absolute.components();
+    let Some(Component::Prefix(prefix)) = components.next() else {
+        return Ok(absolute);
+    };
+    let mut long = match prefix.kind() {
+        Prefix::Disk(_) => {
+            let mut long = OsString::from(r"\\?\");
+            long.push(prefix.as_os_str());
+            long.push(r"\");
+            PathBuf::from(long)
+        }
+        Prefix::UNC(server, share) => PathBuf::from(r"\\?\UNC\").join(server).join(share),
+        // Already verbatim, or a device
+        _ => return Ok(absolute),
+    };
+    for component in components {
+        if let Component::Normal(name) = component {
+            long.push(name);
+        }
+    }
+    Ok(long)
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with second precision, i.e. `2022-01-31T13:45:00Z`, without pulling in a date library.
+pub fn format_rfc3339(time: std::time::SystemTime) -> String {
+    let secs = match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    };
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+/// Days since the epoch to (year, month, day), from Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Best effort hostname without any extra dependencies.
+pub fn hostname() -> String {
+    if let Ok(h) = std::env::var("HOSTNAME") {
+        if !h.is_empty() {
+            return h;
         }
-        Some(f_str) => f_str.to_string(),
+    }
+    match std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        Ok(h) if !h.trim().is_empty() => h.trim().to_string(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// A new 64-bit id as 16 hex characters, unique enough to tell apart the lifetimes of files. Mixes the time, pid and a counter
+/// through std's randomly keyed hasher so we don't need a rng dependency.
+pub fn new_epoch_id() -> String {
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hash, Hasher},
+        sync::atomic::{AtomicU64, Ordering},
     };
-    Ok(string)
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    std::time::SystemTime::now().hash(&mut hasher);
+    // There are no process ids on WASI, where asking for one panics
+    #[cfg(not(target_os = "wasi"))]
+    std::process::id().hash(&mut hasher);
+    COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
 }
@@ -0,0 +1,148 @@
+//! Stamping rotated files with extended attributes, see `XattrHook`.
+use crate::{utils::format_rfc3339, FileIndexInt, RotationHook};
+use std::{io, path::Path, time::SystemTime};
+use sys::{get, set};
+
+/// `RotationHook` which stamps each file as it's rotated with the extended attributes `turnstiles.rotated_at`, an RFC 3339 UTC
+/// timestamp, and `turnstiles.index`, its index in decimal (under `user.` on Linux, i.e. `getfattr -d test.log.3`). They go with the
+/// file when it's renamed, so whatever else renames our files the originals can still be told apart without parsing names. Read them
+/// back with [`XattrHook::read`].
+///
+/// Only on Linux and macOS, and only on filesystems with extended attributes, doing nothing elsewhere. Files written afresh by a
+/// roller, i.e. compressed or encrypted, don't carry them over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XattrHook;
+
+/// What [`XattrHook`] stamped on a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationXattrs {
+    /// RFC 3339 UTC timestamp, i.e. `2022-01-31T13:45:00Z`
+    pub rotated_at: String,
+    pub index: FileIndexInt,
+}
+
+impl XattrHook {
+    /// The attributes stamped on a file, `None` if it doesn't have them or extended attributes aren't supported.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Option<RotationXattrs>> {
+        let path = path.as_ref();
+        let (Some(rotated_at), Some(index)) = (get(path, "rotated_at")?, get(path, "index")?)
+        else {
+            return Ok(None);
+        };
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid turnstiles attributes on {}", path.display()),
+            )
+        };
+        Ok(Some(RotationXattrs {
+            rotated_at: String::from_utf8(rotated_at).map_err(|_| invalid())?,
+            index: std::str::from_utf8(&index)
+                .ok()
+                .and_then(|index| index.parse().ok())
+                .ok_or_else(invalid)?,
+        }))
+    }
+}
+
+impl RotationHook for XattrHook {
+    fn on_after_rotate(
+        &mut self,
+        _old_path: &Path,
+        new_path: &Path,
+        index: FileIndexInt,
+    ) -> io::Result<()> {
+        set(
+            new_path,
+            "rotated_at",
+            format_rfc3339(SystemTime::now()).as_bytes(),
+        )?;
+        set(new_path, "index", index.to_string().as_bytes())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod sys {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+    /// Namespace for the attributes, which Linux insists on.
+    #[cfg(not(target_os = "macos"))]
+    const PREFIX: &str = "user.turnstiles.";
+    #[cfg(target_os = "macos")]
+    const PREFIX: &str = "turnstiles.";
+
+    /// Error for a file without the attribute.
+    #[cfg(not(target_os = "macos"))]
+    const NO_ATTRIBUTE: i32 = libc::ENODATA;
+    #[cfg(target_os = "macos")]
+    const NO_ATTRIBUTE: i32 = libc::ENOATTR;
+
+    fn c_strings(path: &Path, name: &str) -> io::Result<(CString, CString)> {
+        Ok((
+            CString::new(path.as_os_str().as_bytes())?,
+            CString::new(format!("{}{}", PREFIX, name))?,
+        ))
+    }
+
+    /// Whether an error means the filesystem doesn't do extended attributes.
+    fn unsupported(e: &io::Error) -> bool {
+        e.raw_os_error() == Some(libc::ENOTSUP)
+    }
+
+    pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        let (path, name) = c_strings(path, name)?;
+        let value_ptr = value.as_ptr().cast();
+        // SAFETY: both strings are nul terminated and the value is valid for its length
+        #[cfg(not(target_os = "macos"))]
+        let result =
+            unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0) };
+        #[cfg(target_os = "macos")]
+        let result =
+            unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0, 0) };
+        if result == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if unsupported(&e) {
+            Ok(())
+        } else {
+            Err(e)
+        }
+    }
+
+    pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let (path, name) = c_strings(path, name)?;
+        // Our values are short, so this is plenty
+        let mut value = vec![0u8; 256];
+        let value_ptr = value.as_mut_ptr().cast();
+        // SAFETY: both strings are nul terminated and the buffer is valid for its length
+        #[cfg(not(target_os = "macos"))]
+        let len = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len()) };
+        #[cfg(target_os = "macos")]
+        let len =
+            unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value_ptr, value.len(), 0, 0) };
+        if len >= 0 {
+            value.truncate(len as usize);
+            return Ok(Some(value));
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(NO_ATTRIBUTE) => Ok(None),
+            _ if unsupported(&e) => Ok(None),
+            _ => Err(e),
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+mod sys {
+    use std::{io, path::Path};
+
+    pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
@@ -1,36 +1,111 @@
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
+#![warn(clippy::panic, clippy::expect_used, clippy::unwrap_used)]
 /// Code for a TempDir struct to enable creating temporary, randomly named, directories for testing.
-/// Future work: make this an in-mem filesystem instead, maybe?
+/// Tests which don't need the real disk can use turnstiles' `MemoryFileSystem` instead, which can also simulate a full disk and
+/// permission errors. It lives in turnstiles itself, next to the `FileSystem` trait it implements, as this crate is one of
+/// turnstiles' dev-dependencies and so can't depend on it. Tests of what only a real disk does, i.e. permissions, FIFOs, fsync
+/// and streaming into external tools, still need a directory from here, so not every test runs in memory.
+/// `Pcg32Rand` is exported too, for tests which want reproducible random data.
 use std::{
     fs::{create_dir_all, remove_dir_all},
-    iter,
+    io,
+    path::Path,
 };
+
+mod rng;
+pub use rng::Pcg32Rand;
+
 const N_DIR_NAME_CHARS: usize = 7;
+const DEFAULT_PREFIX: &str = "turnstiles-";
+const NAME_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Set to keep every directory, or to `failed` to keep those dropped by a failing (panicking) test, printing where they are.
+const KEEP_VAR: &str = "TURNSTILES_KEEP_TEMPDIRS";
 
-/// Temporary directory with a random name. When the struct is dropped, the directory and its contents are deleted
+/// Temporary directory with a random name, by default under the system temp directory. When the struct is dropped, the directory
+/// and its contents are deleted, unless kept with `persist` or the `TURNSTILES_KEEP_TEMPDIRS` environment variable.
+///
+/// The `try_` constructors give back the error if the directory can't be created; the others panic with it, for tests which would
+/// only unwrap it anyway.
 pub struct TempDir {
     pub path: String,
+    keep: bool,
 }
 impl TempDir {
+    pub fn try_new() -> io::Result<Self> {
+        Self::create(&std::env::temp_dir(), DEFAULT_PREFIX)
+    }
+
+    /// Under the system temp directory, named `prefix` followed by the random characters.
+    pub fn try_with_prefix(prefix: &str) -> io::Result<Self> {
+        Self::create(&std::env::temp_dir(), prefix)
+    }
+
+    /// Under `base` rather than the system temp directory, i.e. to be on a particular filesystem. `base` is created if need be.
+    pub fn try_with_base(base: impl AsRef<Path>) -> io::Result<Self> {
+        Self::create(base.as_ref(), DEFAULT_PREFIX)
+    }
+
+    // Not `Default`, as creating a directory is more than a default value should do
+    #[allow(clippy::expect_used, clippy::new_without_default)]
     pub fn new() -> Self {
-        let mut rng = thread_rng();
-        let chars: String = iter::repeat(())
-            .map(|()| rng.sample(Alphanumeric))
-            .map(char::from)
-            .take(N_DIR_NAME_CHARS)
+        Self::try_new().expect("Couldn't create temporary directory")
+    }
+
+    #[allow(clippy::expect_used)]
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self::try_with_prefix(prefix).expect("Couldn't create temporary directory")
+    }
+
+    #[allow(clippy::expect_used)]
+    pub fn with_base(base: impl AsRef<Path>) -> Self {
+        Self::try_with_base(base).expect("Couldn't create temporary directory")
+    }
+
+    fn create(base: &Path, prefix: &str) -> io::Result<Self> {
+        let mut rng = Pcg32Rand::from_entropy();
+        let chars: String = (0..N_DIR_NAME_CHARS)
+            .map(|_| char::from(NAME_CHARS[rng.next_range(0..NAME_CHARS.len() as u32) as usize]))
             .collect();
-        let path = chars;
-        create_dir_all(&path).unwrap();
-        Self { path }
+        let path = base
+            .join(format!("{}{}", prefix, chars))
+            .into_os_string()
+            .into_string()
+            .map_err(|path| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Temporary directory path {:?} isn't UTF-8", path),
+                )
+            })?;
+        create_dir_all(&path)?;
+        Ok(Self { path, keep: false })
+    }
+
+    /// Keep the directory and its contents once dropped, printing where it is, to look at what a test left behind.
+    pub fn persist(mut self) -> String {
+        self.keep = true;
+        self.path.clone()
     }
 
     fn clear(&self) {
         remove_dir_all(&self.path).unwrap_or(());
     }
+
+    fn keep(&self) -> bool {
+        match std::env::var(KEEP_VAR) {
+            _ if self.keep => true,
+            Ok(value) if value == "failed" => std::thread::panicking(),
+            Ok(value) => !value.is_empty() && value != "0",
+            Err(_) => false,
+        }
+    }
 }
 
 impl Drop for TempDir {
     fn drop(&mut self) {
-        self.clear();
+        if self.keep() {
+            eprintln!("Keeping temporary directory {}", self.path);
+        } else {
+            self.clear();
+        }
     }
 }
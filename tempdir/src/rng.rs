@@ -0,0 +1,73 @@
+//! Small seedable random number generator, for naming directories and for tests which want reproducible data.
+use std::ops::Range;
+
+/// PCG32 (XSH RR 64/32), as in the reference `pcg32_random_r`, so a given seed and stream give the same numbers everywhere.
+/// Not for anything which needs to be unpredictable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcg32Rand {
+    state: u64,
+    /// Odd increment picking the stream
+    inc: u64,
+}
+
+impl Pcg32Rand {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    /// The generator for `seed` on `stream`, matching `pcg32_srandom_r(seed, stream)`. Different streams give unrelated
+    /// sequences from the same seed.
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Seeded from the time, the pid and a counter, so generators made in the same instant by different threads or processes
+    /// still differ.
+    pub fn from_entropy() -> Self {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        Self::new(nanos, (u64::from(std::process::id()) << 32) ^ count)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+    }
+
+    /// Uniformly in `range`, without the bias of taking a remainder, as `pcg32_boundedrand_r`. Panics if the range is empty.
+    pub fn next_range(&mut self, range: Range<u32>) -> u32 {
+        assert!(range.start < range.end, "empty range {:?}", range);
+        let bound = range.end - range.start;
+        // Numbers below this would make the low results more likely
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let r = self.next_u32();
+            if r >= threshold {
+                return range.start + r % bound;
+            }
+        }
+    }
+
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
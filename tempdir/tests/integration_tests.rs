@@ -0,0 +1,149 @@
+use std::path::Path;
+use tempdir::{Pcg32Rand, TempDir};
+
+#[test]
+fn test_under_system_temp_dir() {
+    let dir = TempDir::new();
+    let path = Path::new(&dir.path);
+    assert!(path.is_dir());
+    assert_eq!(path.parent(), Some(std::env::temp_dir().as_path()));
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("turnstiles-"));
+    assert_eq!(name.len(), "turnstiles-".len() + 7);
+    assert!(name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'));
+
+    // Each gets its own
+    let other = TempDir::new();
+    assert_ne!(dir.path, other.path);
+
+    let path = dir.path.clone();
+    drop(dir);
+    assert!(!Path::new(&path).exists());
+}
+
+#[test]
+fn test_prefix_and_base() {
+    let dir = TempDir::with_prefix("prefix-test-");
+    let name = Path::new(&dir.path).file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("prefix-test-"));
+    assert!(Path::new(&dir.path).is_dir());
+
+    // The base is created if need be, and left behind when the directory in it is removed
+    let parent = TempDir::new();
+    let base = Path::new(&parent.path).join("nested").join("base");
+    let dir = TempDir::with_base(&base);
+    assert_eq!(Path::new(&dir.path).parent(), Some(base.as_path()));
+    assert!(Path::new(&dir.path).is_dir());
+    drop(dir);
+    assert!(base.is_dir());
+    assert_eq!(std::fs::read_dir(&base).unwrap().count(), 0);
+}
+
+#[test]
+fn test_persist() {
+    let dir = TempDir::new();
+    std::fs::write(Path::new(&dir.path).join("left.log"), b"behind").unwrap();
+    let path = dir.persist();
+    assert_eq!(
+        std::fs::read(Path::new(&path).join("left.log")).unwrap(),
+        b"behind"
+    );
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_try_constructors() {
+    let dir = TempDir::try_new().unwrap();
+    assert!(Path::new(&dir.path).is_dir());
+    let dir = TempDir::try_with_prefix("try-prefix-").unwrap();
+    assert!(Path::new(&dir.path).is_dir());
+    let base = TempDir::new();
+    let dir = TempDir::try_with_base(&base.path).unwrap();
+    assert!(Path::new(&dir.path).starts_with(&base.path));
+
+    // Something which isn't a directory is in the way, so the base can't be created
+    let file = Path::new(&base.path).join("file");
+    std::fs::write(&file, b"").unwrap();
+    assert!(TempDir::try_with_base(&file).is_err());
+    assert!(TempDir::try_with_base(file.join("below")).is_err());
+    assert_eq!(std::fs::read_dir(&base.path).unwrap().count(), 2);
+}
+
+#[test]
+fn test_names_differ_across_threads() {
+    // Seeded from the time, pid and a counter, so directories made at once by several threads still get their own names
+    let handles: Vec<_> = (0..8)
+        .map(|_| std::thread::spawn(|| (0..16).map(|_| TempDir::new()).collect::<Vec<_>>()))
+        .collect();
+    let dirs: Vec<TempDir> = handles
+        .into_iter()
+        .flat_map(|h| h.join().unwrap())
+        .collect();
+    let names: std::collections::HashSet<_> = dirs.iter().map(|d| d.path.clone()).collect();
+    assert_eq!(names.len(), 8 * 16);
+    assert!(dirs.iter().all(|d| Path::new(&d.path).is_dir()));
+}
+
+#[test]
+fn test_pcg32_known_answers() {
+    // From the reference pcg32_srandom_r and pcg32_random_r
+    let mut rng = Pcg32Rand::new(42, 54);
+    let expected = [
+        0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e,
+    ];
+    assert_eq!((0..6).map(|_| rng.next_u32()).collect::<Vec<_>>(), expected);
+    let mut rng = Pcg32Rand::new(0, 0);
+    let expected = [0xe4c14788, 0x379c6516, 0x5c4ab3bb, 0x601d23e0];
+    assert_eq!((0..4).map(|_| rng.next_u32()).collect::<Vec<_>>(), expected);
+
+    // Directory names pick from 62 characters this way, so these pin down the names a seed gives, here `tpSLRMf`
+    let mut rng = Pcg32Rand::new(42, 54);
+    let expected = [45, 41, 18, 11, 17, 12, 31, 29, 0, 4];
+    assert_eq!(
+        (0..10).map(|_| rng.next_range(0..62)).collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[test]
+fn test_pcg32_ranges() {
+    let mut rng = Pcg32Rand::new(42, 54);
+    // Both ends of the range come up, and nothing outside it
+    let mut seen = [false; 3];
+    for _ in 0..1000 {
+        let n = rng.next_range(10..13);
+        assert!((10..13).contains(&n));
+        seen[(n - 10) as usize] = true;
+    }
+    assert_eq!(seen, [true; 3]);
+    for _ in 0..100 {
+        assert_eq!(rng.next_range(7..8), 7);
+        assert_eq!(rng.next_range(u32::MAX - 1..u32::MAX), u32::MAX - 1);
+        assert!(rng.next_range(0..u32::MAX) < u32::MAX);
+    }
+    assert_eq!(Pcg32Rand::new(42, 54).next_u64(), 0xa15c02b77b47f409);
+}
+
+#[test]
+#[should_panic(expected = "empty range")]
+fn test_pcg32_empty_range() {
+    Pcg32Rand::new(42, 54).next_range(3..3);
+}
+
+#[test]
+fn test_pcg32_fill_bytes() {
+    // Each 4 bytes are the next number, little endian, with the last cut short
+    for len in 0..10 {
+        let mut rng = Pcg32Rand::new(42, 54);
+        let mut numbers = rng.clone();
+        let mut bytes = vec![0; len];
+        rng.fill_bytes(&mut bytes);
+        let expected: Vec<u8> = (0..len.div_ceil(4))
+            .flat_map(|_| numbers.next_u32().to_le_bytes())
+            .take(len)
+            .collect();
+        assert_eq!(bytes, expected);
+        // The rest of a number cut short isn't kept for the next call
+        assert_eq!(rng, numbers);
+    }
+}
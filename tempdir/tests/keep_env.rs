@@ -0,0 +1,38 @@
+//! `TURNSTILES_KEEP_TEMPDIRS` is read on every drop, so these get a process of their own rather than changing it under other tests.
+use std::path::Path;
+use tempdir::TempDir;
+
+const KEEP_VAR: &str = "TURNSTILES_KEEP_TEMPDIRS";
+
+fn dropped_dir_kept() -> bool {
+    let path = TempDir::new().path.clone();
+    let kept = Path::new(&path).exists();
+    let _ = std::fs::remove_dir_all(&path);
+    kept
+}
+
+#[test]
+fn test_keep_env() {
+    std::env::remove_var(KEEP_VAR);
+    assert!(!dropped_dir_kept());
+    std::env::set_var(KEEP_VAR, "1");
+    assert!(dropped_dir_kept());
+    std::env::set_var(KEEP_VAR, "0");
+    assert!(!dropped_dir_kept());
+    std::env::set_var(KEEP_VAR, "");
+    assert!(!dropped_dir_kept());
+
+    // Only kept when dropped by a failing test
+    std::env::set_var(KEEP_VAR, "failed");
+    assert!(!dropped_dir_kept());
+    let dir = TempDir::new();
+    let path = dir.path.clone();
+    let panicked = std::panic::catch_unwind(move || {
+        let _dir = dir;
+        panic!("test failed");
+    });
+    assert!(panicked.is_err());
+    assert!(Path::new(&path).exists());
+    std::fs::remove_dir_all(&path).unwrap();
+    std::env::remove_var(KEEP_VAR);
+}
@@ -1,6 +1,13 @@
 use std::{collections::HashSet, fs, io::Write, thread::sleep, time::Duration};
 use tempdir::TempDir;
-use turnstiles::{PruneCondition, RotatingFile, RotationCondition};
+use turnstiles::{
+    inspect, AfterUpload, CommandRoller, DeleteRoller, KeyedRotatingFiles, LimitPolicy,
+    MemoryFileSystem, OversizedWritePolicy, PruneCondition, RateLimit, RotatedMtime, Rotating,
+    RotatingBuffer, RotatingFile, RotatingFileSet, RotationCondition, RotationHook, Sampling,
+    SamplingTrigger, SanitizeMode, Segment, ShardedBuilder, SharedRotatingFile, SinkFactory,
+    SizeTrigger, Throughput, TimestampRoller, UploadPolicy, UploadRoller, Uploader, WriteCounts,
+    XattrHook,
+};
 
 // Duplicated by doctests but i think that's okay? These have fn names, easier to interpret if failing...
 #[test]
@@ -492,6 +499,14 @@ fn test_invalid_options() {
     )
     .is_err());
 
+    assert!(RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(u64::MAX / 1024), // too many bytes
+        PruneCondition::None,
+        false,
+    )
+    .is_err());
+
     assert!(RotatingFile::new(
         path,
         RotationCondition::SizeMB(1),
@@ -501,6 +516,4358 @@ fn test_invalid_options() {
     .is_err());
 }
 
+#[test]
+fn test_shared_writer_threads() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file = SharedRotatingFile::new(
+        RotatingFile::new(
+            path,
+            RotationCondition::SizeMB(1),
+            PruneCondition::None,
+            false,
+        )
+        .unwrap(),
+    );
+
+    let handles: Vec<_> = (0..4u8)
+        .map(|i| {
+            let file = file.clone();
+            std::thread::spawn(move || {
+                let line = vec![b'a' + i; 999];
+                for _ in 0..500 {
+                    (&file).write_all(&[&line[..], b"\n"].concat()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    (&file).flush().unwrap();
+
+    // 2mb written in 1kb lines, each line should be intact and from a single thread
+    assert!(file.lock().index() == 1);
+    let mut n_lines = 0;
+    for filename in get_dir_files_hashset(&dir.path) {
+        let data = fs::read(format!("{}/{}", &dir.path, filename)).unwrap();
+        for line in data.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            assert_eq!(line.len(), 999);
+            assert!(line.iter().all(|b| *b == line[0]));
+            n_lines += 1;
+        }
+    }
+    assert_eq!(n_lines, 2000);
+}
+
+#[test]
+fn test_from_env() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    // Unique prefix as tests share the process environment
+    std::env::set_var("TEST_FROM_ENV_PATH", path);
+    std::env::set_var("TEST_FROM_ENV_ROTATE_SIZE", "1MB");
+    std::env::set_var("TEST_FROM_ENV_MAX_FILES", "2");
+    let mut file = RotatingFile::from_env("TEST_FROM_ENV").unwrap();
+
+    let data: Vec<u8> = vec![0; 990_000];
+    for _ in 0..6 {
+        file.write_all(&data).unwrap();
+    }
+    assert!(file.index() == 2);
+    assert_correct_files(&dir.path, vec![file.current_file_name_str(), "test.log.2"]);
+
+    std::env::set_var("TEST_FROM_ENV_MAX_AGE", "7d");
+    assert!(RotatingFile::from_env("TEST_FROM_ENV").is_err()); // both MAX_FILES and MAX_AGE
+    std::env::set_var("TEST_FROM_ENV_BAD_PATH", path);
+    std::env::set_var("TEST_FROM_ENV_BAD_ROTATE_SIZE", "1 parsec");
+    assert!(RotatingFile::from_env("TEST_FROM_ENV_BAD").is_err());
+    assert!(RotatingFile::from_env("TEST_FROM_ENV_MISSING").is_err()); // no path
+}
+
+#[test]
+fn test_config_file_reload() {
+    use std::sync::{Arc, Mutex};
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let config_path = &[dir.path.clone(), "turnstiles.conf".to_string()].join("/");
+    fs::write(config_path, "# no rotation to start with\n").unwrap();
+
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_error_hook(move |_, e| errors_hook.lock().unwrap().push(e.to_string()))
+    .with_config_file(config_path, Duration::from_millis(0))
+    .unwrap();
+
+    let data: Vec<u8> = vec![0; 600_000];
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    assert!(file.index() == 0);
+
+    fs::write(config_path, "ROTATE_SIZE = 1MB\nMAX_FILES = 2\n").unwrap();
+    file.write_all(&data).unwrap();
+    assert!(file.index() == 1);
+
+    // Invalid config is reported and the old conditions kept
+    fs::write(config_path, "ROTATE_SIZE = 0MB\n").unwrap();
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    assert!(file.index() == 2);
+    assert_eq!(errors.lock().unwrap().len(), 1);
+    assert_correct_files(
+        &dir.path,
+        vec![
+            file.current_file_name_str(),
+            "test.log.2",
+            "turnstiles.conf",
+        ],
+    );
+}
+
+#[test]
+fn test_conditions_from_str() {
+    assert_eq!(
+        "size: 100MB".parse::<RotationCondition>().unwrap(),
+        RotationCondition::SizeMB(100)
+    );
+    assert_eq!(
+        "age: 1h".parse::<RotationCondition>().unwrap(),
+        RotationCondition::Duration(Duration::from_secs(3600))
+    );
+    assert_eq!(
+        "None".parse::<RotationCondition>().unwrap(),
+        RotationCondition::None
+    );
+    assert_eq!(
+        "files: 10".parse::<PruneCondition>().unwrap(),
+        PruneCondition::MaxFiles(10)
+    );
+    assert_eq!(
+        "age: 7d".parse::<PruneCondition>().unwrap(),
+        PruneCondition::MaxAge(Duration::from_secs(7 * 24 * 3600))
+    );
+    assert!("files: 10".parse::<RotationCondition>().is_err());
+    assert!("size: 10 furlongs".parse::<RotationCondition>().is_err());
+    // Too many bytes for a u64
+    assert!("size: 17000000TB".parse::<RotationCondition>().is_err());
+    assert!("size: 16000000TB".parse::<RotationCondition>().is_ok());
+    assert!("files: lots".parse::<PruneCondition>().is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_conditions_deserialize() {
+    #[derive(serde::Deserialize)]
+    struct LogConfig {
+        rotation: RotationCondition,
+        prune: PruneCondition,
+    }
+    let config: LogConfig =
+        serde_json::from_str(r#"{"rotation": "size: 100MB", "prune": "files: 10"}"#).unwrap();
+    assert_eq!(config.rotation, RotationCondition::SizeMB(100));
+    assert_eq!(config.prune, PruneCondition::MaxFiles(10));
+    assert!(serde_json::from_str::<LogConfig>(r#"{"rotation": "size", "prune": "none"}"#).is_err());
+}
+
+#[test]
+fn test_rate_limit_summarize() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_rate_limit(RateLimit {
+            throughput: Throughput::RecordsPerSec(10),
+            policy: LimitPolicy::Summarize,
+        });
+
+    for _ in 0..100 {
+        file.write_all(b"spam\n").unwrap();
+    }
+    assert_eq!(file.suppressed_count(), 90);
+    sleep(Duration::from_millis(1100));
+    file.write_all(b"back to normal\n").unwrap();
+
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    let expected = format!(
+        "{}turnstiles: 90 records suppressed by rate limit\nback to normal\n",
+        "spam\n".repeat(10)
+    );
+    assert_eq!(data, expected);
+}
+
+#[test]
+fn test_max_line_length() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_max_line_length(10, "…[truncated]");
+
+    file.write_all(b"short\n0123456789abcdef\nalso short\n")
+        .unwrap();
+    // Long line split over several writes
+    file.write_all(b"0123").unwrap();
+    file.write_all(b"456789abc").unwrap();
+    file.write_all(b"def").unwrap();
+    file.write_all(b"\nend\n").unwrap();
+
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    assert_eq!(
+        data,
+        "short\n0123456789…[truncated]\nalso short\n0123456789…[truncated]\nend\n"
+    );
+}
+
+#[test]
+fn test_sanitize() {
+    let dir = TempDir::new();
+    let input = "plain\t\x1b[31mred\x1b[0m \x1b]0;title\x07bell\x07 nul\0 c1\u{9b}\n";
+    for (mode, expected) in [
+        (SanitizeMode::Strip, "plain\tred bell nul c1\n"),
+        (
+            SanitizeMode::Escape,
+            "plain\t\\x1b[31mred\\x1b[0m \\x1b]0;title\\x07bell\\x07 nul\\x00 c1\\xc2\\x9b\n",
+        ),
+    ] {
+        let path = &[dir.path.clone(), format!("{:?}.log", mode)].join("/");
+        let mut file =
+            RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+                .unwrap()
+                .with_sanitize(mode);
+        file.write_all(input.as_bytes()).unwrap();
+        let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+        assert_eq!(data, expected);
+    }
+
+    // Escaping can't take a line over the cap
+    let path = &[dir.path.clone(), "capped.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_sanitize(SanitizeMode::Escape)
+        .with_max_line_length(10, "…");
+    file.write_all(b"ab\x1b\x1b\x1b\nok\n").unwrap();
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    assert_eq!(data, "ab\\x1b\\x1b…\nok\n");
+}
+
+#[test]
+fn test_deduplication() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_deduplication();
+
+    file.write_all(b"start\n").unwrap();
+    for _ in 0..100 {
+        file.write_all(b"error: oh no\n").unwrap();
+    }
+    file.write_all(b"recovered\nrecovered\n").unwrap();
+    // Split line which matches the previous one
+    file.write_all(b"recov").unwrap();
+    file.write_all(b"ered\npartial").unwrap();
+    file.flush().unwrap();
+
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    assert_eq!(
+        data,
+        "start\nerror: oh no\nlast message repeated 99 times\nrecovered\nlast message repeated 2 times\npartial"
+    );
+}
+
+#[test]
+fn test_deduplication_drop() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_deduplication();
+    let active = file.current_file_path().to_path_buf();
+
+    for _ in 0..3 {
+        file.write_all(b"error: oh no\n").unwrap();
+    }
+    file.write_all(b"partial").unwrap();
+    // Written out without a flush
+    drop(file);
+
+    let data = fs::read_to_string(active).unwrap();
+    assert_eq!(data, "error: oh no\nlast message repeated 2 times\npartial");
+}
+
+#[test]
+fn test_sampling_file_size() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(2),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_sampling(Sampling {
+        trigger: SamplingTrigger::FileSizeMB(1),
+        keep_every: 10,
+    })
+    .unwrap();
+
+    let line = [vec![b'a'; 999], vec![b'\n']].concat();
+    // First 1mb goes in as normal, then only every 10th line
+    for _ in 0..1100 {
+        file.write_all(&line).unwrap();
+    }
+    assert!(file.index() == 0);
+    assert_eq!(file.sampled_out_count(), 46);
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    let summary = "turnstiles: sampling 1 in 10 records, 9 dropped";
+    assert_eq!(data.lines().filter(|l| *l == summary).count(), 5);
+    assert_eq!(data.lines().count(), 1100 - 46 + 5);
+
+    // The next kept record triggers a rotation, relieving the pressure
+    file.set_rotation_condition(RotationCondition::SizeMB(1))
+        .unwrap();
+    for _ in 0..10 {
+        file.write_all(&line).unwrap();
+    }
+    assert!(file.index() == 1);
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    assert_eq!(data.lines().next(), Some(summary));
+    assert_eq!(data.lines().count(), 3);
+
+    assert!(
+        RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+            .unwrap()
+            .with_sampling(Sampling {
+                trigger: SamplingTrigger::RecordsPerSec(1),
+                keep_every: 0,
+            })
+            .is_err()
+    );
+}
+
+#[test]
+fn test_transform_redaction() {
+    use std::borrow::Cow;
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let token = regex::bytes::Regex::new(r"token=[A-Za-z0-9]+").unwrap();
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_transform_fn(move |bytes| match token.is_match(bytes) {
+            true => Cow::Owned(
+                token
+                    .replace_all(bytes, &b"token=<redacted>"[..])
+                    .into_owned(),
+            ),
+            false => Cow::Borrowed(bytes),
+        });
+
+    file.write_all(b"login ok token=abc123 user=bob\nnothing to see\n")
+        .unwrap();
+    let data = fs::read_to_string(file.current_file_path_str()).unwrap();
+    assert_eq!(data, "login ok token=<redacted> user=bob\nnothing to see\n");
+}
+
+#[test]
+fn test_mirror() {
+    use turnstiles::Mirror;
+    // Run again as a child process, so what's mirrored can be read from its stdout and stderr
+    if let Ok(path) = std::env::var("TURNSTILES_MIRROR_TEST") {
+        let mirror = if path.ends_with("stderr.log") {
+            Mirror::Stderr
+        } else {
+            Mirror::Stdout
+        };
+        let mut file =
+            RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+                .unwrap()
+                .with_mirror(mirror);
+        file.write_all(b"mirrored line\n").unwrap();
+        file.flush().unwrap();
+        return;
+    }
+    let dir = TempDir::new();
+    for name in ["stdout.log", "stderr.log"] {
+        let path = format!("{}/{}", dir.path, name);
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "test_mirror", "--nocapture"])
+            .env("TURNSTILES_MIRROR_TEST", &path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let (mirrored, other) = match name {
+            "stdout.log" => (output.stdout, output.stderr),
+            _ => (output.stderr, output.stdout),
+        };
+        assert!(String::from_utf8_lossy(&mirrored).contains("mirrored line\n"));
+        assert!(!String::from_utf8_lossy(&other).contains("mirrored line"));
+        assert_eq!(
+            fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+            "mirrored line\n"
+        );
+    }
+}
+
+#[test]
+fn test_tee() {
+    use std::sync::{Arc, Mutex};
+    // Shared buffer so we can look at what the tee got
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    struct Broken;
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken pipe"))
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let buf = SharedBuf::default();
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_error_hook(move |context, _| errors_hook.lock().unwrap().push(context.to_string()))
+    .with_tee(Broken)
+    .with_tee(buf.clone());
+
+    let data: Vec<u8> = vec![1; 600_000];
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    assert!(file.index() == 1);
+    assert_eq!(buf.0.lock().unwrap().len(), 1_800_000);
+    assert_eq!(errors.lock().unwrap().len(), 3);
+    assert!(errors.lock().unwrap()[0].starts_with("tee 0"));
+}
+
+#[test]
+fn test_on_write_hook() {
+    use std::sync::{Arc, Mutex};
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let per_index = Arc::new(Mutex::new(std::collections::HashMap::new()));
+    let per_index_hook = per_index.clone();
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_on_write(move |n, index| *per_index_hook.lock().unwrap().entry(index).or_insert(0) += n);
+
+    let data: Vec<u8> = vec![1; 600_000];
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    let per_index = per_index.lock().unwrap();
+    assert_eq!(per_index.get(&0), Some(&1_200_000));
+    assert_eq!(per_index.get(&1), Some(&600_000));
+}
+
+#[test]
+fn test_on_write_hook_split() {
+    use std::sync::{Arc, Mutex};
+    let fs = MemoryFileSystem::new();
+    let per_index = Arc::new(Mutex::new(std::collections::HashMap::new()));
+    let per_index_hook = per_index.clone();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_on_write(move |n, index| *per_index_hook.lock().unwrap().entry(index).or_insert(0) += n)
+    .with_oversized_write_policy(OversizedWritePolicy::SplitAtLimit)
+    .with_footer()
+    .with_banner()
+    .unwrap();
+
+    // Split over three files, each with a banner and all but the last with a footer
+    file.write_all(&vec![1; 2_500_000]).unwrap();
+    assert_eq!(file.index(), 2);
+    let per_index = per_index.lock().unwrap();
+    let len = |name: &str| fs.read(name).unwrap().len();
+    assert_eq!(per_index.get(&0), Some(&len("/logs/test.log.1")));
+    assert_eq!(per_index.get(&1), Some(&len("/logs/test.log.2")));
+    assert_eq!(per_index.get(&2), Some(&len("/logs/test.log.ACTIVE")));
+}
+
+#[test]
+fn test_banner() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_banner()
+    .unwrap();
+
+    let data: Vec<u8> = vec![b'a'; 600_000];
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    assert!(file.index() == 1);
+
+    let first = fs::read_to_string(format!("{}.1", path)).unwrap();
+    let banner = first.lines().next().unwrap();
+    assert!(banner.starts_with(&format!(
+        "# turnstiles {} | epoch ",
+        env!("CARGO_PKG_VERSION")
+    )));
+    assert!(banner.ends_with("| previous none"));
+
+    let active = fs::read_to_string(file.current_file_path_str()).unwrap();
+    let banner = active.lines().next().unwrap();
+    assert!(banner.ends_with("| previous test.log.1"));
+    assert_eq!(active.len(), banner.len() + 1 + 600_000);
+}
+
+#[test]
+fn test_epoch_id_header_footer() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_footer()
+    .with_banner()
+    .unwrap();
+
+    let first_epoch = file.epoch_id().to_string();
+    let data = [vec![b'a'; 599_999], vec![b'\n']].concat();
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    assert!(file.index() == 1);
+    let second_epoch = file.epoch_id().to_string();
+    assert_ne!(first_epoch, second_epoch);
+
+    let first = fs::read_to_string(format!("{}.1", path)).unwrap();
+    assert!(first
+        .lines()
+        .next()
+        .unwrap()
+        .contains(&format!("| epoch {} |", first_epoch)));
+    let footer = first.lines().last().unwrap();
+    assert!(footer.starts_with(&format!("# turnstiles epoch {} | ended ", first_epoch)));
+    assert!(footer.ends_with(&format!("| next epoch {}", second_epoch)));
+
+    let active = fs::read_to_string(file.current_file_path_str()).unwrap();
+    assert!(active
+        .lines()
+        .next()
+        .unwrap()
+        .contains(&format!("| epoch {} |", second_epoch)));
+}
+
+#[test]
+fn test_oversized_write_split() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_oversized_write_policy(OversizedWritePolicy::SplitAtNewline);
+
+    // 3.5mb in 1000 byte lines as a single write
+    let line = [vec![b'a'; 999], vec![b'\n']].concat();
+    let data = line.repeat(3_500);
+    file.write_all(&data).unwrap();
+    assert!(file.index() == 3);
+
+    let mut total = 0;
+    for i in 1..4 {
+        let chunk = fs::read(format!("{}.{}", path, i)).unwrap();
+        assert!(chunk.len() as u64 <= 1_048_576);
+        assert!(chunk.ends_with(b"\n"));
+        total += chunk.len();
+    }
+    total += fs::read(file.current_file_path_str()).unwrap().len();
+    assert_eq!(total, data.len());
+
+    // Exact splitting
+    let path = &[dir.path.clone(), "exact.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_oversized_write_policy(OversizedWritePolicy::SplitAtLimit);
+    file.write_all(&vec![0; 2_500_000]).unwrap();
+    assert!(file.index() == 2);
+    assert_eq!(fs::read(format!("{}.1", path)).unwrap().len(), 1_048_576);
+    assert_eq!(fs::read(format!("{}.2", path)).unwrap().len(), 1_048_576);
+}
+
+#[test]
+fn test_oversized_write_split_banner() {
+    let fs = MemoryFileSystem::new();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_oversized_write_policy(OversizedWritePolicy::SplitAtNewline)
+    .with_banner()
+    .unwrap();
+
+    // Only the banner is in the file, so the line goes in after it rather than rotating again and again
+    let line = [vec![b'a'; 1_500_000], vec![b'\n']].concat();
+    file.write_all(&line).unwrap();
+    assert_eq!(file.index(), 0);
+    assert!(fs.read("/logs/test.log.ACTIVE").unwrap().ends_with(&line));
+
+    // Otherwise it gets a new file of its own, after that file's banner
+    file.write_all(b"one\n").unwrap();
+    assert_eq!(file.index(), 1);
+    file.write_all(&line).unwrap();
+    assert_eq!(file.index(), 2);
+    assert!(fs.read("/logs/test.log.2").unwrap().ends_with(b"\none\n"));
+    let active = fs.read("/logs/test.log.ACTIVE").unwrap();
+    assert!(active.starts_with(b"# turnstiles "));
+    assert!(active.ends_with(&line));
+    assert_eq!(fs.read("/logs/test.log.3"), None);
+}
+
+#[test]
+fn test_size_counts_buffered_bytes() {
+    // Size is tracked from what's written, so a BufWriter in front of the file doesn't delay rotation past what's been handed over
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    fs::write(file.current_file_path_str(), vec![0; 100]).unwrap(); // written before we counted, so not included
+    file.write_all(&vec![0; 600_000]).unwrap();
+    assert_eq!(file.current_file_size(), 600_000);
+    file.write_all(&vec![0; 600_000]).unwrap();
+    file.write_all(b"x").unwrap();
+    assert!(file.index() == 1);
+    assert_eq!(file.current_file_size(), 1);
+}
+
+#[test]
+fn test_fmt_write() {
+    // Something generic over fmt::Write, as io::Write is also in scope here
+    fn write_line<W: std::fmt::Write>(w: &mut W, line: &str) -> std::fmt::Result {
+        writeln!(w, "{}", line)
+    }
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        true,
+    )
+    .unwrap();
+    let line = "a".repeat(999);
+    for _ in 0..1100 {
+        write_line(&mut file, &line).unwrap();
+    }
+    assert!(file.index() == 1);
+    let mut n_lines = 0;
+    for name in ["test.log.1", file.current_file_name_str()] {
+        let data = fs::read_to_string(format!("{}/{}", dir.path, name)).unwrap();
+        assert!(data.lines().all(|l| l == line));
+        n_lines += data.lines().count();
+    }
+    assert_eq!(n_lines, 1100);
+}
+
+#[test]
+fn test_line_and_record_counts() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    let record = [vec![b'a'; 999], vec![b'\n']].concat().repeat(100); // 100 lines per write
+    for _ in 0..12 {
+        file.write_all(&record).unwrap();
+    }
+    assert!(file.index() == 1);
+    assert_eq!(
+        file.lines_written(),
+        WriteCounts {
+            current_file: 100,
+            total: 1200
+        }
+    );
+    assert_eq!(
+        file.records_written(),
+        WriteCounts {
+            current_file: 1,
+            total: 12
+        }
+    );
+}
+
+#[test]
+fn test_sync_passthroughs() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_deduplication();
+    let active = || fs::read_to_string(format!("{}.ACTIVE", path)).unwrap();
+
+    file.write_all(b"one\n").unwrap();
+    file.write_all(b"one\n").unwrap();
+    assert_eq!(active(), "one\n");
+
+    // Held back repeats are written out before syncing
+    file.sync_data().unwrap();
+    assert_eq!(active(), "one\nlast message repeated 1 times\n");
+    file.write_all(b"two\n").unwrap();
+    file.write_all(b"two\n").unwrap();
+    file.sync_all().unwrap();
+    assert_eq!(
+        active(),
+        "one\nlast message repeated 1 times\ntwo\nlast message repeated 1 times\n"
+    );
+}
+
+#[test]
+fn test_file_info() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    file.write_all(&vec![0; 600_000]).unwrap();
+    file.sync_data().unwrap();
+    file.sync_all().unwrap();
+    let info = file.file_info().unwrap();
+    assert_eq!(info.path.to_str(), Some(file.current_file_path_str()));
+    assert_eq!(info.size, 600_000);
+    assert_eq!(info.index, 0);
+}
+
+#[test]
+fn test_path_join() {
+    let dir = TempDir::new();
+    // Doubled separator, which the parent shouldn't carry through into the paths built from it
+    let path = &format!("{}//test.log", dir.path);
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    let dir_path = std::path::Path::new(&dir.path);
+    assert_eq!(file.current_file_path(), dir_path.join("test.log.ACTIVE"));
+    for _ in 0..3 {
+        file.write_all(b"line\n").unwrap();
+        file.rotate().unwrap();
+    }
+    assert!(!dir_path.join("test.log.1").exists());
+    let paths: Vec<_> = inspect::list(path)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.path)
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            dir_path.join("test.log.2"),
+            dir_path.join("test.log.3"),
+            dir_path.join("test.log.ACTIVE"),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_paths() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+    let dir = TempDir::new();
+    let dir_path = Path::new(&dir.path);
+    // Something else's file which can't be read as UTF-8 shouldn't stop the directory being listed
+    fs::write(dir_path.join(OsStr::from_bytes(b"other\xfe.log")), b"").unwrap();
+    let path = dir_path.join(OsStr::from_bytes(b"test\xff.log"));
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    for _ in 0..3 {
+        file.write_all(b"line\n").unwrap();
+        file.rotate().unwrap();
+    }
+    assert_eq!(
+        file.current_file_name().as_bytes(),
+        b"test\xff.log.ACTIVE".as_slice()
+    );
+    assert_eq!(file.current_file_name_str(), "");
+    assert!(!dir_path.join(OsStr::from_bytes(b"test\xff.log.1")).exists());
+    assert!(dir_path.join(OsStr::from_bytes(b"test\xff.log.3")).exists());
+    drop(file);
+
+    let file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    assert_eq!(file.index(), 3);
+    let indices: Vec<_> = inspect::list(&path)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.index)
+        .collect();
+    assert_eq!(indices, vec![Some(2), Some(3), None]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_mode() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new();
+    let mode = |name: &str| {
+        fs::metadata(format!("{}/{}", dir.path, name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777
+    };
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_precreate()
+        .unwrap()
+        .with_mode(0o640)
+        .unwrap()
+        .with_background_rotation()
+        .unwrap();
+    assert_eq!(mode("test.log.ACTIVE"), 0o640);
+    assert_eq!(mode("test.log.NEXT"), 0o640);
+    for _ in 0..2 {
+        file.write_all(b"line\n").unwrap();
+        file.rotate().unwrap();
+    }
+    file.drain().unwrap();
+    for name in [
+        "test.log.1",
+        "test.log.2",
+        "test.log.ACTIVE",
+        "test.log.NEXT",
+    ] {
+        assert_eq!(mode(name), 0o640, "{}", name);
+    }
+}
+
+#[cfg(all(unix, feature = "ownership"))]
+#[test]
+fn test_owner() {
+    use std::os::unix::fs::MetadataExt;
+    let dir = TempDir::new();
+    // Whoever's running the tests can always give files to themselves
+    let metadata = fs::metadata(&dir.path).unwrap();
+    let (uid, gid) = (metadata.uid(), metadata.gid());
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_owner(Some(uid), Some(gid))
+        .unwrap();
+    file.write_all(b"line\n").unwrap();
+    file.rotate().unwrap();
+    for name in ["test.log.1", "test.log.ACTIVE"] {
+        let metadata = fs::metadata(format!("{}/{}", dir.path, name)).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+    }
+
+    let err = file
+        .with_owner_names(Some("no-such-turnstiles-user"), None)
+        .unwrap_err();
+    assert!(err.to_string().contains("No user named"));
+    if uid == 0 {
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+            .unwrap()
+            .with_owner_names(Some("root"), None)
+            .unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_open_options() {
+    use std::os::unix::fs::OpenOptionsExt;
+    let dir = TempDir::new();
+    let nofollow = |options: &mut fs::OpenOptions| {
+        options.custom_flags(libc::O_NOFOLLOW);
+    };
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_open_options(nofollow)
+        .unwrap();
+    file.write_all(b"line\n").unwrap();
+    file.rotate().unwrap();
+    file.write_all(b"line\n").unwrap();
+    file.flush().unwrap();
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.1", dir.path)).unwrap(),
+        "line\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "line\n"
+    );
+
+    // An active file swapped for a symlink isn't followed
+    fs::write(format!("{}/elsewhere", dir.path), "").unwrap();
+    let path = format!("{}/linked.log", dir.path);
+    std::os::unix::fs::symlink("elsewhere", format!("{}.ACTIVE", path)).unwrap();
+    let file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_open_options(nofollow);
+    assert!(file.is_err());
+}
+
+/// `MemoryFileSystem` without creation times, as on WASI.
+#[derive(Clone, Default)]
+struct NoCreatedFileSystem(MemoryFileSystem);
+
+struct NoCreatedFile(turnstiles::MemoryFile);
+
+impl Write for NoCreatedFile {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.write(bytes)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl turnstiles::FileHandle for NoCreatedFile {
+    fn metadata(&self) -> std::io::Result<turnstiles::Metadata> {
+        let metadata = self.0.metadata()?;
+        Ok(turnstiles::Metadata {
+            created: None,
+            ..metadata
+        })
+    }
+    fn sync_all(&self) -> std::io::Result<()> {
+        self.0.sync_all()
+    }
+    fn sync_data(&self) -> std::io::Result<()> {
+        self.0.sync_data()
+    }
+}
+
+impl turnstiles::FileSystem for NoCreatedFileSystem {
+    type File = NoCreatedFile;
+    fn open_append(&self, path: &std::path::Path) -> std::io::Result<NoCreatedFile> {
+        self.0.open_append(path).map(NoCreatedFile)
+    }
+    fn rename(&self, from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+        self.0.rename(from, to)
+    }
+    fn remove_file(&self, path: &std::path::Path) -> std::io::Result<()> {
+        self.0.remove_file(path)
+    }
+    fn read_dir(&self, path: &std::path::Path) -> std::io::Result<Vec<std::ffi::OsString>> {
+        self.0.read_dir(path)
+    }
+    fn metadata(&self, path: &std::path::Path) -> std::io::Result<turnstiles::Metadata> {
+        let metadata = self.0.metadata(path)?;
+        Ok(turnstiles::Metadata {
+            created: None,
+            ..metadata
+        })
+    }
+}
+
+#[test]
+fn test_duration_without_created() {
+    let fs = NoCreatedFileSystem::default();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::Duration(Duration::from_millis(100)),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    file.write_all(b"line\n").unwrap();
+    assert_eq!(file.index(), 0);
+    sleep(Duration::from_millis(200));
+    file.write_all(b"line\n").unwrap();
+    assert_eq!(file.index(), 1);
+    file.write_all(b"line\n").unwrap();
+    assert_eq!(file.index(), 1);
+    assert_eq!(fs.0.read("/logs/test.log.1").unwrap(), b"line\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dir_file_system() {
+    let dir = TempDir::new();
+    let logs = format!("{}/logs", dir.path);
+    fs::create_dir(&logs).unwrap();
+    let mut file = RotatingFile::new_in(
+        turnstiles::DirFileSystem::open(&logs).unwrap(),
+        format!("{}/test.log", logs),
+        RotationCondition::None,
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap();
+    file.write_all(b"one\n").unwrap();
+    file.rotate().unwrap();
+
+    // Carries on in the same directory after it's been renamed
+    let moved = format!("{}/moved", dir.path);
+    fs::rename(&logs, &moved).unwrap();
+    for line in ["two\n", "three\n"] {
+        file.write_all(line.as_bytes()).unwrap();
+        file.rotate().unwrap();
+    }
+    file.write_all(b"four\n").unwrap();
+    file.flush().unwrap();
+    let mut names: Vec<String> = fs::read_dir(&moved)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["test.log.3", "test.log.ACTIVE"]);
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.3", moved)).unwrap(),
+        "three\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.ACTIVE", moved)).unwrap(),
+        "four\n"
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn test_long_path() {
+    let dir = TempDir::new();
+    let mut logs = std::path::PathBuf::from(&dir.path);
+    for _ in 0..12 {
+        logs.push("a-deeply-nested-log-directory");
+    }
+    fs::create_dir_all(&logs).unwrap();
+    let path = logs.join("test.log");
+    assert!(path.as_os_str().len() > 260);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap();
+    for _ in 0..3 {
+        file.write_all(b"line\n").unwrap();
+        file.rotate().unwrap();
+    }
+    assert!(file.current_file_path().starts_with(r"\\?\"));
+    assert_eq!(
+        fs::read_to_string(logs.join("test.log.3")).unwrap(),
+        "line\n"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_linked_active_file() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_sync_every_write()
+        .unwrap();
+    file.write_all(b"one\n").unwrap();
+    file.rotate().unwrap();
+    file.write_all(b"two\n").unwrap();
+    file.write_all(b"three\n").unwrap();
+
+    // Looks just like a file created by name
+    let plain = format!("{}/plain", dir.path);
+    fs::write(&plain, "").unwrap();
+    let active = fs::metadata(format!("{}.ACTIVE", path)).unwrap();
+    assert_eq!(active.nlink(), 1);
+    assert_eq!(
+        active.permissions().mode(),
+        fs::metadata(&plain).unwrap().permissions().mode()
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "two\nthree\n"
+    );
+    assert_eq!(fs::read_to_string(format!("{}.1", path)).unwrap(), "one\n");
+}
+
+#[test]
+fn test_rotated_mtime() {
+    let dir = TempDir::new();
+    let modified = |name: &str| {
+        fs::metadata(format!("{}/{}", dir.path, name))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_footer()
+        .with_rotated_mtime(RotatedMtime::LastWrite);
+    let before_write = std::time::SystemTime::now();
+    file.write_all(b"line\n").unwrap();
+    let after_write = std::time::SystemTime::now();
+    sleep(Duration::from_millis(100));
+    // The footer written at rotation doesn't count
+    file.rotate().unwrap();
+    let mtime = modified("test.log.1");
+    assert!(mtime >= before_write && mtime <= after_write);
+
+    let mut file = file.with_rotated_mtime(RotatedMtime::Rotation);
+    file.write_all(b"line\n").unwrap();
+    sleep(Duration::from_millis(100));
+    let before_rotate = std::time::SystemTime::now();
+    file.rotate().unwrap();
+    assert!(modified("test.log.2") >= before_rotate);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_xattr_hook() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_xattrs();
+    for _ in 0..2 {
+        file.write_all(b"line\n").unwrap();
+        file.rotate().unwrap();
+    }
+    assert_eq!(XattrHook::read(format!("{}.ACTIVE", path)).unwrap(), None);
+    // Still there after something else renames the file
+    fs::rename(format!("{}.2", path), format!("{}/renamed", dir.path)).unwrap();
+    let Some(xattrs) = XattrHook::read(format!("{}/renamed", dir.path)).unwrap() else {
+        // The filesystem doesn't do extended attributes
+        return;
+    };
+    assert_eq!(xattrs.index, 2);
+    assert_eq!(xattrs.rotated_at.len(), "2022-01-31T13:45:00Z".len());
+    assert_eq!(
+        XattrHook::read(format!("{}.1", path))
+            .unwrap()
+            .unwrap()
+            .index,
+        1
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_passthrough() {
+    use std::sync::{Arc, Mutex};
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut file = RotatingFile::new(
+        "/dev/null",
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap()
+    .with_precreate()
+    .unwrap()
+    .with_rotate_on_close()
+    .with_error_hook(move |_, e| errors_hook.lock().unwrap().push(e.to_string()));
+    for _ in 0..3 {
+        file.write_all(&vec![0; 1_000_000]).unwrap();
+    }
+    file.rotate().unwrap();
+    assert_eq!(file.index(), 0);
+    file.close().unwrap();
+    let errors = errors.lock().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("FIFO or device"));
+    assert!(fs::metadata("/dev/null.ACTIVE").is_err());
+
+    // A named pipe at the active file's path
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let fifo = std::ffi::CString::new(format!("{}.ACTIVE", path)).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+    let reader = {
+        let fifo = format!("{}.ACTIVE", path);
+        std::thread::spawn(move || fs::read_to_string(fifo).unwrap())
+    };
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        true,
+    )
+    .unwrap()
+    .with_error_hook(|_, _| {});
+    file.write_all(b"one\n").unwrap();
+    file.rotate().unwrap();
+    file.write_all(b"two\n").unwrap();
+    drop(file);
+    assert_eq!(reader.join().unwrap(), "one\ntwo\n");
+    assert!(fs::metadata(format!("{}.1", path)).is_err());
+}
+
+#[test]
+fn test_network_filesystem() {
+    use turnstiles::{FileHandle, FileSystem};
+    let fs = MemoryFileSystem::new();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_network_filesystem();
+    file.write_all(b"one\n").unwrap();
+    file.rotate().unwrap();
+    assert_eq!(file.index(), 1);
+    assert_eq!(fs.read("/logs/test.log.lock"), None);
+
+    // Another process is rotating
+    fs.open_append("/logs/test.log.lock".as_ref()).unwrap();
+    file.write_all(b"two\n").unwrap();
+    file.rotate().unwrap();
+    assert_eq!(file.index(), 1);
+
+    // Until it's been held so long it must have been left behind
+    fs.open_append("/logs/test.log.lock".as_ref())
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(120))
+        .unwrap();
+    file.rotate().unwrap();
+    assert_eq!(file.index(), 2);
+    assert_eq!(fs.read("/logs/test.log.2").unwrap(), b"two\n");
+    assert_eq!(fs.read("/logs/test.log.lock"), None);
+}
+
+#[test]
+fn test_network_filesystem_without_locking() {
+    use std::io::ErrorKind;
+    use std::sync::{Arc, Mutex};
+    use turnstiles::{FailingFileSystem, FileOperation};
+    let fs = FailingFileSystem::new(MemoryFileSystem::new());
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_network_filesystem()
+    .with_error_hook(move |context, _| errors_hook.lock().unwrap().push(context.to_string()));
+
+    // The lock file can't be created, so rotation goes ahead without one and there's nothing to remove
+    file.write_all(b"one\n").unwrap();
+    fs.fail_nth(FileOperation::Open, 1, ErrorKind::Unsupported);
+    file.rotate().unwrap();
+    assert_eq!(file.index(), 1);
+    assert_eq!(fs.inner().read("/logs/test.log.1").unwrap(), b"one\n");
+    assert!(errors.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_network_filesystem_lock_held() {
+    use turnstiles::FileSystem;
+    let fs = MemoryFileSystem::new();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_network_filesystem()
+    .with_oversized_write_policy(OversizedWritePolicy::SplitAtNewline)
+    .with_rotate_on_close();
+    // Another process is rotating
+    fs.open_append("/logs/test.log.lock".as_ref()).unwrap();
+
+    // Rather than trying to rotate again and again, the line goes in the active file
+    let line = [vec![b'a'; 1_500_000], vec![b'\n']].concat();
+    file.write_all(b"one\n").unwrap();
+    file.write_all(&line).unwrap();
+    assert_eq!(file.index(), 0);
+
+    // Nothing was rotated away, so nothing is removed
+    file.close().unwrap();
+    assert_eq!(
+        fs.read("/logs/test.log.ACTIVE").unwrap(),
+        [b"one\n".to_vec(), line].concat()
+    );
+    assert_eq!(fs.read("/logs/test.log.1"), None);
+}
+
+#[test]
+fn test_memory_faults() {
+    let fs = MemoryFileSystem::new();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+
+    fs.set_capacity(Some(8));
+    file.write_all(b"one\n").unwrap();
+    let e = file.write_all(b"two\nthree\n").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::StorageFull);
+    assert_eq!(fs.read("/logs/test.log.ACTIVE").unwrap(), b"one\ntwo\n");
+    fs.set_capacity(None);
+
+    fs.deny("/logs");
+    let e = file.rotate().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(file.index(), 0);
+    // Already open, so still writable
+    file.write_all(b"four\n").unwrap();
+    fs.allow("/logs");
+    file.rotate().unwrap();
+    assert_eq!(fs.read("/logs/test.log.1").unwrap(), b"one\ntwo\nfour\n");
+}
+
+#[test]
+fn test_failing_file_system() {
+    use std::io::ErrorKind;
+    use std::sync::{Arc, Mutex};
+    use turnstiles::{FailingFileSystem, FileOperation};
+    let fs = FailingFileSystem::new(MemoryFileSystem::new());
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "/logs/test.log",
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap()
+    .with_error_hook(move |context, _| errors_hook.lock().unwrap().push(context.to_string()));
+
+    // Checking the size before rotating fails, which is reported and the rotation goes ahead on the count
+    fs.fail_nth(FileOperation::Metadata, 1, ErrorKind::Other);
+    file.write_all(&vec![0; 1_100_000]).unwrap();
+    file.write_all(b"next\n").unwrap();
+    assert_eq!(file.index(), 1);
+    assert_eq!(
+        errors.lock().unwrap().as_slice(),
+        ["checking size of active file"]
+    );
+
+    // A failed rename is returned and leaves the files as they were
+    fs.fail_nth(FileOperation::Rename, 1, ErrorKind::PermissionDenied);
+    let e = file.rotate().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(file.index(), 1);
+    file.rotate().unwrap();
+    assert_eq!(file.index(), 2);
+    assert_eq!(fs.inner().read("/logs/test.log.2").unwrap(), b"next\n");
+
+    // Pruning failures are only reported, and leave the files to be pruned next time
+    errors.lock().unwrap().clear();
+    fs.fail_every(FileOperation::Remove, ErrorKind::PermissionDenied);
+    file.rotate().unwrap();
+    assert_eq!(errors.lock().unwrap().as_slice(), ["prune_logs()"]);
+    assert!(fs.inner().read("/logs/test.log.2").is_some());
+    fs.clear();
+    file.prune();
+    assert!(fs.inner().read("/logs/test.log.2").is_none());
+    assert!(fs.inner().read("/logs/test.log.3").is_some());
+
+    fs.fail_every(FileOperation::Write, ErrorKind::StorageFull);
+    let e = file.write_all(b"full\n").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::StorageFull);
+    assert!(fs.calls(FileOperation::Write) > 0);
+}
+
+/// Something which can happen to a `RotatingFile`, for `test_rotation_invariants` to try in any order.
+#[derive(Debug, Clone)]
+enum Step {
+    /// Write this many bytes
+    Write(usize),
+    SleepMillis(u64),
+    /// Drop the file and open it again, as when the process restarts
+    Restart,
+    Rotate,
+    /// Something else appends this many bytes to the active file, i.e. another process sharing it
+    ExternalAppend(usize),
+    /// Something else removes the oldest rotated file, if there are a few, i.e. someone tidying up by hand
+    ExternalRemoveOldest,
+}
+
+fn step() -> impl proptest::strategy::Strategy<Value = Step> {
+    use proptest::prelude::*;
+    prop_oneof![
+        4 => (1..400_000usize).prop_map(Step::Write),
+        1 => (0..5u64).prop_map(Step::SleepMillis),
+        1 => Just(Step::Restart),
+        1 => Just(Step::Rotate),
+        1 => (1..1_000usize).prop_map(Step::ExternalAppend),
+        1 => Just(Step::ExternalRemoveOldest),
+    ]
+}
+
+/// How the `RotatingFile` under test is set up.
+#[derive(Debug, Clone, Copy)]
+struct Setup {
+    /// Rotate on age rather than size
+    by_age: bool,
+    /// Split writes so no file passes the size limit
+    strict: bool,
+    max_files: Option<usize>,
+}
+
+/// Run the steps against a `RotatingFile` on a `MemoryFileSystem`, checking after each that no data has been lost (beyond what was
+/// pruned or removed, which is always the oldest), no file is over the limit in strict mode, the index never goes backwards and
+/// there are never more files than the prune condition allows.
+fn check_rotation_invariants(setup: Setup, steps: &[Step]) {
+    use turnstiles::FileSystem;
+    let fs = MemoryFileSystem::new();
+    let dir = std::path::Path::new("/logs");
+    let open = || {
+        let rotation = if setup.by_age {
+            RotationCondition::Duration(Duration::from_millis(3))
+        } else {
+            RotationCondition::SizeMB(1)
+        };
+        let prune = setup
+            .max_files
+            .map_or(PruneCondition::None, PruneCondition::MaxFiles);
+        let policy = if setup.strict {
+            OversizedWritePolicy::SplitAtLimit
+        } else {
+            OversizedWritePolicy::Allow
+        };
+        RotatingFile::new_in(fs.clone(), "/logs/test.log", rotation, prune, false)
+            .unwrap()
+            .with_oversized_write_policy(policy)
+    };
+    // Index and contents of each rotated file in order, then the active file's
+    let files = || {
+        let mut rotated: Vec<(u32, Vec<u8>)> = fs
+            .paths()
+            .iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let index = name.strip_prefix("test.log.")?.parse().ok()?;
+                Some((index, fs.read(path).unwrap()))
+            })
+            .collect();
+        rotated.sort();
+        let active = fs.read(dir.join("test.log.ACTIVE")).unwrap_or_default();
+        (rotated, active)
+    };
+
+    let mut file = open();
+    let mut written: Vec<u8> = vec![];
+    let mut lossy = setup.max_files.is_some();
+    let mut index = file.index();
+    for step in steps {
+        match step {
+            Step::Write(len) => {
+                let data: Vec<u8> = (written.len()..written.len() + len)
+                    .map(|i| (i % 251) as u8)
+                    .collect();
+                file.write_all(&data).unwrap();
+                written.extend(data);
+            }
+            Step::SleepMillis(millis) => sleep(Duration::from_millis(*millis)),
+            Step::Restart => {
+                drop(file);
+                file = open();
+            }
+            Step::Rotate => file.rotate().unwrap(),
+            // The file's count of what it's written doesn't see these, so they can take it past the limit
+            Step::ExternalAppend(_) if setup.strict => continue,
+            Step::ExternalAppend(len) => {
+                let data = vec![b'x'; *len];
+                let mut other = fs.open_append(&dir.join("test.log.ACTIVE")).unwrap();
+                other.write_all(&data).unwrap();
+                written.extend(data);
+            }
+            // Leaving at least one so a restart can tell which index it's up to
+            Step::ExternalRemoveOldest => {
+                let (rotated, _) = files();
+                if rotated.len() > 1 {
+                    fs.remove_file(&dir.join(format!("test.log.{}", rotated[0].0)))
+                        .unwrap();
+                    lossy = true;
+                }
+            }
+        }
+        file.flush().unwrap();
+
+        assert!(
+            file.index() >= index,
+            "index went backwards after {:?}",
+            step
+        );
+        index = file.index();
+        let (rotated, active) = files();
+        assert!(rotated.iter().all(|(i, _)| *i <= index));
+        if let Some(max_files) = setup.max_files {
+            // The active file counts towards the limit
+            assert!(
+                rotated.len() < max_files,
+                "{} rotated files kept",
+                rotated.len()
+            );
+        }
+        if setup.strict && !setup.by_age {
+            for (i, data) in &rotated {
+                assert!(
+                    data.len() as u64 <= 1_048_576,
+                    "test.log.{} is {} bytes",
+                    i,
+                    data.len()
+                );
+            }
+            assert!(active.len() as u64 <= 1_048_576);
+        }
+        let kept: Vec<u8> = rotated
+            .into_iter()
+            .flat_map(|(_, data)| data)
+            .chain(active)
+            .collect();
+        if lossy {
+            assert!(
+                written.ends_with(&kept),
+                "data lost or out of order after {:?}",
+                step
+            );
+        } else {
+            assert!(
+                kept == written,
+                "data lost or out of order after {:?}",
+                step
+            );
+        }
+    }
+}
+
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(32))]
+    #[test]
+    fn test_rotation_invariants(
+        by_age: bool,
+        strict: bool,
+        max_files in proptest::option::of(2..5usize),
+        steps in proptest::collection::vec(step(), 1..30),
+    ) {
+        check_rotation_invariants(Setup { by_age, strict, max_files }, &steps);
+    }
+}
+
+#[test]
+fn test_rotate_on_flush() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        true,
+    )
+    .unwrap()
+    .with_rotate_on_flush();
+
+    // Records made of several writes with the newline not at the end of any of them
+    for _ in 0..3 {
+        file.write_all(&vec![b'a'; 600_000]).unwrap();
+        file.write_all(b"\nb").unwrap();
+        assert!(file.index() == 0);
+    }
+    file.flush().unwrap();
+    assert!(file.index() == 1);
+    assert_eq!(file.current_file_size(), 0);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_make_writer() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file = SharedRotatingFile::new(
+        RotatingFile::new(
+            path,
+            RotationCondition::SizeMB(1),
+            PruneCondition::None,
+            false,
+        )
+        .unwrap(),
+    );
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(file.clone())
+        .with_ansi(false)
+        .finish();
+    let line = "a".repeat(1000);
+    tracing::subscriber::with_default(subscriber, || {
+        for i in 0..1000 {
+            tracing::info!(i, "{}", line);
+        }
+    });
+
+    assert!(file.lock().index() == 1);
+    let mut n_lines = 0;
+    for filename in get_dir_files_hashset(&dir.path) {
+        let data = fs::read_to_string(format!("{}/{}", &dir.path, filename)).unwrap();
+        for l in data.lines() {
+            assert!(l.contains(&line));
+            n_lines += 1;
+        }
+    }
+    assert_eq!(n_lines, 1000);
+}
+
+#[cfg(feature = "log-backend")]
+#[test]
+fn test_log_backend() {
+    use log::Log;
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file =
+        RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    // Not using init() as the global logger can only be set once per process
+    let logger = turnstiles::RotatingLogger::new(file)
+        .with_level(log::LevelFilter::Info)
+        .with_template("{level} [{target}] {message} {not_a_placeholder}");
+    for (level, msg) in [
+        (log::Level::Info, "hello {message}"),
+        (log::Level::Debug, "filtered out"),
+        (log::Level::Error, "oh no"),
+    ] {
+        logger.log(
+            &log::Record::builder()
+                .level(level)
+                .target("app")
+                .args(format_args!("{}", msg))
+                .build(),
+        );
+    }
+    logger.flush();
+    let data = fs::read_to_string(format!("{}.ACTIVE", path)).unwrap();
+    assert_eq!(
+        data,
+        "INFO [app] hello {message} {not_a_placeholder}\nERROR [app] oh no {not_a_placeholder}\n"
+    );
+}
+
+#[cfg(feature = "slog")]
+#[test]
+fn test_slog_drain_async_data_integrity() {
+    // As test_slog_json_async_data_integrity but with the built in drain and no require_newline
+    use serde::Deserialize;
+    use slog::{info, o, Drain, Logger};
+    use turnstiles::{SlogDrain, SlogFormat};
+    #[derive(Deserialize)]
+    struct JsonLog {
+        msg: String,
+        level: String,
+        n: String,
+    }
+
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let log_file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    let drain = slog_async::Async::new(SlogDrain::new(log_file, SlogFormat::Json).fuse())
+        .chan_size(100_000)
+        .build()
+        .fuse();
+    let logger = Logger::root(drain, o!("app" => "test"));
+    for i in 0..20_000 {
+        info!(logger, "message with \"quotes\" {}", i; "n" => i);
+    }
+    drop(logger); // flushes the async drain
+
+    let mut seen = HashSet::new();
+    for filename in get_dir_files_hashset(&dir.path) {
+        let data = fs::read_to_string(format!("{}/{}", &dir.path, filename)).unwrap();
+        for line in data.lines() {
+            let row: JsonLog = serde_json::from_str(line).unwrap();
+            assert_eq!(row.level, "INFO");
+            assert_eq!(row.msg, format!("message with \"quotes\" {}", row.n));
+            seen.insert(row.n);
+        }
+    }
+    assert_eq!(seen.len(), 20_000);
+    assert!(get_dir_files_hashset(&dir.path).len() > 1);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_appender_non_blocking() {
+    let dir = TempDir::new();
+    let file = turnstiles::appender::daily(&dir.path, "test.log").unwrap();
+    let (writer, guard) = turnstiles::appender::non_blocking(file);
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_ansi(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        for i in 0..1000 {
+            tracing::info!("line {}", i);
+        }
+    });
+    // Everything is written once the guard is dropped
+    drop(guard);
+
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+    let data = fs::read_to_string(format!("{}/test.log.ACTIVE", &dir.path)).unwrap();
+    let lines: Vec<&str> = data.lines().collect();
+    assert_eq!(lines.len(), 1000);
+    assert!(lines[999].ends_with("line 999"));
+}
+
+#[test]
+fn test_trigger_and_rollers() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_trigger(SizeTrigger(1000))
+        .with_roller(TimestampRoller::new().with_max_files(2));
+    let data = vec![b'a'; 600];
+    for _ in 0..10 {
+        file.write_all(&data).unwrap();
+    }
+    assert_eq!(file.index(), 4);
+    let files = get_dir_files_hashset(&dir.path);
+    assert_eq!(files.len(), 3);
+    assert!(files.contains("test.log.ACTIVE"));
+    let mut indices: Vec<&str> = files
+        .iter()
+        .filter(|f| *f != "test.log.ACTIVE")
+        .map(|f| {
+            let (ts, index) = f
+                .strip_prefix("test.log.")
+                .unwrap()
+                .split_once('.')
+                .unwrap();
+            assert!(ts.ends_with('Z'));
+            index
+        })
+        .collect();
+    indices.sort();
+    assert_eq!(indices, vec!["3", "4"]);
+
+    // Deleting instead leaves just the active file
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_trigger(SizeTrigger(1000))
+    .with_roller(DeleteRoller);
+    for _ in 0..10 {
+        file.write_all(&data).unwrap();
+    }
+    assert_eq!(file.index(), 4);
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+}
+
+#[cfg(feature = "reopen")]
+#[test]
+fn test_reopen() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file = SharedRotatingFile::new(
+        RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false).unwrap(),
+    );
+    let mut reopen = file.clone().into_reopen().unwrap();
+    let handle = reopen.handle();
+    reopen.write_all(b"first\n").unwrap();
+
+    // Something else rotates the file out from under us, then sends SIGHUP
+    fs::rename(
+        format!("{}/test.log.ACTIVE", &dir.path),
+        format!("{}/test.log.5", &dir.path),
+    )
+    .unwrap();
+    handle.reopen();
+    reopen.write_all(b"second\n").unwrap();
+
+    assert_eq!(file.lock().index(), 5);
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.5"]);
+    let data = fs::read_to_string(format!("{}/test.log.ACTIVE", &dir.path)).unwrap();
+    assert_eq!(data, "second\n");
+}
+
+#[cfg(all(unix, feature = "journald"))]
+#[test]
+fn test_journald_errors() {
+    // journald may well not be running here, errors should still reach the hook either way
+    use std::sync::{Arc, Mutex};
+    struct Broken;
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken tee"))
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_error_hook(move |context, _| errors_hook.lock().unwrap().push(context.to_string()))
+        .with_journald_errors()
+        .unwrap()
+        .with_tee(Broken);
+    file.write_all(b"hello\n").unwrap();
+    assert_eq!(errors.lock().unwrap().len(), 1);
+}
+
+#[cfg(feature = "syslog")]
+#[test]
+fn test_syslog_tee() {
+    use std::net::UdpSocket;
+    use turnstiles::{SyslogFormat, SyslogTee};
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let tee = SyslogTee::udp(server.local_addr().unwrap(), SyslogFormat::Rfc5424)
+        .unwrap()
+        .with_facility(16)
+        .with_app_name("myapp");
+    let mut file = RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_tee(tee);
+    file.write_all(b"first\nsec").unwrap();
+    file.write_all(b"ond\n").unwrap();
+
+    let mut buf = [0; 1024];
+    for expected in ["first", "second"] {
+        let n = server.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..n]).unwrap();
+        // local0.info
+        assert!(message.starts_with("<134>1 "));
+        assert!(message.contains(" myapp "));
+        assert!(message.ends_with(&format!(" - - {}", expected)));
+    }
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.ACTIVE", &dir.path)).unwrap(),
+        "first\nsecond\n"
+    );
+}
+
+#[test]
+fn test_upload_roller() {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+    // Fails the first attempt at each file, then copies it into uploaded
+    struct Flaky {
+        dest: String,
+        attempts: Arc<Mutex<usize>>,
+    }
+    impl Uploader for Flaky {
+        fn upload(&mut self, path: &Path) -> anyhow::Result<()> {
+            let mut attempts = self.attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts % 2 == 1 {
+                anyhow::bail!("flaky");
+            }
+            fs::copy(
+                path,
+                format!(
+                    "{}/{}",
+                    self.dest,
+                    path.file_name().unwrap().to_str().unwrap()
+                ),
+            )?;
+            Ok(())
+        }
+    }
+    let dir = TempDir::new();
+    let uploaded = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let attempts = Arc::new(Mutex::new(0));
+    let roller = UploadRoller::new(
+        Flaky {
+            dest: uploaded.path.clone(),
+            attempts: attempts.clone(),
+        },
+        UploadPolicy {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            after_upload: AfterUpload::Delete,
+        },
+    )
+    .unwrap();
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_roller(roller);
+    let data = vec![b'a'; 600_000];
+    for _ in 0..5 {
+        file.write_all(&data).unwrap();
+    }
+    assert_eq!(file.index(), 2);
+    // Dropping the file drops the roller, which waits for the uploads
+    drop(file);
+
+    assert_eq!(*attempts.lock().unwrap(), 4);
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+    assert_correct_files(&uploaded.path, vec!["test.log.1", "test.log.2"]);
+}
+
+#[cfg(feature = "object-store")]
+#[test]
+fn test_object_store_uploader() {
+    use object_store::{memory::InMemory, path::Path as ObjectPath, ObjectStore};
+    use std::sync::Arc;
+    use turnstiles::ObjectStoreUploader;
+    let store = Arc::new(InMemory::new());
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let uploader = ObjectStoreUploader::new(store.clone(), "logs/").unwrap();
+    let roller = UploadRoller::new(uploader, UploadPolicy::default()).unwrap();
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_roller(roller);
+    let data = vec![b'a'; 600_000];
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    drop(file);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let meta = runtime
+        .block_on(store.head(&ObjectPath::from("logs/test.log.1")))
+        .unwrap();
+    assert_eq!(meta.size, 1_200_000);
+    // Kept locally by default
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.1"]);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http_uploader() {
+    use std::{
+        io::{BufRead, BufReader, Read},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+    use turnstiles::HttpUploader;
+    // Fails the first request with a 500 then accepts the retry, handing back its headers and body
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut received = None;
+        for status in ["500 Internal Server Error", "200 OK"] {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push(line.trim().to_ascii_lowercase());
+            }
+            let length: usize = headers
+                .iter()
+                .find_map(|h| h.strip_prefix("content-length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            received = Some((headers, body));
+        }
+        received.unwrap()
+    });
+
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let uploader = HttpUploader::new(url)
+        .with_header("Authorization", "Bearer secret")
+        .with_gzip();
+    let roller = UploadRoller::new(
+        uploader,
+        UploadPolicy {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            after_upload: AfterUpload::Delete,
+        },
+    )
+    .unwrap()
+    .with_error_hook(move |context, _| errors_hook.lock().unwrap().push(context.to_string()));
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_roller(roller);
+    let data = vec![b'a'; 600_000];
+    for _ in 0..3 {
+        file.write_all(&data).unwrap();
+    }
+    drop(file);
+
+    let (headers, body) = server.join().unwrap();
+    assert!(headers.contains(&"authorization: bearer secret".to_string()));
+    assert!(headers.contains(&"content-encoding: gzip".to_string()));
+    assert!(headers.contains(&"x-turnstiles-filename: test.log.1".to_string()));
+    let mut decoded = vec![];
+    flate2::read::GzDecoder::new(&body[..])
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded.len(), 1_200_000);
+    assert!(errors.lock().unwrap().is_empty());
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_roller() {
+    use std::sync::{Arc, Mutex};
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    // Copy each rotated file to <file>.done, failing for the second one
+    let script = r#"[ "${1##*.}" = 2 ] && echo nope >&2 && exit 3; cp "$1" "$1.done""#;
+    let roller = CommandRoller::new("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("postrotate")
+        .with_timeout(Duration::from_secs(10))
+        .with_error_hook(move |_, e| errors_hook.lock().unwrap().push(e.to_string()));
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_roller(roller);
+    let data = vec![b'a'; 600_000];
+    for _ in 0..5 {
+        file.write_all(&data).unwrap();
+    }
+    // Waits for the commands to finish
+    drop(file);
+
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.ACTIVE",
+            "test.log.1",
+            "test.log.1.done",
+            "test.log.2",
+        ],
+    );
+    let errors = errors.lock().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("stderr: nope"));
+}
+
+#[test]
+fn test_rotation_hooks() {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+    impl RotationHook for Recorder {
+        fn on_before_rotate(&mut self, old_path: &Path) -> std::io::Result<()> {
+            // The file is still there, with everything written to it
+            let size = fs::metadata(old_path)?.len();
+            self.0.lock().unwrap().push(format!("before {}", size));
+            Ok(())
+        }
+        fn on_after_rotate(
+            &mut self,
+            old_path: &Path,
+            new_path: &Path,
+            index: u32,
+        ) -> std::io::Result<()> {
+            assert!(old_path.is_file() && new_path.is_file());
+            let name = new_path.file_name().unwrap().to_str().unwrap();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("after {} {}", name, index));
+            Ok(())
+        }
+    }
+    // Only interested in the file having been rotated
+    struct Notifier(Arc<Mutex<Vec<String>>>);
+    impl RotationHook for Notifier {
+        fn on_after_rotate(&mut self, _: &Path, _: &Path, index: u32) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(format!("notify {}", index));
+            Ok(())
+        }
+    }
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let events = Arc::new(Mutex::new(vec![]));
+    let mut file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_rotation_hook(Recorder(events.clone()))
+    .with_rotation_hook(Notifier(events.clone()));
+    let data = vec![b'a'; 600_000];
+    for _ in 0..5 {
+        file.write_all(&data).unwrap();
+    }
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "before 1200000",
+            "after test.log.1 1",
+            "notify 1",
+            "before 1200000",
+            "after test.log.2 2",
+            "notify 2"
+        ]
+    );
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_level_router() {
+    use turnstiles::LevelRouter;
+    let dir = TempDir::new();
+    let error_file = RotatingFile::new(
+        format!("{}/app.error.log", &dir.path),
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    let file = RotatingFile::new(
+        format!("{}/app.log", &dir.path),
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(LevelRouter::new(error_file, file, tracing::Level::ERROR))
+        .with_ansi(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!("all good");
+        tracing::warn!("hmm");
+        tracing::error!("oh no");
+    });
+
+    let errors = fs::read_to_string(format!("{}/app.error.log.ACTIVE", &dir.path)).unwrap();
+    let rest = fs::read_to_string(format!("{}/app.log.ACTIVE", &dir.path)).unwrap();
+    assert_eq!(errors.lines().count(), 1);
+    assert!(errors.contains("oh no"));
+    assert_eq!(rest.lines().count(), 2);
+    assert!(rest.contains("all good") && rest.contains("hmm"));
+}
+
+#[cfg(feature = "slog")]
+#[test]
+fn test_slog_level_router() {
+    use slog::{error, info, o, Drain, Level, Logger};
+    use turnstiles::{SlogDrain, SlogFormat, SlogLevelRouter};
+    let dir = TempDir::new();
+    let error_file = RotatingFile::new(
+        format!("{}/app.error.log", &dir.path),
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    let file = RotatingFile::new(
+        format!("{}/app.log", &dir.path),
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    let router = SlogLevelRouter::new(
+        SlogDrain::new(error_file, SlogFormat::Plain),
+        SlogDrain::new(file, SlogFormat::Plain),
+        Level::Error,
+    );
+    let logger = Logger::root(router.fuse(), o!());
+    info!(logger, "all good");
+    error!(logger, "oh no");
+
+    let errors = fs::read_to_string(format!("{}/app.error.log.ACTIVE", &dir.path)).unwrap();
+    let rest = fs::read_to_string(format!("{}/app.log.ACTIVE", &dir.path)).unwrap();
+    assert!(errors.ends_with(" ERRO oh no\n"));
+    assert!(rest.ends_with(" INFO all good\n"));
+}
+
+#[test]
+fn test_rotating_file_set() {
+    let dir = TempDir::new();
+    let config_path = &format!("{}/turnstiles.conf", &dir.path);
+    fs::write(
+        config_path,
+        format!(
+            "ROTATE_SIZE = 1MB\nACCESS_PATH = {0}/access.log\nERRORS_PATH = {0}/errors.log\nERRORS_ROTATE_SIZE = 2MB\nERRORS_MAX_FILES = 2\n",
+            &dir.path
+        ),
+    )
+    .unwrap();
+    let mut set = RotatingFileSet::from_config_file(config_path, &["access", "errors"]).unwrap();
+    assert_eq!(set.names().collect::<Vec<_>>(), vec!["access", "errors"]);
+
+    let data = vec![b'a'; 600_000];
+    for _ in 0..5 {
+        for name in ["access", "errors"] {
+            set.get_mut(name).unwrap().write_all(&data).unwrap();
+        }
+    }
+    // Shared 1MB default for access, errors has its own 2MB
+    assert_eq!(set.get("access").unwrap().index(), 2);
+    assert_eq!(set.get("errors").unwrap().index(), 1);
+
+    set.rotate_all().unwrap();
+    set.rotate_all().unwrap();
+    set.shutdown().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "turnstiles.conf",
+            "access.log.ACTIVE",
+            "access.log.1",
+            "access.log.2",
+            "access.log.3",
+            "access.log.4",
+            "errors.log.ACTIVE",
+            "errors.log.3",
+        ],
+    );
+
+    // Paths aren't shared
+    fs::write(config_path, format!("PATH = {}/shared.log\n", &dir.path)).unwrap();
+    assert!(RotatingFileSet::from_config_file(config_path, &["access"]).is_err());
+}
+
+#[test]
+fn test_keyed_rotating_files() {
+    let dir = TempDir::new();
+    let mut files = KeyedRotatingFiles::new(
+        &dir.path,
+        "app.log",
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap()
+    .with_max_open(2)
+    .unwrap();
+    let data = vec![b'a'; 600_000];
+    for _ in 0..3 {
+        for tenant in ["a", "b", "c"] {
+            files.write_all(tenant, &data).unwrap();
+            assert!(files.open_count() <= 2);
+        }
+    }
+    // Each tenant was closed and reopened along the way but still rotated once at 1.8MB
+    for tenant in ["a", "b", "c"] {
+        assert_eq!(files.get_mut(tenant).unwrap().index(), 1);
+        assert_correct_files(
+            &format!("{}/{}", &dir.path, tenant),
+            vec!["app.log.ACTIVE", "app.log.1"],
+        );
+    }
+    assert!(files.write_all("../escape", b"nope").is_err());
+    assert!(files.write_all("", b"nope").is_err());
+}
+
+#[test]
+fn test_rotating_generic_sink() {
+    // Rotates between in-memory buffers, keeping the finished ones
+    #[derive(Default)]
+    struct Buffers {
+        finished: Vec<(u32, Vec<u8>)>,
+    }
+    impl SinkFactory for Buffers {
+        type Sink = Vec<u8>;
+        fn open_next(&mut self, _: u32) -> std::io::Result<Vec<u8>> {
+            Ok(vec![])
+        }
+        fn finish(&mut self, sink: Vec<u8>, index: u32) -> std::io::Result<()> {
+            self.finished.push((index, sink));
+            Ok(())
+        }
+    }
+    let mut rotating = Rotating::new(Buffers::default(), RotationCondition::SizeMB(1)).unwrap();
+    let data = vec![b'a'; 600_000];
+    for _ in 0..5 {
+        rotating.write_all(&data).unwrap();
+    }
+    assert_eq!(rotating.index(), 2);
+    assert_eq!(rotating.current_size(), 600_000);
+    assert_eq!(
+        rotating.records_written(),
+        WriteCounts {
+            current_file: 1,
+            total: 5
+        }
+    );
+
+    // Triggers work the same as for files
+    let mut rotating = Rotating::new(Buffers::default(), RotationCondition::None)
+        .unwrap()
+        .with_trigger(SizeTrigger(1000));
+    for _ in 0..4 {
+        rotating.write_all(&[b'a'; 600]).unwrap();
+    }
+    let buffers = rotating.finish().unwrap();
+    let sizes: Vec<(u32, usize)> = buffers
+        .finished
+        .iter()
+        .map(|(i, buf)| (*i, buf.len()))
+        .collect();
+    assert_eq!(sizes, vec![(0, 1200), (1, 1200)]);
+
+    // A failing trigger is reported and taken to mean don't rotate, rather than failing the write
+    use std::sync::{Arc, Mutex};
+    use turnstiles::{RotationContext, Trigger};
+    struct Broken;
+    impl Trigger for Broken {
+        fn trigger(&mut self, _: &dyn RotationContext) -> std::io::Result<bool> {
+            Err(std::io::Error::other("broken trigger"))
+        }
+    }
+    let errors = Arc::new(Mutex::new(vec![]));
+    let errors_hook = errors.clone();
+    let mut rotating = Rotating::new(Buffers::default(), RotationCondition::None)
+        .unwrap()
+        .with_trigger(Broken)
+        .with_error_hook(move |_, e| errors_hook.lock().unwrap().push(e.to_string()));
+    rotating.write_all(b"one\n").unwrap();
+    rotating.write_all(b"two\nthree\n").unwrap();
+    assert_eq!(rotating.index(), 0);
+    assert_eq!(
+        rotating.lines_written(),
+        WriteCounts {
+            current_file: 3,
+            total: 3
+        }
+    );
+    assert_eq!(*errors.lock().unwrap(), vec!["broken trigger"; 2]);
+}
+
+#[cfg(feature = "object-store")]
+#[test]
+fn test_object_store_spool() {
+    use object_store::{memory::InMemory, path::Path as ObjectPath, ObjectStore};
+    use std::sync::Arc;
+    use turnstiles::ObjectStoreSpool;
+    let store = Arc::new(InMemory::new());
+    let spool_dir = TempDir::new();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let list = || {
+        let mut names: Vec<String> = runtime
+            .block_on(store.list_with_delimiter(Some(&ObjectPath::from("logs"))))
+            .unwrap()
+            .objects
+            .into_iter()
+            .map(|meta| meta.location.to_string())
+            .collect();
+        names.sort();
+        names
+    };
+
+    let spool = ObjectStoreSpool::new(
+        store.clone(),
+        "logs",
+        "app.log",
+        &spool_dir.path,
+        PruneCondition::MaxFiles(3),
+    )
+    .unwrap();
+    let mut rotating = Rotating::new(spool, RotationCondition::SizeMB(1)).unwrap();
+    let data = vec![b'a'; 600_000];
+    for _ in 0..7 {
+        rotating.write_all(&data).unwrap();
+    }
+    assert_eq!(rotating.index(), 3);
+    // Active plus two rotated, as for files
+    assert_eq!(list(), vec!["logs/app.log.2", "logs/app.log.3"]);
+    rotating.finish().unwrap();
+    assert_eq!(list(), vec!["logs/app.log.3", "logs/app.log.4"]);
+    assert!(get_dir_files_hashset(&spool_dir.path).is_empty());
+
+    // Picks up from the highest index in the bucket
+    let spool = ObjectStoreSpool::new(
+        store.clone(),
+        "logs",
+        "app.log",
+        &spool_dir.path,
+        PruneCondition::None,
+    )
+    .unwrap();
+    let mut rotating = Rotating::new(spool, RotationCondition::SizeMB(1)).unwrap();
+    rotating.write_all(b"restarted\n").unwrap();
+    rotating.finish().unwrap();
+    let restarted = runtime
+        .block_on(store.get(&ObjectPath::from("logs/app.log.5")))
+        .unwrap();
+    assert_eq!(
+        runtime.block_on(restarted.bytes()).unwrap().as_ref(),
+        b"restarted\n"
+    );
+}
+
+#[test]
+fn test_memory_filesystem() {
+    let fs = MemoryFileSystem::new();
+    let mut file = RotatingFile::new_in(
+        fs.clone(),
+        "logs/test.log",
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    for i in 0..4 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    writeln!(file, "line 4").unwrap();
+    file.flush().unwrap();
+
+    assert_eq!(file.index(), 4);
+    assert_eq!(
+        fs.paths(),
+        vec![
+            std::path::PathBuf::from("logs/test.log.3"),
+            std::path::PathBuf::from("logs/test.log.4"),
+            std::path::PathBuf::from("logs/test.log.ACTIVE"),
+        ]
+    );
+    assert_eq!(fs.read("logs/test.log.4").unwrap(), b"line 3\n");
+    assert_eq!(fs.read("logs/test.log.ACTIVE").unwrap(), b"line 4\n");
+
+    // Picks up where it left off, as with files on disk
+    let file = RotatingFile::new_in(
+        fs.clone(),
+        "logs/test.log",
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    assert_eq!(file.index(), 4);
+}
+
+#[test]
+fn test_rotating_buffer() {
+    let mut buffer = RotatingBuffer::new_buffer(
+        "test.log",
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    for i in 0..4 {
+        writeln!(buffer, "line {}", i).unwrap();
+        buffer.rotate().unwrap();
+    }
+    writeln!(buffer, "line 4").unwrap();
+    buffer.flush().unwrap();
+
+    assert_eq!(buffer.active_segment(), b"line 4\n");
+    let segments = buffer.drain_segments().unwrap();
+    assert_eq!(
+        segments,
+        vec![
+            Segment {
+                index: 3,
+                data: b"line 2\n".to_vec()
+            },
+            Segment {
+                index: 4,
+                data: b"line 3\n".to_vec()
+            },
+        ]
+    );
+    assert!(buffer.segments().unwrap().is_empty());
+
+    // Indices carry on after a drain
+    buffer.rotate().unwrap();
+    let segments = buffer.drain_segments().unwrap();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].index, 5);
+    assert_eq!(segments[0].data, b"line 4\n");
+
+    // Segments from streaming compression have its suffix, and are drained all the same
+    #[cfg(feature = "compression")]
+    {
+        use turnstiles::{CompressionCodec, CompressionConfig, SizeBasis};
+        let mut buffer = RotatingBuffer::new_buffer(
+            "test.log",
+            RotationCondition::None,
+            PruneCondition::None,
+            false,
+        )
+        .unwrap()
+        .with_streaming_compression(
+            CompressionConfig::new(CompressionCodec::Gzip),
+            SizeBasis::Uncompressed,
+        )
+        .unwrap();
+        writeln!(buffer, "line").unwrap();
+        buffer.rotate().unwrap();
+        let segments = buffer.drain_segments().unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].index, 1);
+        assert!(buffer.segments().unwrap().is_empty());
+    }
+}
+
+#[test]
+fn test_inspect() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_banner()
+        .unwrap()
+        .with_footer();
+    for i in 0..4 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    writeln!(file, "line 4").unwrap();
+    file.flush().unwrap();
+
+    let files = inspect::list(&path).unwrap();
+    let indices: Vec<_> = files.iter().map(|f| f.index).collect();
+    assert_eq!(indices, vec![Some(1), Some(2), Some(3), Some(4), None]);
+    assert_eq!(inspect::verify(&path).unwrap(), vec![]);
+
+    let mut out = vec![];
+    inspect::cat(&path, &mut out).unwrap();
+    let lines: Vec<_> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(
+        lines,
+        vec!["line 0", "line 1", "line 2", "line 3", "line 4"]
+    );
+
+    std::fs::remove_file(format!("{}/test.log.2", dir.path)).unwrap();
+    assert_eq!(
+        inspect::verify(&path).unwrap(),
+        vec![inspect::Problem::MissingIndices {
+            after: 1,
+            before: 3
+        }]
+    );
+
+    let removed = inspect::prune(&path, &PruneCondition::MaxFiles(3)).unwrap();
+    assert_eq!(
+        removed,
+        vec![std::path::PathBuf::from(format!("{}/test.log.1", dir.path))]
+    );
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.3", "test.log.4", "test.log.ACTIVE"],
+    );
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn test_checksums() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(4),
+        false,
+    )
+    .unwrap()
+    .with_checksums();
+    for i in 0..3 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    assert_eq!(
+        fs::read_to_string(format!("{}.1.sha256", path)).unwrap(),
+        "2ebec9f67113fe51018906ee270559e0067bd3a5ae0204702457c7cdedcecc01  test.log.1\n"
+    );
+    assert_eq!(inspect::verify(&path).unwrap(), vec![]);
+
+    // A flipped byte is caught
+    fs::write(format!("{}.2", path), "line 9\n").unwrap();
+    let problems = inspect::verify(&path).unwrap();
+    assert!(
+        matches!(&problems[..], [inspect::Problem::ChecksumMismatch { path: p, .. }] if *p == std::path::Path::new(&format!("{}.2", path)))
+    );
+
+    // Sidecars go with their files when pruned
+    writeln!(file, "line 3").unwrap();
+    file.rotate().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.2",
+            "test.log.2.sha256",
+            "test.log.3",
+            "test.log.3.sha256",
+            "test.log.4",
+            "test.log.4.sha256",
+            "test.log.ACTIVE",
+        ],
+    );
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn test_verify_integrity() {
+    use inspect::ChecksumStatus;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_checksums();
+    for i in 0..3 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    file.flush().unwrap();
+
+    let report = inspect::verify_integrity(&path).unwrap();
+    assert!(report.is_ok());
+    let statuses: Vec<_> = report
+        .files
+        .iter()
+        .map(|f| (f.file.index, f.file.size, f.checksum.clone()))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            (Some(1), 7, ChecksumStatus::Match),
+            (Some(2), 7, ChecksumStatus::Match),
+            (Some(3), 7, ChecksumStatus::Match),
+            (None, 0, ChecksumStatus::NoSidecar),
+        ]
+    );
+
+    // The newest file going leaves no gap in the indices, but its sidecar gives it away
+    fs::remove_file(format!("{}.3", path)).unwrap();
+    fs::write(format!("{}.1", path), "line 0 and more\n").unwrap();
+    let report = inspect::verify_integrity(&path).unwrap();
+    assert_eq!(report.files.len(), 3);
+    assert!(matches!(
+        report.files[0].checksum,
+        ChecksumStatus::Mismatch { .. }
+    ));
+    assert!(matches!(
+        &report.problems[..],
+        [inspect::Problem::ChecksumMismatch { .. }, inspect::Problem::Missing { path: p }]
+            if *p == std::path::Path::new(&format!("{}.3", path))
+    ));
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn test_hash_chain() {
+    use std::path::{Path, PathBuf};
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    fs::write(format!("{}.ACTIVE", path), "from before\n").unwrap();
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_hash_chain()
+        .unwrap();
+    for i in 0..3 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    file.flush().unwrap();
+    // What was there already is rotated away, so the chain starts with a file written from the start
+    assert_eq!(
+        fs::read_to_string(format!("{}.1", path)).unwrap(),
+        "from before\n"
+    );
+    let banner = fs::read_to_string(format!("{}.3", path)).unwrap();
+    assert!(banner
+        .lines()
+        .next()
+        .unwrap()
+        .contains("| previous test.log.2 | previous sha256 "));
+    assert_eq!(inspect::verify(&path).unwrap(), vec![]);
+
+    // Changing a file breaks the chain
+    let contents = fs::read_to_string(format!("{}.2", path)).unwrap();
+    fs::write(format!("{}.2", path), contents.replace("line 0", "line 9")).unwrap();
+    let problems = inspect::verify(&path).unwrap();
+    assert!(matches!(
+        &problems[..],
+        [inspect::Problem::ChainBroken { path: p, .. }] if *p == Path::new(&format!("{}.2", path))
+    ));
+
+    // As does deleting the newest rotated file, which leaves no gap in the indices
+    fs::write(format!("{}.2", path), contents).unwrap();
+    fs::remove_file(format!("{}.4", path)).unwrap();
+    assert_eq!(
+        inspect::verify(&path).unwrap(),
+        vec![inspect::Problem::Missing {
+            path: PathBuf::from(format!("{}.4", path))
+        }]
+    );
+}
+
+#[test]
+fn test_env_logger_pipe() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let file: SharedRotatingFile =
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, true)
+            .unwrap()
+            .into();
+    let logger = env_logger::Builder::new()
+        .target(env_logger::Target::Pipe(file.pipe_target()))
+        .format(|buf, record| writeln!(buf, "{} {}", record.level(), record.args()))
+        .filter_level(log::LevelFilter::Info)
+        .build();
+    // Calling the logger directly rather than installing it, as other tests may have set the global logger
+    for i in 0..3 {
+        log::Log::log(
+            &logger,
+            &log::Record::builder()
+                .args(format_args!("message {}", i))
+                .level(log::Level::Info)
+                .build(),
+        );
+    }
+    file.lock().flush().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "INFO message 0\nINFO message 1\nINFO message 2\n"
+    );
+}
+
+#[cfg(feature = "futures-io")]
+#[test]
+fn test_futures_rotating_file() {
+    use futures::io::AsyncWriteExt;
+    use turnstiles::FuturesRotatingFile;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let file =
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    let mut file = FuturesRotatingFile::new(file);
+    futures::executor::block_on(async {
+        file.write_all(b"first\n").await.unwrap();
+        file.rotate().await.unwrap();
+        file.write_all(b"second\n").await.unwrap();
+        file.flush().await.unwrap();
+        let file = file.into_inner().await.unwrap();
+        assert_eq!(file.index(), 1);
+    });
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.1", path)).unwrap(),
+        "first\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "second\n"
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_async_rotating_file() {
+    use tokio::io::AsyncWriteExt;
+    use turnstiles::AsyncRotatingFile;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut file = AsyncRotatingFile::new(file);
+        for i in 0..3 {
+            file.write_all(format!("line {}\n", i).as_bytes())
+                .await
+                .unwrap();
+            file.rotate().await.unwrap();
+        }
+        file.write_all(b"line 3\n").await.unwrap();
+        file.shutdown().await.unwrap();
+        assert_eq!(file.into_inner().await.unwrap().index(), 3);
+    });
+    assert_correct_files(&dir.path, vec!["test.log.3", "test.log.ACTIVE"]);
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "line 3\n"
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_shared_async_rotating_file() {
+    use turnstiles::SharedAsyncRotatingFile;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let file =
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let file = SharedAsyncRotatingFile::new(file);
+        let tasks: Vec<_> = (0..8)
+            .map(|task| {
+                let file = file.clone();
+                tokio::spawn(async move {
+                    for i in 0..100 {
+                        file.write_all(format!("task {} line {}\n", task, i).as_bytes())
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        file.rotate().await.unwrap();
+        file.write_all(b"after\n").await.unwrap();
+        file.flush().await.unwrap();
+    });
+    let rotated = std::fs::read_to_string(format!("{}.1", path)).unwrap();
+    let lines: HashSet<&str> = rotated.lines().collect();
+    assert_eq!(rotated.lines().count(), 800);
+    assert_eq!(lines.len(), 800);
+    for task in 0..8 {
+        // Each task's records stay in order
+        let mine: Vec<&str> = rotated
+            .lines()
+            .filter(|line| line.starts_with(&format!("task {} ", task)))
+            .collect();
+        let expected: Vec<String> = (0..100)
+            .map(|i| format!("task {} line {}", task, i))
+            .collect();
+        assert_eq!(mine, expected);
+    }
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "after\n"
+    );
+}
+
+#[test]
+fn test_into_non_blocking() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let file = RotatingFile::new(
+        &path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap();
+    let (mut writer, guard) = file.into_non_blocking();
+    let line = vec![b'a'; 1023];
+    // Just over 3MB, so the worker has to rotate and prune along the way
+    for _ in 0..3100 {
+        writer.write_all(&line).unwrap();
+        writer.write_all(b"\n").unwrap();
+    }
+    drop(guard);
+    assert_correct_files(&dir.path, vec!["test.log.3", "test.log.ACTIVE"]);
+    assert!(writer.write_all(b"too late\n").is_err());
+}
+
+#[test]
+fn test_non_blocking_overflow() {
+    use std::sync::{mpsc, Arc, Mutex};
+    use turnstiles::appender::{NonBlockingBuilder, OverflowPolicy, QueueStats};
+
+    /// Holds up the worker on its first write until told to carry on, so the queue fills up
+    struct Gated {
+        out: Arc<Mutex<Vec<u8>>>,
+        started: mpsc::Sender<()>,
+        gate: Option<mpsc::Receiver<()>>,
+    }
+    impl Write for Gated {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            if let Some(gate) = self.gate.take() {
+                self.started.send(()).unwrap();
+                gate.recv().unwrap();
+            }
+            self.out.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let run = |policy| {
+        let out = Arc::new(Mutex::new(vec![]));
+        let (started_tx, started) = mpsc::channel();
+        let (release, gate) = mpsc::channel();
+        let writer = Gated {
+            out: out.clone(),
+            started: started_tx,
+            gate: Some(gate),
+        };
+        let (mut writer, guard) = NonBlockingBuilder::new()
+            .with_queue_len(2)
+            .unwrap()
+            .with_overflow(policy)
+            .finish(writer);
+        writer.write_all(b"a\n").unwrap();
+        started.recv().unwrap();
+        for line in [b"b\n", b"c\n", b"d\n"] {
+            writer.write_all(line).unwrap();
+        }
+        assert_eq!(writer.dropped_count(), 1);
+        assert_eq!(
+            guard.stats(),
+            QueueStats {
+                depth: 2,
+                high_water_mark: 2,
+                dropped: 1,
+                blocked: Duration::ZERO,
+                spilled: 0,
+            }
+        );
+        release.send(()).unwrap();
+        drop(guard);
+        let out = out.lock().unwrap().clone();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(run(OverflowPolicy::DropNewest), "a\nb\nc\n");
+    assert_eq!(run(OverflowPolicy::DropOldest), "a\nc\nd\n");
+    assert_eq!(
+        run(OverflowPolicy::Summarize),
+        "a\nturnstiles: 1 records dropped by non_blocking queue\nb\nc\n"
+    );
+    assert!(NonBlockingBuilder::new().with_queue_len(0).is_err());
+
+    // Time spent waiting for room is counted with Block
+    let (started_tx, started) = mpsc::channel();
+    let (release, gate) = mpsc::channel();
+    let writer = Gated {
+        out: Arc::new(Mutex::new(vec![])),
+        started: started_tx,
+        gate: Some(gate),
+    };
+    let (mut writer, guard) = NonBlockingBuilder::new()
+        .with_queue_len(1)
+        .unwrap()
+        .finish(writer);
+    writer.write_all(b"a\n").unwrap();
+    started.recv().unwrap();
+    writer.write_all(b"b\n").unwrap();
+    let releaser = std::thread::spawn(move || {
+        sleep(Duration::from_millis(50));
+        release.send(()).unwrap();
+    });
+    writer.write_all(b"c\n").unwrap();
+    releaser.join().unwrap();
+    let stats = writer.stats();
+    assert!(stats.blocked >= Duration::from_millis(40));
+    assert_eq!(stats.dropped, 0);
+    drop(guard);
+    // With a spill file nothing is dropped, and what's spilled is replayed in order once the worker catches up
+    let dir = TempDir::new();
+    let spill = format!("{}/queue.spill", dir.path);
+    let out = Arc::new(Mutex::new(vec![]));
+    let (started_tx, started) = mpsc::channel();
+    let (release, gate) = mpsc::channel();
+    let writer = Gated {
+        out: out.clone(),
+        started: started_tx,
+        gate: Some(gate),
+    };
+    let (mut writer, guard) = NonBlockingBuilder::new()
+        .with_queue_len(2)
+        .unwrap()
+        .with_overflow(OverflowPolicy::DropNewest)
+        .with_spill(&spill)
+        .finish(writer);
+    writer.write_all(b"a\n").unwrap();
+    started.recv().unwrap();
+    for line in [b"b\n", b"c\n", b"d\n", b"e\n", b"f\n"] {
+        writer.write_all(line).unwrap();
+    }
+    let stats = writer.stats();
+    assert_eq!(stats.spilled, 3);
+    assert_eq!(stats.dropped, 0);
+    assert!(std::path::Path::new(&spill).exists());
+    release.send(()).unwrap();
+    drop(guard);
+    assert_eq!(out.lock().unwrap().as_slice(), b"a\nb\nc\nd\ne\nf\n");
+    assert_correct_files(&dir.path, vec![]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_blocking_spill_failure() {
+    use std::sync::{mpsc, Arc, Mutex};
+    use turnstiles::appender::{NonBlockingBuilder, OverflowPolicy};
+    // Run again as a child process, as the file size limit which makes spilling fail applies to the whole process
+    let Ok(spill) = std::env::var("TURNSTILES_SPILL_TEST") else {
+        let dir = TempDir::new();
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "test_non_blocking_spill_failure", "--nocapture"])
+            .env("TURNSTILES_SPILL_TEST", format!("{}/queue.spill", dir.path))
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        // The spill file was replayed and removed
+        assert_correct_files(&dir.path, vec![]);
+        return;
+    };
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        let mut limit = std::mem::zeroed::<libc::rlimit>();
+        assert_eq!(libc::getrlimit(libc::RLIMIT_FSIZE, &mut limit), 0);
+        limit.rlim_cur = 20_000;
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+    }
+
+    /// Holds up the worker on its first write until told to carry on, so the queue fills up
+    struct Gated {
+        out: Arc<Mutex<Vec<u8>>>,
+        started: mpsc::Sender<()>,
+        gate: Option<mpsc::Receiver<()>>,
+    }
+    impl Write for Gated {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            if let Some(gate) = self.gate.take() {
+                self.started.send(()).unwrap();
+                gate.recv().unwrap();
+            }
+            self.out.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let out = Arc::new(Mutex::new(vec![]));
+    let (started_tx, started) = mpsc::channel();
+    let (release, gate) = mpsc::channel();
+    let writer = Gated {
+        out: out.clone(),
+        started: started_tx,
+        gate: Some(gate),
+    };
+    let (mut writer, guard) = NonBlockingBuilder::new()
+        .with_queue_len(2)
+        .unwrap()
+        .with_overflow(OverflowPolicy::DropNewest)
+        .with_spill(&spill)
+        .finish(writer);
+    writer.write_all(b"a\n").unwrap();
+    started.recv().unwrap();
+    writer.write_all(b"b\n").unwrap();
+    writer.write_all(b"c\n").unwrap();
+    // Big enough to go straight to the file, so the third is cut short by the size limit
+    let records: Vec<Vec<u8>> = (b'd'..=b'g').map(|c| vec![c; 9000]).collect();
+    for record in &records {
+        writer.write_all(record).unwrap();
+    }
+    let stats = writer.stats();
+    assert_eq!(stats.spilled, 2);
+    assert_eq!(stats.dropped, 2);
+    release.send(()).unwrap();
+    drop(guard);
+    // What made it to the spill file is still replayed, in order, and the torn record dropped
+    assert_eq!(
+        *out.lock().unwrap(),
+        [
+            b"a\nb\nc\n".to_vec(),
+            records[0].clone(),
+            records[1].clone()
+        ]
+        .concat()
+    );
+}
+
+#[test]
+fn test_non_blocking_spill_torn_tail() {
+    use turnstiles::appender::NonBlockingBuilder;
+    let spilled = |records: &[&[u8]]| -> Vec<u8> {
+        records
+            .iter()
+            .flat_map(|r| [(r.len() as u64).to_le_bytes().to_vec(), r.to_vec()].concat())
+            .collect()
+    };
+    let dir = TempDir::new();
+    let spill = format!("{}/queue.spill", dir.path);
+    let path = format!("{}/test.log", dir.path);
+    // Left by a run which crashed part way through replaying one file and spilling a record to the next
+    fs::write(format!("{}.replay", spill), spilled(&[b"a\n"])).unwrap();
+    let mut torn = spilled(&[b"b\n", b"c\n", b"dropped\n"]);
+    torn.truncate(torn.len() - 3);
+    fs::write(&spill, torn).unwrap();
+
+    for _ in 0..2 {
+        let file =
+            RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false).unwrap();
+        let (mut writer, guard) = NonBlockingBuilder::new().with_spill(&spill).finish(file);
+        writer.write_all(b"d\n").unwrap();
+        drop(guard);
+    }
+    // Everything whole is replayed once and the files removed, so restarting again doesn't repeat it
+    assert_eq!(
+        fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "a\nb\nc\nd\nd\n"
+    );
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+
+    // A length bigger than the rest of the file is taken as torn rather than allocated
+    fs::write(
+        &spill,
+        [spilled(&[b"e\n"]), u64::MAX.to_le_bytes().to_vec()].concat(),
+    )
+    .unwrap();
+    let file =
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    drop(NonBlockingBuilder::new().with_spill(&spill).finish(file));
+    assert!(fs::read_to_string(format!("{}.ACTIVE", path))
+        .unwrap()
+        .ends_with("d\ne\n"));
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+}
+
+#[test]
+fn test_background_rotation() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::MaxFiles(2),
+        false,
+    )
+    .unwrap()
+    .with_background_rotation()
+    .unwrap();
+    let line = vec![b'a'; 1023];
+    for _ in 0..3100 {
+        file.write_all(&line).unwrap();
+        file.write_all(b"\n").unwrap();
+    }
+    // The rename happens straight away, only syncing and pruning are left to the worker
+    assert_eq!(file.index(), 3);
+    assert!(std::path::Path::new(&format!("{}.3", path)).exists());
+    // Dropping waits for the worker
+    drop(file);
+    assert_correct_files(&dir.path, vec!["test.log.3", "test.log.ACTIVE"]);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_latency_histograms() {
+    use metrics::{
+        Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString,
+        Unit,
+    };
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    type Samples = Arc<Mutex<HashMap<String, Vec<f64>>>>;
+    struct Recorded(Samples);
+    struct NamedHistogram(String, Samples);
+    impl HistogramFn for NamedHistogram {
+        fn record(&self, value: f64) {
+            self.1
+                .lock()
+                .unwrap()
+                .entry(self.0.clone())
+                .or_default()
+                .push(value);
+        }
+    }
+    impl Recorder for Recorded {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn register_counter(&self, _: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::noop()
+        }
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(Arc::new(NamedHistogram(
+                key.name().to_string(),
+                self.0.clone(),
+            )))
+        }
+    }
+
+    let samples = Samples::default();
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    metrics::with_local_recorder(&Recorded(samples.clone()), || {
+        let mut file = RotatingFile::new(
+            path,
+            RotationCondition::SizeMB(1),
+            PruneCondition::None,
+            false,
+        )
+        .unwrap();
+        let data: Vec<u8> = vec![1; 600_000];
+        for _ in 0..3 {
+            file.write_all(&data).unwrap();
+        }
+        assert_eq!(file.index(), 1);
+        file.rotate().unwrap();
+    });
+
+    // Every write is timed, along with each rotation whether a write triggered it or not
+    let samples = samples.lock().unwrap();
+    let writes = &samples["turnstiles_write_seconds"];
+    let rotations = &samples["turnstiles_rotation_seconds"];
+    assert_eq!(writes.len(), 3);
+    assert_eq!(rotations.len(), 2);
+    assert!(writes.iter().chain(rotations).all(|&s| s >= 0.0));
+    // The write which rotated took at least as long as the rotation it waited for
+    assert!(writes[2] >= rotations[0]);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_compress_roller() {
+    use std::io::Read;
+    use turnstiles::CompressRoller;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap()
+    .with_roller(CompressRoller::new().unwrap());
+    for i in 0..4 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    // Waits for compression to finish
+    drop(file);
+
+    // Compressed files count towards the index and pruning
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    assert_eq!(file.index(), 4);
+    file.prune();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.3.gz", "test.log.4.gz", "test.log.ACTIVE"],
+    );
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(fs::File::open(format!("{}.4.gz", path)).unwrap())
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, "line 3\n");
+}
+
+#[test]
+fn test_rotate_on_close() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_rotate_on_close();
+    file.write_all(b"line\n").unwrap();
+    file.close().unwrap();
+    assert_correct_files(&dir.path, vec!["test.log.1"]);
+
+    // Nothing written, nothing to rotate
+    let file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_rotate_on_close();
+    file.close().unwrap();
+    assert_correct_files(&dir.path, vec!["test.log.1", "test.log.ACTIVE"]);
+
+    // Nor if there's only a banner
+    let file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_banner()
+        .unwrap()
+        .with_rotate_on_close();
+    file.close().unwrap();
+    assert_correct_files(&dir.path, vec!["test.log.1", "test.log.ACTIVE"]);
+
+    #[cfg(feature = "compression")]
+    {
+        let mut file =
+            RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+                .unwrap()
+                .with_compression()
+                .unwrap()
+                .with_rotate_on_close();
+        file.write_all(b"line\n").unwrap();
+        file.close().unwrap();
+        assert_correct_files(&dir.path, vec!["test.log.1", "test.log.2.gz"]);
+    }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_with_compression() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_compression()
+        .unwrap();
+    file.write_all(b"line\n").unwrap();
+    file.rotate().unwrap();
+    file.drain().unwrap();
+    assert_correct_files(&dir.path, vec!["test.log.1.gz", "test.log.ACTIVE"]);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_compress_roller_zstd() {
+    use turnstiles::CompressRoller;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap()
+    .with_roller(CompressRoller::new_zstd_with_level(19).unwrap());
+    for i in 0..4 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    file.drain().unwrap();
+    drop(file);
+
+    // As with gzip the compressed files count towards the index and pruning
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    assert_eq!(file.index(), 4);
+    file.prune();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.3.zst", "test.log.4.zst", "test.log.ACTIVE"],
+    );
+    let decoded = zstd::decode_all(fs::File::open(format!("{}.4.zst", path)).unwrap()).unwrap();
+    assert_eq!(decoded, b"line 3\n");
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_encrypt_roller() {
+    use age::x25519::Identity;
+    use std::io::Read;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let identity = Identity::generate();
+    assert!(
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+            .unwrap()
+            .with_encryption("age1notarecipient")
+            .is_err()
+    );
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap()
+    .with_encryption(&identity.to_public().to_string())
+    .unwrap();
+    for i in 0..4 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    file.drain().unwrap();
+    drop(file);
+
+    // The encrypted files count towards the index and pruning, with no plaintext left behind
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap();
+    assert_eq!(file.index(), 4);
+    file.prune();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.3.age", "test.log.4.age", "test.log.ACTIVE"],
+    );
+    let encrypted = fs::read(format!("{}.4.age", path)).unwrap();
+    assert!(!encrypted.windows(6).any(|w| w == b"line 3"));
+    let mut decrypted = String::new();
+    age::Decryptor::new(&encrypted[..])
+        .unwrap()
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .unwrap()
+        .read_to_string(&mut decrypted)
+        .unwrap();
+    assert_eq!(decrypted, "line 3\n");
+
+    // And the key really is needed
+    let other = Identity::generate();
+    assert!(age::Decryptor::new(&encrypted[..])
+        .unwrap()
+        .decrypt(std::iter::once(&other as &dyn age::Identity))
+        .is_err());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_compression_config() {
+    use turnstiles::{CompressRoller, CompressionCodec, CompressionConfig};
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let config = CompressionConfig {
+        level: Some(100),
+        threads: 2,
+        min_size: 1024,
+        ..CompressionConfig::new(CompressionCodec::Zstd)
+    };
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_roller(CompressRoller::with_config(config).unwrap());
+    // Too small to be worth compressing
+    file.write_all(b"small\n").unwrap();
+    file.rotate().unwrap();
+    let big = "a".repeat(4096);
+    file.write_all(big.as_bytes()).unwrap();
+    file.rotate().unwrap();
+    file.drain().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.1", "test.log.2.zst", "test.log.ACTIVE"],
+    );
+    let decoded = zstd::decode_all(fs::File::open(format!("{}.2.zst", path)).unwrap()).unwrap();
+    assert_eq!(decoded, big.as_bytes());
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_compress_roller_lz4() {
+    use std::io::Read;
+    use turnstiles::CompressRoller;
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_roller(CompressRoller::new_lz4().unwrap());
+    file.write_all(b"line 0\n").unwrap();
+    file.rotate().unwrap();
+    file.drain().unwrap();
+    drop(file);
+    assert_correct_files(&dir.path, vec!["test.log.1.lz4", "test.log.ACTIVE"]);
+    let file =
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    assert_eq!(file.index(), 1);
+    let mut decoded = String::new();
+    lz4_flex::frame::FrameDecoder::new(fs::File::open(format!("{}.1.lz4", path)).unwrap())
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, "line 0\n");
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn test_streaming_compression() {
+    use std::io::Read;
+    use turnstiles::{CompressionCodec, CompressionConfig, SizeBasis};
+
+    let gunzip = |path: String| {
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(path).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        decoded
+    };
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let open = || {
+        RotatingFile::new(
+            &path,
+            RotationCondition::SizeMB(1),
+            PruneCondition::None,
+            false,
+        )
+        .unwrap()
+        .with_streaming_compression(
+            CompressionConfig::new(CompressionCodec::Gzip),
+            SizeBasis::Uncompressed,
+        )
+        .unwrap()
+    };
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    file.write_all(b"uncompressed\n").unwrap();
+    drop(file);
+
+    // What was there already is rotated away as it is
+    let mut file = open();
+    assert_correct_files(&dir.path, vec!["test.log.1", "test.log.ACTIVE.gz"]);
+    // Rotates on the uncompressed size, even though a megabyte of the same line compresses to next to nothing
+    let line = format!("{}\n", "a".repeat(1023));
+    for _ in 0..1025 {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.write_all(b"last\n").unwrap();
+    assert_eq!(file.index(), 2);
+    drop(file);
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.1", "test.log.2.gz", "test.log.ACTIVE.gz"],
+    );
+    assert_eq!(gunzip(format!("{}.2.gz", path)), line.repeat(1025));
+    assert_eq!(gunzip(format!("{}.ACTIVE.gz", path)), "last\n");
+
+    // A compressed active file from before is rotated away rather than appended to, and counted by the index
+    let file = open();
+    assert_eq!(file.index(), 3);
+    drop(file);
+    assert_eq!(gunzip(format!("{}.3.gz", path)), "last\n");
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.1",
+            "test.log.2.gz",
+            "test.log.3.gz",
+            "test.log.ACTIVE.gz",
+        ],
+    );
+    // Opening and closing without writing leaves the active file empty, so there's nothing to rotate away next time
+    drop(open());
+    assert_eq!(open().index(), 3);
+}
+
+#[test]
+fn test_close_before_rename() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_close_before_rename();
+    for i in 0..3 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    writeln!(file, "line 3").unwrap();
+    file.flush().unwrap();
+    // The spare the new file was opened under has been moved into place
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.1", "test.log.2", "test.log.3", "test.log.ACTIVE"],
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.3", path)).unwrap(),
+        "line 2\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "line 3\n"
+    );
+    drop(file);
+
+    // Using the precreated file where there is one, on the background thread too
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_background_rotation()
+        .unwrap()
+        .with_precreate()
+        .unwrap()
+        .with_close_before_rename();
+    file.rotate().unwrap();
+    writeln!(file, "line 4").unwrap();
+    file.rotate().unwrap();
+    file.drain().unwrap();
+    assert_eq!(
+        fs::read_to_string(format!("{}.4", path)).unwrap(),
+        "line 3\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.5", path)).unwrap(),
+        "line 4\n"
+    );
+    assert!(fs::metadata(format!("{}.ACTIVE", path)).is_ok());
+}
+
+#[test]
+fn test_precreate() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_precreate()
+        .unwrap();
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.NEXT"]);
+    for i in 0..3 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    writeln!(file, "line 3").unwrap();
+    file.flush().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.1",
+            "test.log.2",
+            "test.log.3",
+            "test.log.ACTIVE",
+            "test.log.NEXT",
+        ],
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.3", path)).unwrap(),
+        "line 2\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(),
+        "line 3\n"
+    );
+    assert_eq!(fs::read_to_string(format!("{}.NEXT", path)).unwrap(), "");
+    drop(file);
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.1", "test.log.2", "test.log.3", "test.log.ACTIVE"],
+    );
+
+    // Spares opened on the background thread
+    let mut file = RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false)
+        .unwrap()
+        .with_background_rotation()
+        .unwrap()
+        .with_precreate()
+        .unwrap();
+    for i in 4..8 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    drop(file);
+    // The first picks up "line 3" from the old active file
+    for i in 5..8 {
+        assert_eq!(
+            fs::read_to_string(format!("{}.{}", path, i)).unwrap(),
+            format!("line {}\n", i)
+        );
+    }
+    assert_eq!(fs::read_to_string(format!("{}.ACTIVE", path)).unwrap(), "");
+    assert!(!std::path::Path::new(&format!("{}.NEXT", path)).exists());
+}
+
+#[test]
+fn test_size_resync_after_truncate() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    let line = vec![b'a'; 1023];
+    for _ in 0..1024 {
+        file.write_all(&line).unwrap();
+        file.write_all(b"\n").unwrap();
+    }
+    // Over the limit, so the next write would rotate
+    file.write_all(&line).unwrap();
+    assert!(file.current_file_size() > 1024 * 1024);
+    // Truncated from outside, as by logrotate's copytruncate
+    fs::OpenOptions::new()
+        .write(true)
+        .open(format!("{}.ACTIVE", path))
+        .unwrap()
+        .set_len(0)
+        .unwrap();
+    file.write_all(b"after truncate\n").unwrap();
+    assert_eq!(file.index(), 0);
+    assert_eq!(file.current_file_size(), 15);
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+}
+
+#[test]
+fn test_rotated_listing_refresh() {
+    use std::sync::{Arc, Mutex};
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let errors = Arc::new(Mutex::new(vec![]));
+    let hook_errors = errors.clone();
+    let mut log_file = RotatingFile::new(
+        path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(3),
+        false,
+    )
+    .unwrap()
+    .with_error_hook(move |ctx, e| hook_errors.lock().unwrap().push(format!("{}: {}", ctx, e)));
+    for _ in 0..3 {
+        log_file.rotate().unwrap();
+    }
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.ACTIVE", "test.log.2", "test.log.3"],
+    );
+
+    // A file deleted by something else is noticed when pruning
+    fs::remove_file(format!("{}/test.log.2", &dir.path)).unwrap();
+    log_file.rotate().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.ACTIVE", "test.log.3", "test.log.4"],
+    );
+
+    // One added by something else isn't seen until a refresh
+    fs::write(format!("{}/test.log.1", &dir.path), "restored\n").unwrap();
+    log_file.rotate().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.ACTIVE", "test.log.1", "test.log.4", "test.log.5"],
+    );
+    log_file.refresh().unwrap();
+    log_file.rotate().unwrap();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.ACTIVE", "test.log.5", "test.log.6"],
+    );
+    assert!(errors.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_write_buffer() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let active = format!("{}/test.log.ACTIVE", &dir.path);
+    let mut log_file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        true,
+    )
+    .unwrap()
+    .with_write_buffer(4096);
+    let line = [b'a'; 1023]
+        .iter()
+        .chain(b"\n")
+        .copied()
+        .collect::<Vec<u8>>();
+    log_file.write_all(&line).unwrap();
+    assert_eq!(fs::metadata(&active).unwrap().len(), 0);
+    log_file.flush().unwrap();
+    assert_eq!(fs::metadata(&active).unwrap().len(), 1024);
+
+    // Buffered bytes count towards the size, and are written before rotating
+    for _ in 0..1025 {
+        log_file.write_all(&line).unwrap();
+    }
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.1"]);
+    assert_eq!(
+        fs::metadata(format!("{}/test.log.1", &dir.path))
+            .unwrap()
+            .len(),
+        1025 * 1024
+    );
+    assert_eq!(log_file.file_info().unwrap().size, 1024);
+    drop(log_file);
+    assert_eq!(fs::metadata(&active).unwrap().len(), 1024);
+}
+
+#[test]
+fn test_sync_every_write() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut log_file =
+        RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false)
+            .unwrap()
+            .with_write_buffer(4096)
+            .with_precreate()
+            .unwrap()
+            .with_sync_every_write()
+            .unwrap();
+    // Nothing is held back, even with a write buffer
+    log_file.write_all(b"first\n").unwrap();
+    let active = format!("{}/test.log.ACTIVE", &dir.path);
+    assert_eq!(fs::read_to_string(&active).unwrap(), "first\n");
+    log_file.rotate().unwrap();
+    log_file.write_all(b"second\n").unwrap();
+    assert_eq!(fs::read_to_string(&active).unwrap(), "second\n");
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.ACTIVE", "test.log.1", "test.log.NEXT"],
+    );
+
+    // Without O_DSYNC each write is synced instead
+    let memory = MemoryFileSystem::new();
+    let mut log_file = RotatingFile::new_in(
+        memory.clone(),
+        "/logs/test.log",
+        RotationCondition::None,
+        PruneCondition::None,
+        false,
+    )
+    .unwrap()
+    .with_sync_every_write()
+    .unwrap();
+    log_file.write_all(b"first\n").unwrap();
+    assert_eq!(
+        memory.read("/logs/test.log.ACTIVE").unwrap(),
+        b"first\n".to_vec()
+    );
+}
+
+#[test]
+fn test_group_commit() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file = SharedRotatingFile::new(
+        RotatingFile::new(path, RotationCondition::None, PruneCondition::None, true).unwrap(),
+    )
+    .with_group_commit()
+    .unwrap();
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let mut file = file.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    writeln!(file, "thread {} line {}", t, i).unwrap();
+                    if t == 0 && i == 25 {
+                        file.lock().rotate().unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.1"]);
+    let lines = ["test.log.1", "test.log.ACTIVE"]
+        .iter()
+        .map(|name| fs::read_to_string(format!("{}/{}", &dir.path, name)).unwrap())
+        .collect::<String>();
+    assert_eq!(lines.lines().count(), 400);
+}
+
+#[test]
+fn test_sharded_rotating_file() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file = ShardedBuilder::new()
+        .with_shards(3)
+        .unwrap()
+        .with_shard_capacity(256)
+        .unwrap()
+        .finish(
+            RotatingFile::new(
+                path,
+                RotationCondition::SizeMB(1),
+                PruneCondition::None,
+                true,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+    let line = [b'a'; 1023]
+        .iter()
+        .chain(b"\n")
+        .copied()
+        .collect::<Vec<u8>>();
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let mut file = file.clone();
+            let line = line.clone();
+            std::thread::spawn(move || {
+                for _ in 0..200 {
+                    file.write_all(&line).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let mut file = file;
+    file.flush().unwrap();
+    assert_eq!(file.lock().unwrap().index(), 1);
+    drop(file);
+
+    // Every record whole, and rotation where it would be without sharding
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.1"]);
+    let rotated = fs::read(format!("{}/test.log.1", &dir.path)).unwrap();
+    let active = fs::read(format!("{}/test.log.ACTIVE", &dir.path)).unwrap();
+    assert_eq!(rotated.len(), 1025 * 1024);
+    assert_eq!(active.len(), (1600 - 1025) * 1024);
+    for data in [rotated, active] {
+        assert!(data.chunks(1024).all(|chunk| chunk == line.as_slice()));
+    }
+}
+
+#[test]
+fn test_require_newline_partial_buffer() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut log_file = RotatingFile::new(
+        path,
+        RotationCondition::SizeMB(1),
+        PruneCondition::None,
+        true,
+    )
+    .unwrap();
+    let line = [b'a'; 1023]
+        .iter()
+        .chain(b"\n")
+        .copied()
+        .collect::<Vec<u8>>();
+    for _ in 0..1025 {
+        log_file.write_all(&line).unwrap();
+    }
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+
+    // Complete lines followed by a partial one, the rotation goes between them
+    log_file.write_all(b"x\ny\npartial").unwrap();
+    log_file.write_all(b" end\n").unwrap();
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE", "test.log.1"]);
+    let rotated = fs::read_to_string(format!("{}/test.log.1", &dir.path)).unwrap();
+    assert!(rotated.ends_with("a\nx\ny\n"));
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.ACTIVE", &dir.path)).unwrap(),
+        "partial end\n"
+    );
+}
+
+#[test]
+fn test_file_system_copy() {
+    use turnstiles::{FileSystem, StdFileSystem};
+    let dir = TempDir::new();
+    let from = format!("{}/test.log.1", &dir.path);
+    let to = format!("{}/test.log.copy", &dir.path);
+    fs::write(&from, "rotated\n").unwrap();
+    let copied = StdFileSystem
+        .copy(std::path::Path::new(&from), std::path::Path::new(&to))
+        .unwrap();
+    assert_eq!(copied, 8);
+    assert_eq!(fs::read_to_string(&to).unwrap(), "rotated\n");
+
+    let memory = MemoryFileSystem::new();
+    let from = std::path::Path::new("/logs/test.log.1");
+    let to = std::path::Path::new("/logs/test.log.copy");
+    memory.open_append(to).unwrap().write_all(b"old").unwrap();
+    memory
+        .open_append(from)
+        .unwrap()
+        .write_all(b"rotated\n")
+        .unwrap();
+    assert_eq!(memory.copy(from, to).unwrap(), 8);
+    assert_eq!(memory.read(to).unwrap(), b"rotated\n".to_vec());
+    assert!(memory
+        .copy(std::path::Path::new("/logs/missing"), to)
+        .is_err());
+}
+
+#[test]
+fn test_rotation_target_taken() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let mut log_file =
+        RotatingFile::new(path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    log_file.write_all(b"first\n").unwrap();
+    log_file.rotate().unwrap();
+
+    // Something else puts files where the next rotations would go
+    fs::write(format!("{}/test.log.2", &dir.path), "external\n").unwrap();
+    fs::write(format!("{}/test.log.3", &dir.path), "external\n").unwrap();
+    log_file.write_all(b"second\n").unwrap();
+    log_file.rotate().unwrap();
+    assert_eq!(log_file.index(), 4);
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.ACTIVE",
+            "test.log.1",
+            "test.log.2",
+            "test.log.3",
+            "test.log.4",
+        ],
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.2", &dir.path)).unwrap(),
+        "external\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.4", &dir.path)).unwrap(),
+        "second\n"
+    );
+}
+
+#[test]
+fn test_flush_guard() {
+    let dir = TempDir::new();
+    let path = &[dir.path.clone(), "test.log".to_string()].join("/");
+    let file = SharedRotatingFile::new(
+        RotatingFile::new(
+            path,
+            RotationCondition::None,
+            PruneCondition::MaxFiles(1),
+            false,
+        )
+        .unwrap()
+        .with_write_buffer(4096)
+        .with_background_rotation()
+        .unwrap(),
+    );
+    // As if the file had been given to a global logger and is never dropped
+    let global = Box::leak(Box::new(file.clone()));
+    let guard = file.flush_guard();
+    global.write_all(b"first\n").unwrap();
+    global.lock().rotate().unwrap();
+    global.write_all(b"second\n").unwrap();
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.ACTIVE", &dir.path)).unwrap(),
+        ""
+    );
+
+    drop(guard);
+    assert_eq!(
+        fs::read_to_string(format!("{}/test.log.ACTIVE", &dir.path)).unwrap(),
+        "second\n"
+    );
+    // The prune on the background thread has happened too
+    assert_correct_files(&dir.path, vec!["test.log.ACTIVE"]);
+}
+
+#[test]
+fn test_duration_deadline_recomputed() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::Duration(Duration::from_secs(3600)),
+        PruneCondition::None,
+        false,
+    )
+    .unwrap();
+    file.write_all(b"a\n").unwrap();
+    // The deadline worked out for an hour must not outlive the condition it came from
+    file.set_rotation_condition(RotationCondition::Duration(Duration::from_millis(100)))
+        .unwrap();
+    sleep(Duration::from_millis(150));
+    file.write_all(b"b\n").unwrap();
+    assert_eq!(file.index(), 1);
+    // And the new active file gets a deadline of its own
+    file.write_all(b"c\n").unwrap();
+    assert_eq!(file.index(), 1);
+    sleep(Duration::from_millis(150));
+    file.write_all(b"d\n").unwrap();
+    assert_eq!(file.index(), 2);
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.1", "test.log.2", "test.log.ACTIVE"],
+    );
+}
+
+#[test]
+fn test_sharded_commit_after_idle() {
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let file =
+        RotatingFile::new(&path, RotationCondition::None, PruneCondition::None, false).unwrap();
+    let sharded = ShardedBuilder::new()
+        .with_commit_interval(Duration::from_millis(20))
+        .finish(file)
+        .unwrap();
+    let active = format!("{}.ACTIVE", path);
+    // The committer sleeps until something is written, then commits it an interval later without needing a flush
+    for line in ["first\n", "second\n"] {
+        sleep(Duration::from_millis(100));
+        (&sharded).write_all(line.as_bytes()).unwrap();
+        sleep(Duration::from_millis(200));
+        assert!(fs::read_to_string(&active).unwrap().ends_with(line));
+    }
+    assert_eq!(fs::read_to_string(&active).unwrap(), "first\nsecond\n");
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn test_archive() {
+    use std::{io::Read, time::UNIX_EPOCH};
+
+    let dir = TempDir::new();
+    let path = format!("{}/test.log", dir.path);
+    let mut file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(6),
+        false,
+    )
+    .unwrap()
+    .with_archive(Duration::from_secs(24 * 3600));
+    for i in 1..=5 {
+        writeln!(file, "line {}", i).unwrap();
+        file.rotate().unwrap();
+    }
+    let set_modified = |index: u32, secs: u64| {
+        fs::File::options()
+            .write(true)
+            .open(format!("{}.{}", path, index))
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    };
+    // 2024-06-15, 2024-06-15 and 2024-07-02
+    set_modified(1, 1718409600);
+    set_modified(2, 1718409600);
+    set_modified(3, 1719878400);
+    let members = |archive: &str| {
+        let mut members = vec![];
+        let file = fs::File::open(format!("{}/{}", dir.path, archive)).unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            members.push((entry.path().unwrap().display().to_string(), contents));
+        }
+        members
+    };
+
+    file.prune();
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.4",
+            "test.log.5",
+            "test.log.2024-06.tar.gz",
+            "test.log.2024-07.tar.gz",
+            "test.log.ACTIVE",
+        ],
+    );
+    assert_eq!(
+        members("test.log.2024-06.tar.gz"),
+        vec![
+            ("test.log.1".to_string(), "line 1\n".to_string()),
+            ("test.log.2".to_string(), "line 2\n".to_string())
+        ]
+    );
+
+    // Each tarball counts as one file for MaxFiles, so with test.log.4 and test.log.5 still loose there's only room for July
+    file.set_prune_condition(PruneCondition::MaxFiles(4))
+        .unwrap();
+    file.prune();
+    assert_correct_files(
+        &dir.path,
+        vec![
+            "test.log.4",
+            "test.log.5",
+            "test.log.2024-07.tar.gz",
+            "test.log.ACTIVE",
+        ],
+    );
+    assert_eq!(
+        members("test.log.2024-07.tar.gz"),
+        vec![("test.log.3".to_string(), "line 3\n".to_string())]
+    );
+
+    // Added to the end of the month's tarball, while the newest file stays loose however old it is
+    set_modified(4, 1721433600);
+    set_modified(5, 1721433600);
+    file.prune();
+    assert_correct_files(
+        &dir.path,
+        vec!["test.log.5", "test.log.2024-07.tar.gz", "test.log.ACTIVE"],
+    );
+    assert_eq!(
+        members("test.log.2024-07.tar.gz"),
+        vec![
+            ("test.log.3".to_string(), "line 3\n".to_string()),
+            ("test.log.4".to_string(), "line 4\n".to_string())
+        ]
+    );
+    drop(file);
+    let file = RotatingFile::new(
+        &path,
+        RotationCondition::None,
+        PruneCondition::MaxFiles(4),
+        false,
+    )
+    .unwrap();
+    assert_eq!(file.index(), 5);
+}
+
 // Some helpers
 fn get_dir_files_hashset(dir: &str) -> HashSet<String> {
     let mut files = HashSet::new();